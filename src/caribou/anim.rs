@@ -0,0 +1,745 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Brush, Material, Transform};
+use crate::caribou::math::{Matrix, ScalarPair};
+use crate::caribou::property::Property;
+use crate::caribou::widget::Widget;
+
+thread_local! {
+    static ANIMATIONS: RefCell<Vec<Animation>> = RefCell::new(Vec::new());
+    // The animation driving each property, by property identity
+    static PROPERTY_ANIMATIONS: RefCell<HashMap<usize, AnimationHandle>> = RefCell::new(HashMap::new());
+}
+
+pub type StepFunc = Box<dyn Fn(f32)>;
+pub type FinishFunc = Box<dyn FnOnce()>;
+
+/// Advances an animation to the given instant, returning whether it has finished.
+type DriveFunc = Box<dyn FnMut(Instant) -> bool>;
+
+struct Animation {
+    drive: DriveFunc,
+    finish: Option<FinishFunc>,
+    cancelled: Rc<Cell<bool>>,
+}
+
+#[derive(Clone)]
+pub struct AnimationHandle {
+    cancelled: Rc<Cell<bool>>,
+}
+
+impl AnimationHandle {
+    /// Stop the animation where it is, without running its finishing callback.
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+}
+
+pub struct Animator;
+
+impl Animator {
+    fn push(drive: DriveFunc, finish: Option<FinishFunc>) -> AnimationHandle {
+        let cancelled = Rc::new(Cell::new(false));
+        ANIMATIONS.with(|animations| animations.borrow_mut().push(Animation {
+            drive,
+            finish,
+            cancelled: cancelled.clone(),
+        }));
+        AnimationHandle { cancelled }
+    }
+
+    /// Run `step` with the linear progress (0 to 1) of every frame until `duration` elapses.
+    pub fn start(duration: Duration, step: StepFunc, finish: Option<FinishFunc>) -> AnimationHandle {
+        let started = Instant::now();
+        Animator::push(Box::new(move |now| {
+            let progress = if duration.is_zero() {
+                1.0
+            } else {
+                ((now - started).as_secs_f32() / duration.as_secs_f32()).min(1.0)
+            };
+            step(progress);
+            progress >= 1.0
+        }), finish)
+    }
+
+    /// Run `step` with the value of a spring moving from `from` to `to`, starting with
+    /// `velocity` (in units per second, e.g. that of the gesture being released).
+    pub fn spring(
+        spring: Spring, from: f32, to: f32, velocity: f32,
+        step: StepFunc, finish: Option<FinishFunc>,
+    ) -> SpringHandle {
+        let state = Rc::new(Cell::new((from, velocity)));
+        let shared = state.clone();
+        let mut last = Instant::now();
+        let handle = Animator::push(Box::new(move |now| {
+            let (position, velocity) = shared.get();
+            let (position, velocity) =
+                spring.integrate(position, velocity, to, (now - last).as_secs_f32());
+            last = now;
+            let resting = spring.is_resting(position, velocity, to);
+            let position = if resting { to } else { position };
+            shared.set((position, if resting { 0.0 } else { velocity }));
+            step(position);
+            resting
+        }), finish);
+        SpringHandle { handle, state }
+    }
+
+    /// Advance all animations, returning whether any of them still runs.
+    pub fn tick() -> bool {
+        let now = Instant::now();
+        // Take the list out so that steps and callbacks may start new animations
+        let running = ANIMATIONS.with(|animations| animations.take());
+        let mut retained = Vec::new();
+        for mut animation in running {
+            if animation.cancelled.get() {
+                continue;
+            }
+            if !(animation.drive)(now) {
+                retained.push(animation);
+            } else if let Some(finish) = animation.finish.take() {
+                finish();
+            }
+        }
+        ANIMATIONS.with(|animations| {
+            let mut animations = animations.borrow_mut();
+            retained.append(&mut animations);
+            *animations = retained;
+            !animations.is_empty()
+        })
+    }
+}
+
+/// A damped harmonic oscillator pulling a value towards its target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spring {
+    pub stiffness: f32,
+    pub damping: f32,
+    pub mass: f32,
+    /// Distance and speed below which the spring is considered at rest
+    pub rest_threshold: f32,
+}
+
+impl Default for Spring {
+    fn default() -> Self {
+        Spring {
+            stiffness: 170.0,
+            damping: 26.0,
+            mass: 1.0,
+            rest_threshold: 0.01,
+        }
+    }
+}
+
+impl Spring {
+    pub fn new(stiffness: f32, damping: f32) -> Self {
+        Spring { stiffness, damping, ..Spring::default() }
+    }
+
+    /// The fastest spring of the given stiffness that does not overshoot.
+    pub fn critically_damped(stiffness: f32) -> Self {
+        Spring { stiffness, damping: 2.0 * stiffness.sqrt(), ..Spring::default() }
+    }
+
+    /// Advance `(position, velocity)` by `dt` seconds.
+    pub fn integrate(&self, position: f32, velocity: f32, target: f32, dt: f32) -> (f32, f32) {
+        // Small fixed sub-steps keep stiff springs stable on long frames
+        const MAX_STEP: f32 = 1.0 / 240.0;
+        let (mut position, mut velocity) = (position, velocity);
+        let mut remaining = dt;
+        while remaining > 0.0 {
+            let dt = remaining.min(MAX_STEP);
+            let force = -self.stiffness * (position - target) - self.damping * velocity;
+            velocity += force / self.mass * dt;
+            position += velocity * dt;
+            remaining -= dt;
+        }
+        (position, velocity)
+    }
+
+    pub fn is_resting(&self, position: f32, velocity: f32, target: f32) -> bool {
+        (position - target).abs() < self.rest_threshold && velocity.abs() < self.rest_threshold
+    }
+}
+
+/// Handle of a running spring, whose state can seed a follow-up spring.
+#[derive(Clone)]
+pub struct SpringHandle {
+    pub handle: AnimationHandle,
+    state: Rc<Cell<(f32, f32)>>,
+}
+
+impl SpringHandle {
+    pub fn value(&self) -> f32 {
+        self.state.get().0
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.state.get().1
+    }
+
+    pub fn cancel(&self) {
+        self.handle.cancel();
+    }
+}
+
+/// A composable description of animated changes over time.
+#[derive(Clone)]
+pub enum Motion {
+    /// Linear progress from 0 to 1 over the duration
+    Tween(Duration, Rc<dyn Fn(f32)>),
+    /// A value interpolated between `(fraction of duration, value)` keyframes
+    Keyframes(Duration, Vec<(f32, f32)>, Rc<dyn Fn(f32)>),
+    Delay(Duration),
+    /// All motions at once, lasting as long as the longest
+    Parallel(Vec<Motion>),
+    /// One motion after another
+    Sequence(Vec<Motion>),
+}
+
+impl Motion {
+    pub fn tween<F: Fn(f32) + 'static>(duration: Duration, step: F) -> Motion {
+        Motion::Tween(duration, Rc::new(step))
+    }
+
+    pub fn keyframes<F: Fn(f32) + 'static>(duration: Duration, frames: Vec<(f32, f32)>, step: F) -> Motion {
+        Motion::Keyframes(duration, frames, Rc::new(step))
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            Motion::Tween(duration, _) => *duration,
+            Motion::Keyframes(duration, _, _) => *duration,
+            Motion::Delay(duration) => *duration,
+            Motion::Parallel(motions) => motions.iter()
+                .map(|motion| motion.duration()).max().unwrap_or_default(),
+            Motion::Sequence(motions) => motions.iter()
+                .map(|motion| motion.duration()).sum(),
+        }
+    }
+
+    /// Apply the state of the motion at `time` seconds from its beginning.
+    pub fn evaluate(&self, time: f32) {
+        let fraction = |duration: &Duration| if duration.is_zero() {
+            1.0
+        } else {
+            (time / duration.as_secs_f32()).clamp(0.0, 1.0)
+        };
+        match self {
+            Motion::Tween(duration, step) => step(fraction(duration)),
+            Motion::Keyframes(duration, frames, step) => {
+                if let Some(value) = keyframe_value(frames, fraction(duration)) {
+                    step(value);
+                }
+            }
+            Motion::Delay(_) => {}
+            Motion::Parallel(motions) => {
+                for motion in motions {
+                    motion.evaluate(time);
+                }
+            }
+            Motion::Sequence(motions) => {
+                let mut starts = Vec::with_capacity(motions.len());
+                let mut start = 0.0;
+                for motion in motions {
+                    starts.push(start);
+                    start += motion.duration().as_secs_f32();
+                }
+                // Motions not yet started are rewound first, the last one first, so that when
+                // seeking backwards the earlier ones win on shared targets
+                for (motion, start) in motions.iter().zip(&starts).rev() {
+                    if time < *start {
+                        motion.evaluate(0.0);
+                    }
+                }
+                for (motion, start) in motions.iter().zip(&starts) {
+                    if time >= *start {
+                        motion.evaluate(time - start);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn keyframe_value(frames: &[(f32, f32)], fraction: f32) -> Option<f32> {
+    let first = frames.first()?;
+    if fraction <= first.0 {
+        return Some(first.1);
+    }
+    for pair in frames.windows(2) {
+        let ((begin, from), (end, to)) = (pair[0], pair[1]);
+        if fraction <= end {
+            let span = end - begin;
+            let local = if span > 0.0 { (fraction - begin) / span } else { 1.0 };
+            return Some(from + (to - from) * local);
+        }
+    }
+    frames.last().map(|frame| frame.1)
+}
+
+struct TimelineState {
+    motion: Motion,
+    position: Cell<f32>,
+    reversed: Cell<bool>,
+    running: RefCell<Option<AnimationHandle>>,
+}
+
+/// Playback control over a `Motion`.
+#[derive(Clone)]
+pub struct Timeline {
+    state: Rc<TimelineState>,
+}
+
+impl Timeline {
+    pub fn new(motion: Motion) -> Timeline {
+        Timeline {
+            state: Rc::new(TimelineState {
+                motion,
+                position: Cell::new(0.0),
+                reversed: Cell::new(false),
+                running: RefCell::new(None),
+            })
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.state.motion.duration()
+    }
+
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f32(self.state.position.get())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.running.borrow().is_some()
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.state.reversed.get()
+    }
+
+    /// Play from the current position in the current direction, restarting from the
+    /// corresponding end when already there.
+    pub fn play(&self) {
+        if self.is_playing() {
+            return;
+        }
+        let total = self.duration().as_secs_f32();
+        let state = &self.state;
+        match (state.reversed.get(), state.position.get()) {
+            (false, position) if position >= total => state.position.set(0.0),
+            (true, position) if position <= 0.0 => state.position.set(total),
+            _ => {}
+        }
+        let shared = self.state.clone();
+        let mut last = Instant::now();
+        let handle = Animator::push(Box::new(move |now| {
+            let delta = (now - last).as_secs_f32();
+            last = now;
+            let position = if shared.reversed.get() {
+                (shared.position.get() - delta).max(0.0)
+            } else {
+                (shared.position.get() + delta).min(total)
+            };
+            shared.position.set(position);
+            shared.motion.evaluate(position);
+            let done = if shared.reversed.get() { position <= 0.0 } else { position >= total };
+            if done {
+                shared.running.replace(None);
+            }
+            done
+        }), None);
+        self.state.running.replace(Some(handle));
+    }
+
+    pub fn pause(&self) {
+        if let Some(handle) = self.state.running.replace(None) {
+            handle.cancel();
+        }
+    }
+
+    /// Jump to a position and apply the state there right away.
+    pub fn seek(&self, position: Duration) {
+        let position = position.min(self.duration()).as_secs_f32();
+        self.state.position.set(position);
+        self.state.motion.evaluate(position);
+    }
+
+    /// Flip the direction of playback, keeping the current position.
+    pub fn reverse(&self) {
+        self.state.reversed.set(!self.state.reversed.get());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransitionEffect {
+    Fade,
+    /// Slide in from (or out to) the given offset
+    Slide(ScalarPair),
+    /// Scale in from (or out to) the given factor, around the widget's center
+    Scale(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChildTransition {
+    pub effect: TransitionEffect,
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+impl ChildTransition {
+    pub fn new(effect: TransitionEffect, duration: Duration) -> Self {
+        Self { effect, duration, easing: Easing::default() }
+    }
+
+    pub fn with_easing(self, easing: Easing) -> Self {
+        Self { easing, ..self }
+    }
+}
+
+/// Curves mapping the linear progress of an animation to the progress of the animated value,
+/// both going from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    /// Winds up with growing oscillations
+    ElasticIn,
+    /// Overshoots and settles with fading oscillations
+    ElasticOut,
+    BounceIn,
+    /// Drops to the end and bounces off it like a ball
+    BounceOut,
+    /// The CSS `cubic-bezier(x1, y1, x2, y2)` curve from (0, 0) to (1, 1)
+    CubicBezier(f32, f32, f32, f32),
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Easing {
+    pub fn evaluate(&self, t: f32) -> f32 {
+        use std::f32::consts::PI;
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut => if t < 0.5 {
+                2.0 * t * t
+            } else {
+                1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+            },
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+            },
+            Easing::ElasticIn => 1.0 - Easing::ElasticOut.evaluate(1.0 - t),
+            Easing::ElasticOut => if t == 0.0 || t == 1.0 {
+                t
+            } else {
+                2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+            },
+            Easing::BounceIn => 1.0 - Easing::BounceOut.evaluate(1.0 - t),
+            Easing::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+            Easing::CubicBezier(x1, y1, x2, y2) => {
+                let bezier = |a: f32, b: f32, s: f32|
+                    3.0 * a * s * (1.0 - s).powi(2) + 3.0 * b * s * s * (1.0 - s) + s.powi(3);
+                // Find the curve parameter whose x is the progress by bisection; x is
+                // monotonic as x1 and x2 lie within [0, 1]
+                let (mut low, mut high) = (0.0f32, 1.0f32);
+                for _ in 0..24 {
+                    let mid = (low + high) / 2.0;
+                    if bezier(x1, x2, mid) < t {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                bezier(y1, y2, (low + high) / 2.0)
+            }
+        }
+    }
+}
+
+/// Values that can be blended, `t` going from 0 (`self`) to 1 (`other`).
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for ScalarPair {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        ScalarPair {
+            x: self.x.interpolate(&other.x, t),
+            y: self.y.interpolate(&other.y, t),
+        }
+    }
+}
+
+impl Interpolate for Material {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        // Transparent fades as the other color with zero alpha, rather than through black
+        let (from, to) = match (*self, *other) {
+            (Material::Transparent, Material::Transparent) => return Material::Transparent,
+            (Material::Transparent, Material::Solid(r, g, b, a)) =>
+                ((r, g, b, 0.0), (r, g, b, a)),
+            (Material::Solid(r, g, b, a), Material::Transparent) =>
+                ((r, g, b, a), (r, g, b, 0.0)),
+            (Material::Solid(r1, g1, b1, a1), Material::Solid(r2, g2, b2, a2)) =>
+                ((r1, g1, b1, a1), (r2, g2, b2, a2)),
+        };
+        Material::Solid(
+            from.0.interpolate(&to.0, t),
+            from.1.interpolate(&to.1, t),
+            from.2.interpolate(&to.2, t),
+            from.3.interpolate(&to.3, t))
+    }
+}
+
+impl Interpolate for Brush {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Brush {
+            stroke_mat: self.stroke_mat.interpolate(&other.stroke_mat, t),
+            fill_mat: self.fill_mat.interpolate(&other.fill_mat, t),
+            stroke_width: self.stroke_width.interpolate(&other.stroke_width, t),
+            dash: if t < 0.5 { self.dash } else { other.dash },
+            blend_mode: if t < 0.5 { self.blend_mode } else { other.blend_mode },
+        }
+    }
+}
+
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Transform {
+            translate: self.translate.interpolate(&other.translate, t),
+            scale: self.scale.interpolate(&other.scale, t),
+            rotate: self.rotate.interpolate(&other.rotate, t),
+            rotate_center: self.rotate_center.interpolate(&other.rotate_center, t),
+            clip_size: match (self.clip_size, other.clip_size) {
+                (Some(from), Some(to)) => Some(from.interpolate(&to, t)),
+                (from, to) => if t < 1.0 { from } else { to },
+            },
+            opacity: self.opacity.interpolate(&other.opacity, t),
+            matrix: self.matrix.interpolate(&other.matrix, t),
+        }
+    }
+}
+
+impl Interpolate for Matrix {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        let mut values = self.values;
+        for (value, target) in values.iter_mut().zip(other.values) {
+            *value = value.interpolate(&target, t);
+        }
+        Matrix::new(values)
+    }
+}
+
+impl<T> Property<T> where T: Interpolate + Clone + 'static {
+    /// Move the value to `target` over `duration`, one step per frame.
+    pub fn animate_to(&self, target: T, duration: Duration) -> AnimationHandle {
+        self.animate_with(target, duration, Easing::Linear, None)
+    }
+
+    /// Move the value to `target` over `duration` along the easing curve, then run `finish`
+    /// unless cancelled. An animation of the property already running is cancelled, so the
+    /// new one starts from wherever the value is.
+    pub fn animate_with(&self, target: T, duration: Duration, easing: Easing,
+                        finish: Option<FinishFunc>) -> AnimationHandle {
+        let from = self.get_cloned();
+        let property = self.clone();
+        let id = self.id();
+        // Starting another animation cancels this one, so while it finishes it is still the
+        // one registered for the property
+        let finish: FinishFunc = Box::new(move || {
+            PROPERTY_ANIMATIONS.with(|running| running.borrow_mut().remove(&id));
+            if let Some(finish) = finish {
+                finish();
+            }
+        });
+        let handle = Animator::start(duration, Box::new(move |progress| {
+            property.set_immediate(from.interpolate(&target, easing.evaluate(progress)));
+            Caribou::request_redraw();
+        }), Some(finish));
+        let previous = PROPERTY_ANIMATIONS.with(|running| {
+            let mut running = running.borrow_mut();
+            running.retain(|_, handle| !handle.is_cancelled());
+            running.insert(self.id(), handle.clone())
+        });
+        if let Some(previous) = previous {
+            previous.cancel();
+        }
+        handle
+    }
+
+    /// Animate towards the value of every later `set` instead of jumping to it.
+    pub fn transition(&self, duration: Duration, easing: Easing) {
+        self.set_transition(Some(Rc::new(move |property: &Property<T>, target: T| {
+            property.animate_with(target, duration, easing, None);
+        })));
+    }
+
+    /// Make `set` write the value right away again.
+    pub fn clear_transition(&self) {
+        self.set_transition(None);
+    }
+
+    /// Stop the running animation of the property, leaving the value where it is.
+    pub fn stop_animation(&self) {
+        if let Some(handle) = PROPERTY_ANIMATIONS.with(|running| running.borrow_mut().remove(&self.id())) {
+            handle.cancel();
+        }
+    }
+}
+
+/// Widget properties that can transition implicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionProperty {
+    Position,
+    Size,
+    Opacity,
+    Rotation,
+    CornerRadius,
+    Background,
+    Foreground,
+    Border,
+}
+
+/// Declares which properties of a widget animate when set, e.g.
+/// `Transitions::declare(&card, &[TransitionProperty::Position], duration, Easing::CubicOut)`.
+pub struct Transitions;
+
+impl Transitions {
+    pub fn declare(widget: &Widget, properties: &[TransitionProperty], duration: Duration, easing: Easing) {
+        for property in properties {
+            match property {
+                TransitionProperty::Position => widget.position.transition(duration, easing),
+                TransitionProperty::Size => widget.size.transition(duration, easing),
+                TransitionProperty::Opacity => widget.opacity.transition(duration, easing),
+                TransitionProperty::Rotation => widget.rotation.transition(duration, easing),
+                TransitionProperty::CornerRadius => widget.corner_radius.transition(duration, easing),
+                TransitionProperty::Background => widget.background.transition(duration, easing),
+                TransitionProperty::Foreground => widget.foreground.transition(duration, easing),
+                TransitionProperty::Border => widget.border.transition(duration, easing),
+            }
+        }
+    }
+
+    /// Make the properties snap to new values again.
+    pub fn remove(widget: &Widget, properties: &[TransitionProperty]) {
+        for property in properties {
+            match property {
+                TransitionProperty::Position => widget.position.clear_transition(),
+                TransitionProperty::Size => widget.size.clear_transition(),
+                TransitionProperty::Opacity => widget.opacity.clear_transition(),
+                TransitionProperty::Rotation => widget.rotation.clear_transition(),
+                TransitionProperty::CornerRadius => widget.corner_radius.clear_transition(),
+                TransitionProperty::Background => widget.background.clear_transition(),
+                TransitionProperty::Foreground => widget.foreground.clear_transition(),
+                TransitionProperty::Border => widget.border.clear_transition(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use super::{Easing, Motion, Timeline};
+
+    const ALL: [Easing; 12] = [
+        Easing::Linear, Easing::QuadIn, Easing::QuadOut, Easing::QuadInOut,
+        Easing::CubicIn, Easing::CubicOut, Easing::CubicInOut,
+        Easing::ElasticIn, Easing::ElasticOut, Easing::BounceIn, Easing::BounceOut,
+        Easing::CubicBezier(0.25, 0.1, 0.25, 1.0),
+    ];
+
+    #[test]
+    fn easings_go_from_zero_to_one() {
+        for easing in ALL {
+            assert!(easing.evaluate(0.0).abs() < 1e-4, "{:?} starts off zero", easing);
+            assert!((easing.evaluate(1.0) - 1.0).abs() < 1e-4, "{:?} ends off one", easing);
+        }
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        for easing in ALL {
+            assert_eq!(easing.evaluate(-0.5), easing.evaluate(0.0));
+            assert_eq!(easing.evaluate(1.5), easing.evaluate(1.0));
+        }
+    }
+
+    #[test]
+    fn symmetric_easings_pass_the_middle() {
+        assert!((Easing::QuadInOut.evaluate(0.5) - 0.5).abs() < 1e-4);
+        assert!((Easing::CubicInOut.evaluate(0.5) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn straight_cubic_bezier_is_linear() {
+        let easing = Easing::CubicBezier(0.0, 0.0, 1.0, 1.0);
+        for step in 0..=10 {
+            let t = step as f32 / 10.0;
+            assert!((easing.evaluate(t) - t).abs() < 1e-3);
+        }
+    }
+    #[test]
+    fn sequence_rewinds_when_seeking_back() {
+        let (shared, last) = (Rc::new(Cell::new(0.0)), Rc::new(Cell::new(0.0)));
+        let (first_target, second_target) = (shared.clone(), shared.clone());
+        let last_target = last.clone();
+        let step = Duration::from_secs(1);
+        let timeline = Timeline::new(Motion::Sequence(vec![
+            Motion::tween(step, move |progress| first_target.set(progress)),
+            Motion::tween(step, move |progress| second_target.set(1.0 + progress)),
+            Motion::tween(step, move |progress| last_target.set(progress)),
+        ]));
+        timeline.seek(Duration::from_secs(3));
+        assert_eq!((shared.get(), last.get()), (2.0, 1.0));
+        timeline.seek(Duration::from_millis(500));
+        assert_eq!((shared.get(), last.get()), (0.5, 0.0));
+    }
+}
@@ -1,10 +1,15 @@
 use std::any::Any;
 use std::cell::{Ref, RefCell};
 use std::collections::BTreeMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, LockResult, Mutex, MutexGuard, RwLock, RwLockReadGuard};
-use crate::caribou::math::ScalarPair;
+use crate::caribou::Caribou;
+use crate::caribou::dispatch::Dispatcher;
+use crate::caribou::skia::skia_decode_pict;
+use crate::caribou::store::Store;
+use crate::caribou::math::{Matrix, Region, ScalarPair};
 
 #[derive(Debug, Clone)]
 #[repr(transparent)]
@@ -28,6 +33,93 @@ impl Batch {
     pub fn data(&self) -> LockResult<RwLockReadGuard<Vec<BatchOp>>> {
         self.data.read()
     }
+
+    /// The union of the areas the ops may paint, including stroke widths, transforms and clips.
+    pub fn bounds(&self) -> Option<Region> {
+        self.data().unwrap().iter()
+            .filter_map(|op| op.bounds())
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Whether any op paints over the point.
+    pub fn hit_test(&self, point: ScalarPair) -> bool {
+        self.data().unwrap().iter().any(|op| op.hit_test(point))
+    }
+}
+
+impl BatchOp {
+    pub fn transform(&self) -> &Transform {
+        match self {
+            BatchOp::Pict { transform, .. }
+            | BatchOp::PictRegion { transform, .. }
+            | BatchOp::PictFit { transform, .. }
+            | BatchOp::PictNine { transform, .. }
+            | BatchOp::Path { transform, .. }
+            | BatchOp::Text { transform, .. }
+            | BatchOp::RichText { transform, .. }
+            | BatchOp::Shadow { transform, .. }
+            | BatchOp::Batch { transform, .. }
+            | BatchOp::Clear { transform, .. }
+            | BatchOp::Recording { transform, .. } => transform,
+        }
+    }
+
+    /// The area the op may paint, in the coordinates of its batch.
+    pub fn bounds(&self) -> Option<Region> {
+        self.transform().map_region(self.local_bounds()?)
+    }
+
+    fn local_bounds(&self) -> Option<Region> {
+        match self {
+            BatchOp::Pict { pict, .. } =>
+                Some(Region::origin_size(ScalarPair::default(), pict.size())),
+            BatchOp::PictRegion { dest, .. }
+            | BatchOp::PictFit { dest, .. }
+            | BatchOp::PictNine { dest, .. } => Some(*dest),
+            BatchOp::Path { path, brush, .. } => {
+                if let Material::Transparent = brush.stroke_mat {
+                    path.bounds()
+                } else {
+                    path.bounds().map(|bounds| bounds.inflate(brush.stroke_width / 2.0))
+                }
+            }
+            BatchOp::Text { text, font, alignment, layout, .. } =>
+                Caribou::text_bounds(text, font, alignment, layout),
+            BatchOp::RichText { runs, alignment, layout, .. } =>
+                Caribou::rich_text_bounds(runs, alignment, layout),
+            BatchOp::Shadow { path, shadow, .. } => {
+                // A Gaussian blur fades out within about three sigmas
+                let spread = (shadow.blur_radius * 0.57735 + 0.5) * 3.0;
+                path.bounds().map(|bounds| Region::origin_size(
+                    bounds.origin + shadow.offset, bounds.size).inflate(spread))
+            }
+            BatchOp::Batch { batch, .. } => batch.bounds(),
+            BatchOp::Clear { region, .. } => Some(*region),
+            BatchOp::Recording { recording, .. } => recording.bounds(),
+        }
+    }
+
+    /// Whether the op paints over a point given in the coordinates of its batch. Paths are
+    /// tested against their actual fill and stroke, other ops against their bounds, and
+    /// shadows and clears are never hit.
+    pub fn hit_test(&self, point: ScalarPair) -> bool {
+        let transform = self.transform();
+        if let Some(clip_size) = transform.clip_size {
+            if !Region::origin_size(transform.translate, clip_size).contains(point) {
+                return false;
+            }
+        }
+        let local = match transform.invert() {
+            Some(inverse) => inverse.apply_to_point(point),
+            None => return false,
+        };
+        match self {
+            BatchOp::Path { path, brush, .. } => path.hit_test(local, brush),
+            BatchOp::Shadow { .. } | BatchOp::Clear { .. } => false,
+            BatchOp::Batch { batch, .. } => batch.hit_test(local),
+            _ => self.local_bounds().map_or(false, |bounds| bounds.contains(local)),
+        }
+    }
 }
 
 pub trait BatchConsolidation {
@@ -49,6 +141,31 @@ pub enum BatchOp {
     Pict {
         transform: Transform,
         pict: Pict,
+        sampling: Sampling,
+    },
+    PictRegion {
+        transform: Transform,
+        pict: Pict,
+        src_rect: Region,
+        dest: Region,
+        sampling: Sampling,
+    },
+    /// A whole pict scaled into `dest` according to `fit`
+    PictFit {
+        transform: Transform,
+        pict: Pict,
+        dest: Region,
+        fit: Fit,
+        sampling: Sampling,
+    },
+    /// A pict stretched to `dest` by its `center`, in pict pixels, leaving the corners at
+    /// native size and stretching the edges along one axis only
+    PictNine {
+        transform: Transform,
+        pict: Pict,
+        center: Region,
+        dest: Region,
+        sampling: Sampling,
     },
     Path {
         transform: Transform,
@@ -60,21 +177,111 @@ pub enum BatchOp {
         text: String,
         font: Font,
         alignment: TextAlignment,
+        layout: TextLayout,
+        decoration: TextDecoration,
         brush: Brush,
     },
+    /// Text mixing several fonts and brushes, laid out as one block
+    RichText {
+        transform: Transform,
+        runs: Vec<TextRun>,
+        alignment: TextAlignment,
+        layout: TextLayout,
+    },
+    /// The blurred silhouette of a path, drawn underneath the path itself
+    Shadow {
+        transform: Transform,
+        path: Path,
+        shadow: Shadow,
+    },
     Batch {
         transform: Transform,
         batch: Batch,
+        filter: Option<Filter>,
     },
+    /// Erases everything drawn so far within the region to full transparency. Inside a group
+    /// with a filter or reduced opacity, only the group itself is erased.
+    Clear {
+        transform: Transform,
+        region: Region,
+    },
+    /// Replays a batch recorded earlier by the backend
+    Recording {
+        transform: Transform,
+        recording: Recording,
+    },
+}
+
+pub trait RecordingImpl: Send + Sync + Debug {
+    fn get(&self) -> Box<dyn Any>;
 }
 
+/// A batch recorded once by the backend, cheap to draw again as long as it stays valid.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    data: Arc<Box<dyn RecordingImpl>>,
+    bounds: Option<Region>,
+}
+
+impl Recording {
+    pub fn new(data: Box<dyn RecordingImpl>, bounds: Option<Region>) -> Recording {
+        Recording { data: Arc::new(data), bounds }
+    }
+
+    pub fn data(&self) -> &dyn RecordingImpl {
+        self.data.as_ref().as_ref()
+    }
+
+    pub fn bounds(&self) -> Option<Region> {
+        self.bounds
+    }
+}
+
+/// An effect applied to a nested batch as a whole.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Gaussian blur of the batch's own content, with the given sigma
+    Blur(f32),
+    /// Gaussian blur of what was drawn behind the batch, within its clip, before drawing it
+    BackdropBlur(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shadow {
+    pub offset: ScalarPair,
+    pub blur_radius: f32,
+    pub color: Material,
+}
+
+impl Shadow {
+    pub fn new(offset: ScalarPair, blur_radius: f32, color: Material) -> Shadow {
+        Shadow { offset, blur_radius, color }
+    }
+
+    /// The shadow of `path`, to be added before the op drawing the path.
+    pub fn cast(&self, transform: Transform, path: Path) -> BatchOp {
+        BatchOp::Shadow {
+            transform,
+            path,
+            shadow: *self,
+        }
+    }
+}
+
+/// Placement of drawn content: the content is mapped by `matrix`, rotated, scaled and
+/// finally translated, while the clip applies right after the translation.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transform {
     pub translate: ScalarPair,
     pub scale: ScalarPair,
     pub rotate: f32,
     pub rotate_center: ScalarPair,
     pub clip_size: Option<ScalarPair>,
+    /// Opacity of everything drawn under this transform, composited as a group
+    pub opacity: f32,
+    /// Applied before everything else, for skews and other arbitrary affine maps
+    pub matrix: Matrix,
 }
 
 impl Default for Transform {
@@ -85,18 +292,322 @@ impl Default for Transform {
             rotate: 0.0,
             rotate_center: (0.0, 0.0).into(),
             clip_size: None,
+            opacity: 1.0,
+            matrix: Matrix::identity(),
         }
     }
 }
 
+impl Transform {
+    pub fn from_matrix(matrix: Matrix) -> Transform {
+        Transform { matrix, ..Transform::default() }
+    }
+
+    /// The whole geometric mapping as a single matrix, leaving out clip and opacity.
+    pub fn to_matrix(&self) -> Matrix {
+        self.matrix
+            .then(&Matrix::rotate_about(self.rotate, self.rotate_center))
+            .then(&Matrix::scale(self.scale))
+            .then(&Matrix::translate(self.translate))
+    }
+
+    /// Where a point in the transformed coordinates ends up in the outer ones.
+    pub fn apply_to_point(&self, point: ScalarPair) -> ScalarPair {
+        self.to_matrix().apply_to_point(point)
+    }
+
+    /// The bounds of a transformed region in the outer coordinates, cut by the clip.
+    pub fn map_region(&self, region: Region) -> Option<Region> {
+        let mapped = self.to_matrix().apply_to_region(region);
+        match self.clip_size {
+            Some(clip_size) => mapped.intersection(&Region::origin_size(self.translate, clip_size)),
+            None => Some(mapped),
+        }
+    }
+
+    /// The transform applying `self` first and then `outer`, as when drawing a batch inside a
+    /// batch. The clip of `outer` is kept, while the clip of `self` cannot be expressed once
+    /// other transforms follow it and is dropped.
+    pub fn then(&self, outer: &Transform) -> Transform {
+        Transform {
+            opacity: self.opacity * outer.opacity,
+            matrix: self.to_matrix().then(&outer.matrix),
+            ..*outer
+        }
+    }
+
+    /// The transform mapping outer coordinates back into the transformed ones, e.g. to locate
+    /// the cursor inside rotated content. Clip and opacity are not inverted.
+    pub fn invert(&self) -> Option<Transform> {
+        self.to_matrix().invert().map(Transform::from_matrix)
+    }
+
+    /// Rotate by `degrees` clockwise around `center`, given in the transformed coordinates.
+    pub fn rotated_about(self, degrees: f32, center: ScalarPair) -> Transform {
+        Transform {
+            rotate: degrees,
+            rotate_center: center,
+            ..self
+        }
+    }
+
+    /// Shear the content by the given angles in degrees.
+    pub fn skewed(self, degrees: ScalarPair) -> Transform {
+        Transform {
+            matrix: self.matrix.then(&Matrix::skew(degrees)),
+            ..self
+        }
+    }
+}
+
+/// A span of rich text sharing one font and brush.
 #[derive(Debug, Clone)]
-pub enum TextAlignment {
-    Origin,
-    Center
+pub struct TextRun {
+    pub text: String,
+    pub font: Font,
+    pub brush: Brush,
+    pub decoration: TextDecoration,
+}
+
+impl TextRun {
+    pub fn new(text: &str, font: Font, brush: Brush) -> TextRun {
+        TextRun {
+            text: text.to_string(),
+            font,
+            brush,
+            decoration: TextDecoration::default(),
+        }
+    }
+
+    pub fn underlined(self) -> TextRun {
+        TextRun { decoration: TextDecoration::underline(), ..self }
+    }
+
+    pub fn decorated(self, decoration: TextDecoration) -> TextRun {
+        TextRun { decoration, ..self }
+    }
+}
+
+/// Lines drawn along text. Color and thickness default to the text's own color and the
+/// thickness recommended by the font.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub color: Option<Material>,
+    pub thickness: Option<f32>,
+}
+
+impl TextDecoration {
+    pub fn underline() -> TextDecoration {
+        TextDecoration { underline: true, ..TextDecoration::default() }
+    }
+
+    pub fn strikethrough() -> TextDecoration {
+        TextDecoration { strikethrough: true, ..TextDecoration::default() }
+    }
+
+    pub fn is_none(&self) -> bool {
+        !self.underline && !self.strikethrough
+    }
+}
+
+/// How text is broken into lines. Newlines always start a new line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextLayout {
+    /// Width available to a line, needed for wrapping
+    pub width: Option<f32>,
+    pub wrap: TextWrap,
+    /// Multiplier of the line spacing recommended by the font
+    pub line_spacing: f32,
+    /// What happens to lines still wider than the width after wrapping
+    pub overflow: TextOverflow,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        TextLayout {
+            width: None,
+            wrap: TextWrap::None,
+            line_spacing: 1.0,
+            overflow: TextOverflow::Visible,
+        }
+    }
+}
+
+impl TextLayout {
+    pub fn wrapped(width: f32, wrap: TextWrap) -> TextLayout {
+        TextLayout {
+            width: Some(width),
+            wrap,
+            ..TextLayout::default()
+        }
+    }
+
+    pub fn truncated(width: f32, overflow: TextOverflow) -> TextLayout {
+        TextLayout {
+            width: Some(width),
+            overflow,
+            ..TextLayout::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOverflow {
+    /// Draw past the width
+    Visible,
+    /// Cut off at the width
+    Clip,
+    /// Replace the beginning with an ellipsis
+    EllipsisStart,
+    /// Replace the middle with an ellipsis
+    EllipsisMiddle,
+    /// Replace the end with an ellipsis
+    EllipsisEnd,
+}
+
+impl Default for TextOverflow {
+    fn default() -> Self {
+        TextOverflow::Visible
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextWrap {
+    None,
+    /// Break between words, and inside words too long for a line
+    Word,
+    /// Break between any two characters
+    Character,
+}
+
+impl Default for TextWrap {
+    fn default() -> Self {
+        TextWrap::None
+    }
+}
+
+/// Placement of a block of text relative to the origin of its transform. Horizontal
+/// alignment is within the layout width when there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextAlignment {
+    pub horizontal: HorizontalAlignment,
+    pub vertical: VerticalAlignment,
+}
+
+impl TextAlignment {
+    pub fn new(horizontal: HorizontalAlignment, vertical: VerticalAlignment) -> TextAlignment {
+        TextAlignment { horizontal, vertical }
+    }
+
+    /// The top-left corner of the text at the origin.
+    pub fn origin() -> TextAlignment {
+        TextAlignment::new(HorizontalAlignment::Left, VerticalAlignment::Top)
+    }
+
+    /// The text centered on the origin.
+    pub fn center() -> TextAlignment {
+        TextAlignment::new(HorizontalAlignment::Center, VerticalAlignment::Middle)
+    }
+}
+
+impl Default for TextAlignment {
+    fn default() -> Self {
+        TextAlignment::origin()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HorizontalAlignment {
+    Left,
+    Center,
+    Right,
+    /// Stretch the spaces of every line but the last of a paragraph to fill the width
+    Justify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalAlignment {
+    /// The top of the first line's ascent
+    Top,
+    Middle,
+    /// The baseline of the first line
+    Baseline,
+    /// The bottom of the last line's descent
+    Bottom,
+}
+
+/// How pict pixels are filtered when drawn at another size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Crisp pixels, for pixel art and exact 1:1 drawing
+    Nearest,
+    /// Bilinear filtering, good for moderate scaling
+    Linear,
+    /// Bilinear between mipmap levels, avoids shimmering when shrinking a lot
+    Mipmap,
+    /// Bicubic filtering, the sharpest when enlarging
+    Cubic,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::Linear
+    }
+}
+
+/// How a pict is sized into a destination of a different aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Stretch to the destination, ignoring the aspect ratio
+    Fill,
+    /// Scale to fit entirely inside, centered, leaving bars on two sides
+    Contain,
+    /// Scale to cover the destination, centered, cropping the overflowing part
+    Cover,
+    /// Keep the native size, centered and cropped to the destination
+    None,
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit::Contain
+    }
+}
+
+impl Fit {
+    /// The source and destination rectangles for an image of `size` drawn into `dest`. Both
+    /// are empty if either the image or `dest` is, as nothing would be drawn.
+    pub fn resolve(&self, size: ScalarPair, dest: Region) -> (Region, Region) {
+        if size.x <= 0.0 || size.y <= 0.0 || dest.size.x <= 0.0 || dest.size.y <= 0.0 {
+            return (Region::default(), Region::default());
+        }
+        let whole = Region::origin_size(ScalarPair::default(), size);
+        let centered = |inner: ScalarPair, outer: Region| Region::origin_size(
+            outer.origin + (outer.size - inner).times(0.5), inner);
+        match self {
+            Fit::Fill => (whole, dest),
+            Fit::Contain => {
+                let scale = (dest.size.x / size.x).min(dest.size.y / size.y);
+                (whole, centered(size.times(scale), dest))
+            }
+            Fit::Cover => {
+                let scale = (dest.size.x / size.x).max(dest.size.y / size.y);
+                (centered(dest.size.times(1.0 / scale), whole), dest)
+            }
+            Fit::None => {
+                let shown = ScalarPair::new(size.x.min(dest.size.x), size.y.min(dest.size.y));
+                (centered(shown, whole), centered(shown, dest))
+            }
+        }
+    }
 }
 
 pub trait PictImpl: Send + Sync + Debug {
     fn get(&self) -> Box<dyn Any>;
+    /// Size in pixels
+    fn size(&self) -> ScalarPair;
 }
 
 #[derive(Debug, Clone)]
@@ -112,6 +623,99 @@ impl Pict {
     pub fn data(&self) -> LockResult<RwLockReadGuard<Box<dyn PictImpl>>> {
         self.data.read()
     }
+
+    pub fn size(&self) -> ScalarPair {
+        self.data.read().unwrap().size()
+    }
+
+    /// Decode an image file in any format the backend supports, such as PNG, JPEG or WebP.
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Pict, ImageError> {
+        Pict::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Pict, ImageError> {
+        skia_decode_pict(bytes)
+    }
+
+    /// Decode an image file on the Dispatcher, which must have been launched, and hand the
+    /// result to `callback` on the UI thread before the next frame.
+    pub fn load_async<F>(path: PathBuf, callback: F)
+        where F: FnOnce(Result<Pict, ImageError>) + Send + 'static {
+        Dispatcher::push(Box::new(move || {
+            let result = Pict::from_path(path);
+            Store::post_task(Box::new(move || callback(result)));
+        }));
+    }
+}
+
+/// Why an image could not be loaded.
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    /// The data is not in a format the backend recognizes
+    UnsupportedFormat,
+    /// The data is in a known format but damaged or truncated
+    Corrupt,
+    /// The image was asked for at a size without any pixels
+    EmptySize,
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "cannot read image: {}", error),
+            ImageError::UnsupportedFormat => write!(f, "unsupported image format"),
+            ImageError::Corrupt => write!(f, "corrupt image data"),
+            ImageError::EmptySize => write!(f, "image size has no area"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(error: std::io::Error) -> Self {
+        ImageError::Io(error)
+    }
+}
+
+/// A pict sliced into a grid of equally sized cells, e.g. an icon strip or a sprite sheet.
+#[derive(Debug, Clone)]
+pub struct PictAtlas {
+    pub pict: Pict,
+    pub cell_size: ScalarPair,
+    columns: usize,
+}
+
+impl PictAtlas {
+    /// Slice the pict into cells, `columns` of them per row. There is no atlas without any
+    /// columns.
+    pub fn new(pict: Pict, cell_size: ScalarPair, columns: usize) -> Option<PictAtlas> {
+        (columns > 0).then_some(PictAtlas { pict, cell_size, columns })
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// The source rectangle of the cell at `index`, counted row by row.
+    pub fn region(&self, index: usize) -> Region {
+        let column = (index % self.columns) as f32;
+        let row = (index / self.columns) as f32;
+        Region::origin_size(
+            (column * self.cell_size.x, row * self.cell_size.y).into(),
+            self.cell_size)
+    }
+
+    pub fn draw(&self, index: usize, transform: Transform, dest: Region, sampling: Sampling) -> BatchOp {
+        BatchOp::PictRegion {
+            transform,
+            pict: self.pict.clone(),
+            src_rect: self.region(index),
+            dest,
+            sampling,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -140,9 +744,155 @@ impl Path {
     pub fn data(&self) -> LockResult<RwLockReadGuard<Vec<PathOp>>> {
         self.data.read()
     }
+
+    /// A region containing the whole path, computed from the control points of curves so it
+    /// may be slightly larger than the tight bounds.
+    pub fn bounds(&self) -> Option<Region> {
+        let mut points = Vec::new();
+        for op in self.data().unwrap().iter() {
+            match op {
+                PathOp::MoveTo(pair) | PathOp::LineTo(pair) => points.push(*pair),
+                PathOp::QuadTo(pair1, pair2) => points.extend([*pair1, *pair2]),
+                PathOp::CubicTo(pair1, pair2, pair3) => points.extend([*pair1, *pair2, *pair3]),
+                PathOp::Close => {}
+                PathOp::Line(begin, end) => points.extend([*begin, *end]),
+                PathOp::Rect(position, size)
+                | PathOp::RoundRect(position, size, _)
+                | PathOp::Oval(position, size) =>
+                    points.extend(Region::origin_size(*position, *size).corners()),
+                PathOp::Arc { oval, .. } | PathOp::ArcTo { oval, .. } =>
+                    points.extend(oval.corners()),
+                PathOp::ArcToTangent(pair1, pair2, _) => points.extend([*pair1, *pair2]),
+                PathOp::Polygon(vertices, _) => points.extend(vertices.iter().copied()),
+            }
+        }
+        Region::enclosing(points)
+    }
+
+    /// Whether the point lies inside the filled area of the path.
+    pub fn contains(&self, point: ScalarPair) -> bool {
+        Caribou::path_contains(self, point)
+    }
+
+    /// Whether the point lies on the outline the brush would stroke, dashes included.
+    pub fn stroke_contains(&self, point: ScalarPair, brush: &Brush) -> bool {
+        Caribou::path_stroke_contains(self, point, brush)
+    }
+
+    /// Whether drawing the path with the brush would paint over the point.
+    pub fn hit_test(&self, point: ScalarPair, brush: &Brush) -> bool {
+        brush.fill_mat != Material::Transparent && self.contains(point)
+            || brush.stroke_mat != Material::Transparent && self.stroke_contains(point, brush)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Chainable construction of a path, e.g.
+/// `PathBuilder::new().move_to(a).line_to(b).line_to(c).close().build()`.
+/// Shape helpers each add a contour of their own.
+#[derive(Debug, Clone, Default)]
+pub struct PathBuilder {
+    ops: Vec<PathOp>,
+}
+
+impl PathBuilder {
+    pub fn new() -> PathBuilder {
+        PathBuilder::default()
+    }
+
+    pub fn build(self) -> Path {
+        Path::from_vec(self.ops)
+    }
+
+    pub fn op(mut self, op: PathOp) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn move_to(self, point: ScalarPair) -> Self {
+        self.op(PathOp::MoveTo(point))
+    }
+
+    pub fn line_to(self, point: ScalarPair) -> Self {
+        self.op(PathOp::LineTo(point))
+    }
+
+    pub fn quad_to(self, control: ScalarPair, end: ScalarPair) -> Self {
+        self.op(PathOp::QuadTo(control, end))
+    }
+
+    pub fn cubic_to(self, control1: ScalarPair, control2: ScalarPair, end: ScalarPair) -> Self {
+        self.op(PathOp::CubicTo(control1, control2, end))
+    }
+
+    pub fn close(self) -> Self {
+        self.op(PathOp::Close)
+    }
+
+    pub fn rect(self, region: Region) -> Self {
+        self.op(PathOp::Rect(region.origin, region.size))
+    }
+
+    pub fn rounded_rect(self, region: Region, radius: f32) -> Self {
+        self.op(PathOp::RoundRect(region.origin, region.size, radius))
+    }
+
+    pub fn circle(self, center: ScalarPair, radius: f32) -> Self {
+        let extent = ScalarPair::new(radius, radius);
+        self.op(PathOp::Oval(center - extent, extent.times(2.0)))
+    }
+
+    /// A slice of the circle, angles in degrees clockwise from the positive x-axis.
+    pub fn pie(self, center: ScalarPair, radius: f32, start_angle: f32, sweep: f32) -> Self {
+        let extent = ScalarPair::new(radius, radius);
+        self.move_to(center)
+            .op(PathOp::ArcTo {
+                oval: Region::origin_size(center - extent, extent.times(2.0)),
+                start_angle,
+                sweep,
+            })
+            .close()
+    }
+
+    /// A line from `begin` to `end` with an open head of the given length at `end`, meant to be
+    /// stroked.
+    pub fn arrow(self, begin: ScalarPair, end: ScalarPair, head_length: f32) -> Self {
+        let direction = end - begin;
+        let length = (direction.x * direction.x + direction.y * direction.y).sqrt();
+        if length == 0.0 {
+            return self;
+        }
+        // The wings spread 30 degrees to either side of the shaft
+        let back = direction.times(-head_length / length);
+        let (sin, cos) = 30.0f32.to_radians().sin_cos();
+        let wing = |sin: f32| end + ScalarPair::new(
+            back.x * cos - back.y * sin,
+            back.x * sin + back.y * cos);
+        self.move_to(begin)
+            .line_to(end)
+            .move_to(wing(sin))
+            .line_to(end)
+            .line_to(wing(-sin))
+    }
+
+    /// A star with `points` tips on the outer radius, the first one straight up, alternating
+    /// with notches on the inner radius.
+    pub fn star(self, center: ScalarPair, points: usize, outer_radius: f32, inner_radius: f32) -> Self {
+        if points < 2 {
+            return self;
+        }
+        let step = std::f32::consts::PI / points as f32;
+        let vertices = (0..points * 2)
+            .map(|index| {
+                let radius = if index % 2 == 0 { outer_radius } else { inner_radius };
+                let (sin, cos) = (step * index as f32 - std::f32::consts::FRAC_PI_2).sin_cos();
+                center + ScalarPair::new(cos * radius, sin * radius)
+            })
+            .collect();
+        self.op(PathOp::Polygon(vertices, true))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum PathOp {
     MoveTo(ScalarPair),
     LineTo(ScalarPair),
@@ -151,14 +901,26 @@ pub enum PathOp {
     Close,
     Line(ScalarPair, ScalarPair),
     Rect(ScalarPair, ScalarPair),
+    RoundRect(ScalarPair, ScalarPair, f32),
     Oval(ScalarPair, ScalarPair),
+    /// A new contour along the oval, angles in degrees clockwise from the positive x-axis
+    Arc { oval: Region, start_angle: f32, sweep: f32 },
+    /// Like `Arc`, but connected to the current point by a line
+    ArcTo { oval: Region, start_angle: f32, sweep: f32 },
+    /// An arc of the given radius tangent to the lines towards and from the first point
+    ArcToTangent(ScalarPair, ScalarPair, f32),
+    /// A contour through all the points, closed back to the first one if requested
+    Polygon(Vec<ScalarPair>, bool),
 }
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Brush {
     pub stroke_mat: Material,
     pub fill_mat: Material,
     pub stroke_width: f32,
+    pub dash: Option<DashPattern>,
+    pub blend_mode: BlendMode,
 }
 
 impl Brush {
@@ -167,6 +929,8 @@ impl Brush {
             stroke_mat: mat,
             fill_mat: Material::Transparent,
             stroke_width,
+            dash: None,
+            blend_mode: BlendMode::SrcOver,
         }
     }
 
@@ -175,6 +939,8 @@ impl Brush {
             stroke_mat: Material::Transparent,
             fill_mat: mat,
             stroke_width: 0.0,
+            dash: None,
+            blend_mode: BlendMode::SrcOver,
         }
     }
 
@@ -183,8 +949,22 @@ impl Brush {
             stroke_mat: Material::Transparent,
             fill_mat: Material::Transparent,
             stroke_width: 0.0,
+            dash: None,
+            blend_mode: BlendMode::SrcOver,
         }
     }
+
+    pub fn is_transparent(&self) -> bool {
+        self.stroke_mat == Material::Transparent && self.fill_mat == Material::Transparent
+    }
+
+    pub fn dashed(self, dash: DashPattern) -> Brush {
+        Brush { dash: Some(dash), ..self }
+    }
+
+    pub fn blended(self, blend_mode: BlendMode) -> Brush {
+        Brush { blend_mode, ..self }
+    }
 }
 
 impl Default for Brush {
@@ -193,18 +973,69 @@ impl Default for Brush {
     }
 }
 
+/// How painted pixels combine with what is already drawn below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+    /// Paint over, respecting alpha
+    SrcOver,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    /// Erase to transparent wherever painted
+    Clear,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::SrcOver
+    }
+}
+
+/// Alternating on and off lengths along a stroke, starting `phase` into the pattern.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DashPattern {
+    pub on: f32,
+    pub off: f32,
+    pub phase: f32,
+}
+
+impl DashPattern {
+    pub fn dashed(on: f32, off: f32) -> DashPattern {
+        DashPattern { on, off, phase: 0.0 }
+    }
+
+    /// Round dots, as wide as the stroke, separated by `spacing`.
+    pub fn dotted(spacing: f32) -> DashPattern {
+        DashPattern { on: 0.0, off: spacing, phase: 0.0 }
+    }
+
+    pub fn is_dotted(&self) -> bool {
+        self.on == 0.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Material {
     Transparent,
     Solid(f32, f32, f32, f32),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Font {
     pub family: Arc<String>,
     pub size: f32,
     pub weight: i32,
     pub slant: FontSlant,
+    /// Overrides the edging of `Window::text_rendering` for this font
+    pub edging: Option<TextEdging>,
+    /// Overrides the hinting of `Window::text_rendering` for this font
+    pub hinting: Option<TextHinting>,
 }
 
 impl Default for Font {
@@ -214,13 +1045,170 @@ impl Default for Font {
             size: 12.0,
             weight: 400,
             slant: FontSlant::Normal,
+            edging: None,
+            hinting: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How the edges of glyphs are smoothed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextEdging {
+    /// Hard pixel edges
+    Alias,
+    /// Grayscale coverage
+    Antialias,
+    /// Coverage per color element, sharper on LCD panels but tinted on others
+    SubpixelAntialias,
+}
+
+impl Default for TextEdging {
+    fn default() -> Self {
+        TextEdging::Antialias
+    }
+}
+
+/// How strongly glyph outlines are fitted to the pixel grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextHinting {
+    None,
+    Slight,
+    Normal,
+    Full,
+}
+
+impl Default for TextHinting {
+    fn default() -> Self {
+        TextHinting::Normal
+    }
+}
+
+/// Arrangement of the color elements within a pixel of the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LcdOrder {
+    Rgb,
+    Bgr,
+    VerticalRgb,
+    VerticalBgr,
+}
+
+impl Default for LcdOrder {
+    fn default() -> Self {
+        LcdOrder::Rgb
+    }
+}
+
+/// Text rendering settings applying to every font that does not override them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextRendering {
+    pub edging: TextEdging,
+    pub hinting: TextHinting,
+    /// Used by subpixel antialiasing
+    pub lcd_order: LcdOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FontSlant {
     Normal,
     Italic,
     Oblique,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fit, Transform};
+    use crate::caribou::math::{Matrix, Region, ScalarPair};
+
+    fn region(x: f32, y: f32, width: f32, height: f32) -> Region {
+        Region::origin_size(ScalarPair::new(x, y), ScalarPair::new(width, height))
+    }
+
+    fn resolve(fit: Fit, width: f32, height: f32, dest: Region) -> (Region, Region) {
+        fit.resolve(ScalarPair::new(width, height), dest)
+    }
+
+    #[test]
+    fn fill_stretches() {
+        let dest = region(5.0, 5.0, 40.0, 40.0);
+        assert_eq!(resolve(Fit::Fill, 10.0, 20.0, dest), (region(0.0, 0.0, 10.0, 20.0), dest));
+    }
+
+    #[test]
+    fn contain_centers_inside() {
+        let dest = region(0.0, 0.0, 40.0, 40.0);
+        assert_eq!(resolve(Fit::Contain, 20.0, 10.0, dest),
+                   (region(0.0, 0.0, 20.0, 10.0), region(0.0, 10.0, 40.0, 20.0)));
+    }
+
+    #[test]
+    fn cover_crops_the_overflow() {
+        let dest = region(0.0, 0.0, 40.0, 40.0);
+        assert_eq!(resolve(Fit::Cover, 20.0, 10.0, dest), (region(5.0, 0.0, 10.0, 10.0), dest));
+    }
+
+    #[test]
+    fn none_keeps_the_native_size() {
+        let dest = region(0.0, 0.0, 40.0, 40.0);
+        assert_eq!(resolve(Fit::None, 20.0, 60.0, dest),
+                   (region(0.0, 10.0, 20.0, 40.0), region(10.0, 0.0, 20.0, 40.0)));
+    }
+
+    #[test]
+    fn empty_sizes_draw_nothing() {
+        let empty = (Region::default(), Region::default());
+        assert_eq!(resolve(Fit::Contain, 0.0, 10.0, region(0.0, 0.0, 40.0, 40.0)), empty);
+        assert_eq!(resolve(Fit::Cover, 10.0, 10.0, region(0.0, 0.0, 40.0, 0.0)), empty);
+    }
+    fn assert_near(actual: ScalarPair, expected: ScalarPair) {
+        assert!((actual.x - expected.x).abs() < 1e-4 && (actual.y - expected.y).abs() < 1e-4,
+                "{:?} is not {:?}", actual, expected);
+    }
+
+    fn sample_transform() -> Transform {
+        Transform {
+            translate: (5.0, 7.0).into(),
+            scale: (2.0, 3.0).into(),
+            matrix: Matrix::skew((45.0, 0.0).into()),
+            ..Transform::default()
+        }.rotated_about(90.0, (1.0, 1.0).into())
+    }
+
+    #[test]
+    fn transform_maps_like_the_canvas() {
+        // The canvas translates, scales, rotates and concatenates the matrix in turn, so
+        // points go through them in the opposite order
+        let canvas = |point: ScalarPair| {
+            let skewed = ScalarPair::new(point.x + point.y, point.y);
+            let rotated = ScalarPair::new(1.0 - (skewed.y - 1.0), 1.0 + (skewed.x - 1.0));
+            ScalarPair::new(rotated.x * 2.0 + 5.0, rotated.y * 3.0 + 7.0)
+        };
+        let transform = sample_transform();
+        for point in [(0.0, 0.0), (1.0, 1.0), (3.0, -2.0)] {
+            let point = ScalarPair::from(point);
+            assert_near(transform.apply_to_point(point), canvas(point));
+        }
+    }
+
+    #[test]
+    fn transforms_compose_and_invert() {
+        let inner = sample_transform();
+        let outer = Transform {
+            translate: (-4.0, 2.0).into(),
+            scale: (0.5, 0.5).into(),
+            ..Transform::default()
+        }.rotated_about(30.0, (3.0, 0.0).into());
+        let composed = inner.then(&outer);
+        let inverse = composed.invert().unwrap();
+        for point in [(0.0, 0.0), (1.0, 1.0), (3.0, -2.0)] {
+            let point = ScalarPair::from(point);
+            let mapped = composed.apply_to_point(point);
+            assert_near(mapped, outer.apply_to_point(inner.apply_to_point(point)));
+            assert_near(inverse.apply_to_point(mapped), point);
+        }
+    }
+}
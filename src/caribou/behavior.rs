@@ -0,0 +1,126 @@
+use std::rc::Rc;
+use crate::caribou::Caribou;
+use crate::caribou::event::{EventInit, SingleArgEvent};
+use crate::caribou::property::{BoolProperty, Property, PropertyInit};
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetRef, WidgetRefer};
+
+/// Width thresholds splitting a widget's possible widths into ranges, numbered from 0 for
+/// the narrowest, with an event fired whenever the widget moves to another range.
+pub struct Breakpoints {
+    pub thresholds: Vec<f32>,
+    pub current: Property<usize>,
+    pub on_change: SingleArgEvent<usize>,
+    owner: WidgetRef,
+}
+
+impl Breakpoints {
+    pub fn attach(widget: &Widget, mut thresholds: Vec<f32>) -> Rc<Breakpoints> {
+        thresholds.sort_by(|a, b| a.total_cmp(b));
+        let breakpoints = Rc::new(Breakpoints {
+            current: widget.init_property(0),
+            on_change: widget.init_event(),
+            thresholds,
+            owner: widget.refer(),
+        });
+        breakpoints.current.set(breakpoints.range_of(widget.size.get().x));
+        let weak = Rc::downgrade(&breakpoints);
+        widget.size.listen(Box::new(move |size| {
+            if let Some(breakpoints) = weak.upgrade() {
+                breakpoints.update(size.x);
+            }
+        }));
+        breakpoints
+    }
+
+    pub fn range_of(&self, width: f32) -> usize {
+        self.thresholds.iter().filter(|threshold| width >= **threshold).count()
+    }
+
+    fn update(&self, width: f32) {
+        let range = self.range_of(width);
+        if self.current.set_distinct(range) {
+            self.on_change.broadcast(range);
+        }
+    }
+
+    /// Run `action` every time the widget enters the given range, and right away if it is
+    /// already in it.
+    pub fn on(&self, range: usize, action: Box<dyn Fn(Widget)>) {
+        if *self.current.get() == range {
+            if let Some(widget) = self.owner.acquire() {
+                action(widget);
+            }
+        }
+        self.on_change.subscribe(Box::new(move |widget, entered| {
+            if entered == range {
+                action(widget);
+            }
+        }));
+    }
+}
+
+/// Hover, press and focus tracking attachable to any widget.
+pub struct Interactivity {
+    pub is_hovered: BoolProperty,
+    pub is_pressed: BoolProperty,
+    pub is_focused: BoolProperty,
+}
+
+impl Interactivity {
+    pub fn attach(widget: &Widget) -> Rc<Interactivity> {
+        let inter = Rc::new(Interactivity {
+            is_hovered: widget.init_property(false),
+            is_pressed: widget.init_property(false),
+            is_focused: widget.init_property(false),
+        });
+        let this = inter.clone();
+        widget.on_mouse_enter.subscribe(Box::new(move |_| {
+            if this.is_hovered.set_distinct(true) {
+                Caribou::request_redraw();
+            }
+        }));
+        let this = inter.clone();
+        widget.on_mouse_leave.subscribe(Box::new(move |_| {
+            let changed = this.is_hovered.set_distinct(false) | this.is_pressed.set_distinct(false);
+            if changed {
+                Caribou::request_redraw();
+            }
+        }));
+        let this = inter.clone();
+        widget.on_primary_down.subscribe(Box::new(move |comp| {
+            if comp.enabled.is_true() {
+                let changed = this.is_pressed.set_distinct(true);
+                Caribou::request_focus(&comp);
+                if changed {
+                    Caribou::request_redraw();
+                }
+            }
+        }));
+        let this = inter.clone();
+        widget.on_primary_up.subscribe(Box::new(move |_| {
+            if this.is_pressed.set_distinct(false) {
+                Caribou::request_redraw();
+            }
+        }));
+        let this = inter.clone();
+        widget.on_gain_focus.subscribe(Box::new(move |comp| {
+            if comp.enabled.is_true() {
+                if this.is_focused.set_distinct(true) {
+                    Caribou::request_redraw();
+                }
+                true
+            } else {
+                false
+            }
+        }));
+        let this = inter.clone();
+        widget.on_lose_focus.subscribe(Box::new(move |_| {
+            let changed = this.is_focused.set_distinct(false) | this.is_pressed.set_distinct(false);
+            if changed {
+                Caribou::request_redraw();
+            }
+            true
+        }));
+        inter
+    }
+}
@@ -0,0 +1,148 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use crate::caribou::batch::{Batch, Brush, Font};
+use crate::caribou::math::{Dimension, DimensionPair};
+use crate::caribou::widget::Widget;
+use crate::caribou::widgets::{Button, Layout, ScrollView, TextField};
+
+/// Fluent construction of a widget of kind `W`, e.g.
+/// `Button::builder().text("OK").size(80.0, 28.0).on_action(|_, _| {}).build()`.
+pub struct Builder<W> {
+    widget: Widget,
+    kind: PhantomData<W>,
+}
+
+impl<W> Builder<W> {
+    pub fn wrap(widget: Widget) -> Builder<W> {
+        Builder { widget, kind: PhantomData }
+    }
+
+    pub fn position(self, x: f32, y: f32) -> Self {
+        self.widget.position.set((x, y).into());
+        self
+    }
+
+    pub fn size(self, width: f32, height: f32) -> Self {
+        self.widget.size.set((width, height).into());
+        self
+    }
+
+    pub fn relative_size(self, width: Dimension, height: Dimension) -> Self {
+        self.widget.relative_size.set(DimensionPair::new(width, height));
+        self
+    }
+
+    pub fn z_index(self, z_index: i32) -> Self {
+        self.widget.z_index.set(z_index);
+        self
+    }
+
+    pub fn enabled(self, enabled: bool) -> Self {
+        self.widget.enabled.set(enabled);
+        self
+    }
+
+    pub fn background(self, brush: Brush) -> Self {
+        self.widget.background.set(brush);
+        self
+    }
+
+    pub fn border(self, brush: Brush) -> Self {
+        self.widget.border.set(brush);
+        self
+    }
+
+    pub fn corner_radius(self, radius: f32) -> Self {
+        self.widget.corner_radius.set(radius);
+        self
+    }
+
+    pub fn rotation(self, degrees: f32) -> Self {
+        self.widget.rotation.set(degrees);
+        self
+    }
+
+    pub fn font(self, font: Font) -> Self {
+        self.widget.font.set(Some(font));
+        self
+    }
+
+    pub fn on_action<F>(self, action: F) -> Self where F: Fn(Widget, Rc<dyn Any>) + 'static {
+        self.widget.action.subscribe(Box::new(action));
+        self
+    }
+
+    pub fn on_draw<F>(self, draw: F) -> Self where F: Fn(Widget) -> Batch + 'static {
+        self.widget.on_draw.subscribe(Box::new(draw));
+        self
+    }
+
+    /// Run arbitrary setup on the widget being built.
+    pub fn with<F>(self, setup: F) -> Self where F: FnOnce(&Widget) {
+        setup(&self.widget);
+        self
+    }
+
+    pub fn build(self) -> Widget {
+        self.widget
+    }
+}
+
+impl Builder<Layout> {
+    pub fn child(self, child: Widget) -> Self {
+        self.widget.children.push(child);
+        self
+    }
+}
+
+impl Builder<ScrollView> {
+    pub fn content(self, content: Widget) -> Self {
+        self.widget.content.put(content);
+        self
+    }
+}
+
+impl Builder<Button> {
+    pub fn text(self, text: &str) -> Self {
+        Button::interpret(&self.widget).unwrap().text.set(text.to_string());
+        self
+    }
+
+    /// Draw with the default style even where the provider for buttons was replaced.
+    pub fn default_style(self) -> Self {
+        Button::interpret(&self.widget).unwrap().apply_default_style();
+        self
+    }
+}
+
+impl Builder<TextField> {
+    pub fn text(self, text: &str) -> Self {
+        TextField::interpret(&self.widget).unwrap().text.set(text.to_string());
+        self
+    }
+}
+
+impl Layout {
+    pub fn builder() -> Builder<Layout> {
+        Builder::wrap(Layout::create())
+    }
+}
+
+impl ScrollView {
+    pub fn builder() -> Builder<ScrollView> {
+        Builder::wrap(ScrollView::create())
+    }
+}
+
+impl Button {
+    pub fn builder() -> Builder<Button> {
+        Builder::wrap(Button::create())
+    }
+}
+
+impl TextField {
+    pub fn builder() -> Builder<TextField> {
+        Builder::wrap(TextField::create())
+    }
+}
@@ -0,0 +1,43 @@
+use std::cell::RefCell;
+use log::warn;
+use crate::caribou::Caribou;
+
+thread_local! {
+    // Kept alive for the whole session, as on X11 the copied contents vanish with it
+    static CLIPBOARD: RefCell<Option<arboard::Clipboard>> = RefCell::new(
+        arboard::Clipboard::new()
+            .map_err(|err| warn!("Clipboard unavailable: {}", err))
+            .ok());
+}
+
+/// Access to the platform clipboard, obtained from `Caribou::clipboard()`.
+pub struct Clipboard;
+
+impl Clipboard {
+    /// The text on the clipboard, if it holds any.
+    pub fn text(&self) -> Option<String> {
+        CLIPBOARD.with(|clipboard| clipboard.borrow_mut().as_mut()?.get_text().ok())
+    }
+
+    /// Put text on the clipboard, returning whether the platform took it.
+    pub fn set_text(&self, text: &str) -> bool {
+        CLIPBOARD.with(|clipboard| match clipboard.borrow_mut().as_mut() {
+            Some(clipboard) => clipboard.set_text(text).is_ok(),
+            None => false,
+        })
+    }
+
+    pub fn clear(&self) {
+        CLIPBOARD.with(|clipboard| {
+            if let Some(clipboard) = clipboard.borrow_mut().as_mut() {
+                let _ = clipboard.clear();
+            }
+        });
+    }
+}
+
+impl Caribou {
+    pub fn clipboard() -> Clipboard {
+        Clipboard
+    }
+}
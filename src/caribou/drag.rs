@@ -0,0 +1,118 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Batch, BatchOp, Transform};
+use crate::caribou::math::{IntPair, ScalarPair};
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetRef, WidgetRefer};
+
+/// Opacity of the visual following the cursor during a drag
+const GHOST_OPACITY: f32 = 0.7;
+
+struct Drag {
+    source: WidgetRef,
+    payload: Rc<dyn Any>,
+    visual: Option<Batch>,
+    /// Where the cursor holds the visual, in the visual's coordinates
+    grip: ScalarPair,
+    target: Option<WidgetRef>,
+}
+
+thread_local! {
+    static DRAG: RefCell<Option<Drag>> = RefCell::new(None);
+}
+
+impl Caribou {
+    /// Start dragging `payload` from `source`, typically from its `on_mouse_move` while the
+    /// primary button is held. The visual is drawn above everything else with the cursor at
+    /// `grip`. Widgets under the cursor are offered the payload through `on_drag_over`, and
+    /// releasing the button delivers it to the accepting one through `on_drop`. The release
+    /// ends the drag and is not delivered as `on_primary_up`; the source learns the outcome
+    /// from `on_drag_end`.
+    pub fn start_drag(source: &Widget, payload: Rc<dyn Any>, visual: Option<Batch>, grip: ScalarPair) {
+        Caribou::cancel_drag();
+        DRAG.with(|drag| *drag.borrow_mut() = Some(Drag {
+            source: source.refer(),
+            payload,
+            visual,
+            grip,
+            target: None,
+        }));
+        Caribou::route_drag_move(Caribou::mouse_position());
+    }
+
+    pub fn is_dragging() -> bool {
+        DRAG.with(|drag| drag.borrow().is_some())
+    }
+
+    /// End the current drag without dropping.
+    pub fn cancel_drag() {
+        if let Some(drag) = DRAG.with(|drag| drag.borrow_mut().take()) {
+            Caribou::finish_drag(drag, false);
+        }
+    }
+
+    /// Offer the dragged payload to the widgets under the cursor, innermost first. Returns
+    /// whether a drag is in progress.
+    pub(crate) fn route_drag_move(pos: IntPair) -> bool {
+        let payload = match DRAG.with(|drag| drag.borrow().as_ref().map(|drag| drag.payload.clone())) {
+            Some(payload) => payload,
+            None => return false,
+        };
+        let target = Caribou::widgets_at(pos.to_scalar()).into_iter().rev()
+            .find(|widget| widget.on_drag_over.any_true(payload.clone()));
+        let previous = DRAG.with(|drag| drag.borrow_mut().as_mut()
+            .and_then(|drag| std::mem::replace(&mut drag.target, target.as_ref().map(|widget| widget.refer()))));
+        if let Some(previous) = previous.and_then(|widget| widget.acquire()) {
+            if !target.map_or(false, |target| Rc::ptr_eq(&target, &previous)) {
+                previous.on_drag_leave.broadcast();
+            }
+        }
+        Caribou::request_redraw();
+        true
+    }
+
+    /// Drop the payload on the accepting widget. Returns whether a drag was in progress.
+    pub(crate) fn route_drag_release() -> bool {
+        match DRAG.with(|drag| drag.borrow_mut().take()) {
+            Some(drag) => {
+                let dropped = drag.target.as_ref().and_then(|target| target.acquire())
+                    .map_or(false, |target| target.on_drop.any_true(drag.payload.clone()));
+                Caribou::finish_drag(drag, dropped);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish_drag(drag: Drag, dropped: bool) {
+        if !dropped {
+            if let Some(target) = drag.target.as_ref().and_then(|target| target.acquire()) {
+                target.on_drag_leave.broadcast();
+            }
+        }
+        if let Some(source) = drag.source.acquire() {
+            source.on_drag_end.broadcast(dropped);
+        }
+        Caribou::request_redraw();
+    }
+
+    /// The visual of the current drag placed at the cursor, drawn on the overlay.
+    pub(crate) fn drag_ghost() -> Batch {
+        let batch = Batch::new();
+        DRAG.with(|drag| {
+            if let Some(Drag { visual: Some(visual), grip, .. }) = drag.borrow().as_ref() {
+                batch.add_op(BatchOp::Batch {
+                    transform: Transform {
+                        translate: Caribou::mouse_position().to_scalar() - *grip,
+                        opacity: GHOST_OPACITY,
+                        ..Transform::default()
+                    },
+                    batch: visual.clone(),
+                    filter: None,
+                });
+            }
+        });
+        batch
+    }
+}
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use crate::caribou::widget::{Widget, WidgetRef};
+use crate::caribou::math::ScalarPair;
+use crate::caribou::widget::{ScrollChaining, Widget, WidgetRef};
 use crate::WidgetInner;
 
 pub type ZeroArgEvent<R=()> = Event<Box<dyn Fn(Widget) -> R>>;
@@ -48,19 +49,63 @@ impl<T> PartialEq for Subscriber<T> {
     }
 }
 
+/// Removes a subscriber or property listener when dropped. Keep it for as long as the
+/// callback should run, or hand it to a widget with `WidgetInner::own` to tie the callback
+/// to the widget's lifetime.
+#[must_use]
+pub struct SubscriptionGuard {
+    remove: Option<Box<dyn FnOnce()>>,
+}
+
+impl SubscriptionGuard {
+    pub fn new<F>(remove: F) -> SubscriptionGuard where F: FnOnce() + 'static {
+        SubscriptionGuard { remove: Some(Box::new(remove)) }
+    }
+
+    /// Keep the callback for as long as the event or property lives.
+    pub fn forget(mut self) {
+        self.remove = None;
+    }
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        if let Some(remove) = self.remove.take() {
+            remove();
+        }
+    }
+}
+
 pub struct Event<F> {
-    listeners: RefCell<Vec<Subscriber<F>>>,
+    listeners: Rc<RefCell<Vec<Subscriber<F>>>>,
     back_ref: WidgetRef,
 }
 
 impl<F> Event<F> {
     pub fn new(back_ref: WidgetRef) -> Self {
         Self {
-            listeners: RefCell::new(Vec::new()),
+            listeners: Rc::new(RefCell::new(Vec::new())),
             back_ref,
         }
     }
 
+    /// Subscribe until the returned guard is dropped.
+    pub fn subscribe_guarded(&self, listener: F) -> SubscriptionGuard where F: 'static {
+        let subscriber = self.subscribe(listener);
+        let listeners = Rc::downgrade(&self.listeners);
+        SubscriptionGuard::new(move || {
+            if let Some(listeners) = listeners.upgrade() {
+                listeners.borrow_mut().retain(|l| l != &subscriber);
+            }
+        })
+    }
+
+    /// Subscribe for as long as `owner` lives, e.g. when a widget reacts to an event of
+    /// another one that may outlive it.
+    pub fn subscribe_for(&self, owner: &Widget, listener: F) where F: 'static {
+        owner.own(self.subscribe_guarded(listener));
+    }
+
     pub fn subscribe(&self, listener: F) -> Subscriber<F> {
         let func = Subscriber::new(listener);
         self.listeners.borrow_mut().push(func.clone());
@@ -113,3 +158,32 @@ impl ZeroArgEvent<bool> {
         self.broadcast().iter().any(|x| !*x)
     }
 }
+
+impl<T> SingleArgEvent<T, bool> where T: Clone {
+    pub fn none_true(&self, value: T) -> bool {
+        !self.broadcast(value).iter().any(|x| *x)
+    }
+
+    pub fn any_true(&self, value: T) -> bool {
+        self.broadcast(value).iter().any(|x| *x)
+    }
+}
+
+impl SingleArgEvent<ScalarPair, ScalarPair> {
+    /// Broadcast a scroll delta and return the part no subscriber consumed.
+    pub fn remaining(&self, delta: ScalarPair) -> ScalarPair {
+        self.broadcast(delta).iter().fold(delta, |rest, x| ScalarPair {
+            x: if x.x.abs() < rest.x.abs() { x.x } else { rest.x },
+            y: if x.y.abs() < rest.y.abs() { x.y } else { rest.y },
+        })
+    }
+
+    /// Offer a scroll delta to the widget and return what should bubble to its parent.
+    pub fn route(&self, delta: ScalarPair) -> ScalarPair {
+        let rest = self.remaining(delta);
+        match *self.back_ref.upgrade().unwrap().scroll_chaining.get() {
+            ScrollChaining::Chain => rest,
+            ScrollChaining::Contain => ScalarPair::default(),
+        }
+    }
+}
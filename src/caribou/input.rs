@@ -3,6 +3,28 @@
 pub struct KeyEvent {
     pub key: Key,
     pub modifiers: Vec<Modifier>,
+    /// Sent again by the OS because the key is held down
+    pub repeat: bool,
+}
+
+impl KeyEvent {
+    /// Whether the modifier of shortcuts such as copy and paste is held: Command on macOS,
+    /// Control elsewhere.
+    pub fn has_shortcut_modifier(&self) -> bool {
+        let modifier = if cfg!(target_os = "macos") { Modifier::Meta } else { Modifier::Control };
+        self.modifiers.contains(&modifier)
+    }
+
+    /// The dedicated editing key a shortcut stands for, e.g. `Key::Copy` for Ctrl+C, or else
+    /// the key itself.
+    pub fn editing_key(&self) -> Key {
+        match self.key {
+            Key::C if self.has_shortcut_modifier() => Key::Copy,
+            Key::X if self.has_shortcut_modifier() => Key::Cut,
+            Key::V if self.has_shortcut_modifier() => Key::Paste,
+            key => key,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -133,4 +155,32 @@ pub enum Key {
     Copy,
     Paste,
     Cut,
-}
\ No newline at end of file
+}
+
+/// Shape of the mouse cursor over a widget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorIcon {
+    /// Whatever the widget below shows
+    Inherit,
+    Arrow,
+    /// Text insertion
+    IBeam,
+    /// Links and other clickable content
+    Hand,
+    Crosshair,
+    Move,
+    NotAllowed,
+    Wait,
+    ResizeHorizontal,
+    ResizeVertical,
+    /// Resizing along the diagonal from top-left to bottom-right
+    ResizeNwse,
+    /// Resizing along the diagonal from top-right to bottom-left
+    ResizeNesw,
+}
+
+impl Default for CursorIcon {
+    fn default() -> Self {
+        CursorIcon::Inherit
+    }
+}
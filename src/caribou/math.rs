@@ -1,6 +1,7 @@
 use std::ops::{Add, Sub};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScalarPair {
     pub x: f32,
     pub y: f32,
@@ -56,7 +57,62 @@ impl ScalarPair {
     }
 }
 
+/// Extent of a widget along one axis, relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dimension {
+    /// Keep the value stored in `size`
+    Absolute,
+    /// Fraction of the parent's extent, `Percent(0.5)` being half of it
+    Percent(f32),
+    /// Everything from the widget's position to the parent's far edge
+    Fill,
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Dimension::Absolute
+    }
+}
+
+impl Dimension {
+    pub fn resolve(&self, absolute: f32, position: f32, parent: f32) -> f32 {
+        match self {
+            Dimension::Absolute => absolute,
+            Dimension::Percent(ratio) => parent * ratio,
+            Dimension::Fill => (parent - position).max(0.0),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DimensionPair {
+    pub x: Dimension,
+    pub y: Dimension,
+}
+
+impl DimensionPair {
+    pub fn new(x: Dimension, y: Dimension) -> Self {
+        Self { x, y }
+    }
+
+    pub fn resolve(&self, absolute: ScalarPair, position: ScalarPair, parent: ScalarPair) -> ScalarPair {
+        ScalarPair {
+            x: self.x.resolve(absolute.x, position.x, parent.x),
+            y: self.y.resolve(absolute.y, position.y, parent.y),
+        }
+    }
+}
+
+impl From<(Dimension, Dimension)> for DimensionPair {
+    fn from((x, y): (Dimension, Dimension)) -> Self {
+        Self { x, y }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IntPair {
     pub x: i32,
     pub y: i32,
@@ -112,6 +168,8 @@ impl IntPair {
     }
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     pub origin: ScalarPair,
     pub size: ScalarPair,
@@ -140,7 +198,242 @@ impl Region {
     }
 
     pub fn intersects(&self, region: &Region) -> bool {
-        self.contains(region.origin) || self.contains(region.origin + region.size) ||
-        region.contains(self.origin) || region.contains(self.origin + self.size)
+        self.origin.x < region.origin.x + region.size.x &&
+        region.origin.x < self.origin.x + self.size.x &&
+        self.origin.y < region.origin.y + region.size.y &&
+        region.origin.y < self.origin.y + self.size.y
+    }
+
+    /// The smallest region containing both.
+    pub fn union(&self, region: &Region) -> Region {
+        let begin = ScalarPair::new(self.origin.x.min(region.origin.x),
+                                    self.origin.y.min(region.origin.y));
+        let end = ScalarPair::new((self.origin.x + self.size.x).max(region.origin.x + region.size.x),
+                                  (self.origin.y + self.size.y).max(region.origin.y + region.size.y));
+        Region::begin_end(begin, end)
+    }
+
+    /// The smallest region containing all the points, if there are any.
+    pub fn enclosing<I: IntoIterator<Item=ScalarPair>>(points: I) -> Option<Region> {
+        points.into_iter()
+            .map(|point| Region::origin_size(point, ScalarPair::default()))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Grow by `amount` on every side.
+    pub fn inflate(&self, amount: f32) -> Region {
+        Region::origin_size(self.origin - ScalarPair::new(amount, amount),
+                            self.size + ScalarPair::new(amount, amount).times(2.0))
+    }
+
+    pub fn corners(&self) -> [ScalarPair; 4] {
+        [
+            self.origin,
+            self.origin + ScalarPair::new(self.size.x, 0.0),
+            self.origin + ScalarPair::new(0.0, self.size.y),
+            self.origin + self.size,
+        ]
+    }
+
+    pub fn intersection(&self, region: &Region) -> Option<Region> {
+        if !self.intersects(region) {
+            return None;
+        }
+        let begin = ScalarPair::new(self.origin.x.max(region.origin.x),
+                                    self.origin.y.max(region.origin.y));
+        let end = ScalarPair::new((self.origin.x + self.size.x).min(region.origin.x + region.size.x),
+                                  (self.origin.y + self.size.y).min(region.origin.y + region.size.y));
+        Some(Region::begin_end(begin, end))
+    }
+}
+
+/// A 3x3 matrix mapping points in homogeneous coordinates, stored row by row. The last row
+/// is `[0, 0, 1]` for affine maps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Matrix {
+    pub values: [f32; 9],
+}
+
+impl Default for Matrix {
+    fn default() -> Self {
+        Matrix::identity()
+    }
+}
+
+impl Matrix {
+    pub fn new(values: [f32; 9]) -> Matrix {
+        Matrix { values }
+    }
+
+    pub fn identity() -> Matrix {
+        Matrix::new([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    pub fn translate(offset: ScalarPair) -> Matrix {
+        Matrix::new([1.0, 0.0, offset.x, 0.0, 1.0, offset.y, 0.0, 0.0, 1.0])
     }
-}
\ No newline at end of file
+
+    pub fn scale(factors: ScalarPair) -> Matrix {
+        Matrix::new([factors.x, 0.0, 0.0, 0.0, factors.y, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    /// Rotate by `degrees` clockwise around `center`.
+    pub fn rotate_about(degrees: f32, center: ScalarPair) -> Matrix {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Matrix::translate(ScalarPair::default() - center)
+            .then(&Matrix::new([cos, -sin, 0.0, sin, cos, 0.0, 0.0, 0.0, 1.0]))
+            .then(&Matrix::translate(center))
+    }
+
+    /// Shear by the tangents of the given angles in degrees, x along y and y along x.
+    pub fn skew(degrees: ScalarPair) -> Matrix {
+        Matrix::new([1.0, degrees.x.to_radians().tan(), 0.0,
+                     degrees.y.to_radians().tan(), 1.0, 0.0,
+                     0.0, 0.0, 1.0])
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Matrix::identity()
+    }
+
+    /// The matrix applying `self` first and `next` after it.
+    pub fn then(&self, next: &Matrix) -> Matrix {
+        let (a, b) = (&next.values, &self.values);
+        let mut values = [0.0; 9];
+        for row in 0..3 {
+            for column in 0..3 {
+                values[row * 3 + column] = (0..3)
+                    .map(|k| a[row * 3 + k] * b[k * 3 + column])
+                    .sum();
+            }
+        }
+        Matrix::new(values)
+    }
+
+    pub fn apply_to_point(&self, point: ScalarPair) -> ScalarPair {
+        let m = &self.values;
+        let x = m[0] * point.x + m[1] * point.y + m[2];
+        let y = m[3] * point.x + m[4] * point.y + m[5];
+        let w = m[6] * point.x + m[7] * point.y + m[8];
+        if w == 1.0 || w == 0.0 {
+            ScalarPair::new(x, y)
+        } else {
+            ScalarPair::new(x / w, y / w)
+        }
+    }
+
+    /// The bounds of the mapped corners of a region.
+    pub fn apply_to_region(&self, region: Region) -> Region {
+        Region::enclosing(region.corners().map(|corner| self.apply_to_point(corner))).unwrap()
+    }
+
+    /// The matrix undoing this one, unless it collapses the plane.
+    pub fn invert(&self) -> Option<Matrix> {
+        let m = &self.values;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize|
+            m[r0 * 3 + c0] * m[r1 * 3 + c1] - m[r0 * 3 + c1] * m[r1 * 3 + c0];
+        let adjugate = [
+            cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2),
+            -cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2),
+            cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1),
+        ];
+        let determinant = m[0] * adjugate[0] + m[1] * adjugate[3] + m[2] * adjugate[6];
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+        Some(Matrix::new(adjugate.map(|value| value / determinant)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Matrix, Region, ScalarPair};
+
+    fn assert_near(actual: ScalarPair, expected: ScalarPair) {
+        assert!((actual.x - expected.x).abs() < 1e-4 && (actual.y - expected.y).abs() < 1e-4,
+                "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let translate = Matrix::translate((10.0, 0.0).into());
+        let scale = Matrix::scale((2.0, 2.0).into());
+        assert_near(translate.then(&scale).apply_to_point((1.0, 1.0).into()), (22.0, 2.0).into());
+        assert_near(scale.then(&translate).apply_to_point((1.0, 1.0).into()), (12.0, 2.0).into());
+    }
+
+    #[test]
+    fn rotation_is_clockwise_about_the_center() {
+        let rotation = Matrix::rotate_about(90.0, (10.0, 10.0).into());
+        assert_near(rotation.apply_to_point((10.0, 10.0).into()), (10.0, 10.0).into());
+        assert_near(rotation.apply_to_point((20.0, 10.0).into()), (10.0, 20.0).into());
+        assert_near(rotation.apply_to_point((10.0, 20.0).into()), (0.0, 10.0).into());
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = Matrix::translate((5.0, -3.0).into())
+            .then(&Matrix::rotate_about(30.0, (2.0, 2.0).into()))
+            .then(&Matrix::scale((2.0, 0.5).into()))
+            .then(&Matrix::skew((10.0, 0.0).into()));
+        let inverse = matrix.invert().unwrap();
+        for point in [(0.0, 0.0), (1.0, 0.0), (-7.5, 12.0)] {
+            let point = ScalarPair::from(point);
+            assert_near(inverse.apply_to_point(matrix.apply_to_point(point)), point);
+        }
+        let round_trip = matrix.then(&inverse);
+        for (value, identity) in round_trip.values.iter().zip(Matrix::identity().values) {
+            assert!((value - identity).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn collapsing_matrix_has_no_inverse() {
+        assert_eq!(Matrix::scale((0.0, 1.0).into()).invert(), None);
+        assert_eq!(Matrix::new([1.0, 2.0, 0.0, 2.0, 4.0, 0.0, 0.0, 0.0, 1.0]).invert(), None);
+    }
+
+    #[test]
+    fn mapped_region_encloses_the_mapped_corners() {
+        let region = Region::origin_size((0.0, 0.0).into(), (10.0, 20.0).into());
+        let mapped = Matrix::rotate_about(90.0, ScalarPair::default()).apply_to_region(region);
+        assert_near(mapped.origin, (-20.0, 0.0).into());
+        assert_near(mapped.size, (20.0, 10.0).into());
+    }
+    fn region(x: f32, y: f32, width: f32, height: f32) -> Region {
+        Region::origin_size((x, y).into(), (width, height).into())
+    }
+
+    #[test]
+    fn union_covers_both_regions() {
+        assert_eq!(region(0.0, 0.0, 10.0, 10.0).union(&region(5.0, -5.0, 20.0, 5.0)),
+                   region(0.0, -5.0, 25.0, 15.0));
+    }
+
+    #[test]
+    fn enclosing_covers_all_points() {
+        let points = [(3.0, 4.0), (-1.0, 2.0), (5.0, -6.0)].map(ScalarPair::from);
+        assert_eq!(Region::enclosing(points), Some(region(-1.0, -6.0, 6.0, 10.0)));
+        assert_eq!(Region::enclosing(Vec::new()), None);
+    }
+
+    #[test]
+    fn inflate_grows_every_side() {
+        assert_eq!(region(2.0, 2.0, 4.0, 6.0).inflate(1.0), region(1.0, 1.0, 6.0, 8.0));
+        assert_eq!(region(2.0, 2.0, 4.0, 6.0).inflate(-1.0), region(3.0, 3.0, 2.0, 4.0));
+    }
+
+    #[test]
+    fn corners_of_a_region() {
+        let corners = region(1.0, 2.0, 3.0, 4.0).corners();
+        assert_eq!(corners, [(1.0, 2.0), (4.0, 2.0), (1.0, 6.0), (4.0, 6.0)].map(ScalarPair::from));
+    }
+    #[test]
+    fn intersection_is_the_shared_part() {
+        assert_eq!(region(0.0, 0.0, 10.0, 10.0).intersection(&region(5.0, -5.0, 20.0, 10.0)),
+                   Some(region(5.0, 0.0, 5.0, 5.0)));
+        // Regions only touching along an edge share nothing
+        assert_eq!(region(0.0, 0.0, 10.0, 10.0).intersection(&region(10.0, 0.0, 5.0, 5.0)), None);
+    }
+}
@@ -1,15 +1,21 @@
 use std::any::Any;
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use log::info;
 use widget::WidgetInner;
 use event::{EventInit, SingleArgEvent};
-use property::{Property, PropertyInit};
+use property::{Property, PropertyInit, Transaction};
 
-use crate::caribou::math::{IntPair, ScalarPair};
+use crate::caribou::batch::{Batch, BatchConsolidation, Brush, Font, Path, Pict, Recording, TextAlignment, TextLayout, TextRun};
+use crate::caribou::math::{IntPair, Region, ScalarPair};
 use crate::caribou::widgets::Layout;
 use crate::caribou::input::{Key, KeyEvent};
 use crate::caribou::widget::{create_widget, Widget, WidgetRef};
+use crate::caribou::window::Window;
+use crate::caribou::theme::{ColorScheme, Theme};
+use crate::caribou::style::StyleSheet;
+use crate::caribou::routing::RoutedInput;
 
 pub mod skia;
 
@@ -22,6 +28,20 @@ pub mod widget;
 pub mod event;
 pub mod property;
 pub mod dispatch;
+pub mod anim;
+pub mod overlay;
+pub mod behavior;
+pub mod builder;
+pub mod painter;
+pub mod store;
+pub mod drag;
+pub mod clipboard;
+pub mod shortcut;
+pub mod notification;
+pub mod state;
+pub mod theme;
+pub mod style;
+pub mod routing;
 
 thread_local! {
     static ROOT_COMPONENT: RefCell<Widget> = Layout::create().into();
@@ -35,7 +55,12 @@ impl Caribou {
         ROOT_COMPONENT.with(|root| root.borrow().clone())
     }
 
+    /// Replace the root, which is sized to fill the window like the one before.
     pub fn replace_root_component(new_root: Widget) {
+        let logical_size = *Caribou::instance().window.logical_size.get();
+        if logical_size.x > 0.0 && logical_size.y > 0.0 {
+            new_root.size.set_distinct(logical_size);
+        }
         ROOT_COMPONENT.with(|root| *root.borrow_mut() = new_root);
     }
 
@@ -44,34 +69,265 @@ impl Caribou {
     }
 
     pub fn launch() {
+        skia::runtime::skia_bootstrap();
+    }
+
+    /// Whether the key is held down, as far as the window has seen while focused.
+    pub fn is_key_down(key: Key) -> bool {
+        Caribou::instance().pressed_keys.borrow().contains(&key)
+    }
+
+    /// Release every held key, e.g. when the window loses focus and would miss the releases.
+    pub fn release_all_keys() {
+        let pressed = Caribou::instance().pressed_keys.take();
+        for key in pressed {
+            Caribou::dispatch_key_up(KeyEvent { key, modifiers: vec![], repeat: false });
+        }
+    }
+
+    /// Offer a key press to the registered shortcuts, then route it to the focused widget, then
+    /// offer it to the focused widget, its ancestors, the window and the application in turn,
+    /// stopping at the first that handles it. Unhandled tabs move the focus.
+    pub fn dispatch_key_down(event: KeyEvent) -> bool {
         let instance = Caribou::instance();
-        instance.on_key_down.subscribe(Box::new(|_, event| {
-            if event.key == Key::Tab {
-                Caribou::circulate_focus();
-            } else if let Some(rc) =
-            Caribou::instance().focused_component.get().upgrade() {
-                rc.on_key_down.broadcast(event);
+        if !event.repeat {
+            instance.pressed_keys.borrow_mut().push(event.key);
+        }
+        if Caribou::dispatch_shortcut(&event)
+            || Caribou::route_focused(RoutedInput::KeyDown(event.clone())) {
+            return true;
+        }
+        let mut target = instance.focused_component.get().upgrade()
+            .filter(|rc| rc.is_interactive());
+        while let Some(widget) = target {
+            if widget.on_key_down.any_true(event.clone()) {
+                return true;
             }
-        }));
-        instance.on_key_up.subscribe(Box::new(|_, event| {
-            if let Some(rc) =
-            Caribou::instance().focused_component.get().upgrade() {
-                rc.on_key_up.broadcast(event);
+            target = widget.parent.get_cloned().and_then(|parent| parent.upgrade());
+        }
+        if instance.window.on_key_down.any_true(event.clone())
+            || instance.on_key_down.any_true(event.clone()) {
+            return true;
+        }
+        if event.key == Key::Tab {
+            return Caribou::circulate_focus();
+        }
+        false
+    }
+
+    /// Key releases follow the same chain as presses.
+    pub fn dispatch_key_up(event: KeyEvent) -> bool {
+        let instance = Caribou::instance();
+        instance.pressed_keys.borrow_mut().retain(|key| *key != event.key);
+        if Caribou::route_focused(RoutedInput::KeyUp(event.clone())) {
+            return true;
+        }
+        let mut target = instance.focused_component.get().upgrade()
+            .filter(|rc| rc.is_interactive());
+        while let Some(widget) = target {
+            if widget.on_key_up.any_true(event.clone()) {
+                return true;
             }
-        }));
-        skia::runtime::skia_bootstrap();
+            target = widget.parent.get_cloned().and_then(|parent| parent.upgrade());
+        }
+        instance.window.on_key_up.any_true(event.clone())
+            || instance.on_key_up.any_true(event)
+    }
+
+    /// Route a typed character to the focused widget, then offer it to the focused widget and
+    /// its ancestors.
+    pub fn dispatch_char(c: char) -> bool {
+        if c.is_control() {
+            return false;
+        }
+        if Caribou::route_focused(RoutedInput::Char(c)) {
+            return true;
+        }
+        let mut target = Caribou::instance().focused_component.get().upgrade()
+            .filter(|rc| rc.is_interactive());
+        while let Some(widget) = target {
+            if widget.on_char.any_true(c) {
+                return true;
+            }
+            target = widget.parent.get_cloned().and_then(|parent| parent.upgrade());
+        }
+        false
+    }
+
+    /// Hand text composed with an input method to the focused widget.
+    pub fn dispatch_commit(text: String) {
+        if let Some(widget) = Caribou::instance().focused_component.get().upgrade()
+            .filter(|rc| rc.is_interactive()) {
+            widget.on_commit.broadcast(text);
+        }
+    }
+
+    /// The caret of the focused widget in window coordinates, where the input method window
+    /// goes.
+    pub fn caret_area() -> Option<Region> {
+        let widget = Caribou::instance().focused_component.get().upgrade()
+            .filter(|rc| rc.is_interactive())?;
+        let area = widget.caret_area.get_cloned()?;
+        Some(Region::origin_size(area.origin + widgets::window_position(&widget), area.size))
+    }
+
+    /// The last known cursor position in window coordinates, in logical units.
+    pub fn mouse_position() -> IntPair {
+        Caribou::instance().pointer.get()
+    }
+
+    /// Close the window and end the application, e.g. once `on_close_requested` was vetoed and
+    /// the user confirmed afterwards. Can be called from any thread.
+    pub fn quit() {
+        skia::skia_request_exit();
+    }
+
+    /// Whether the window has the keyboard focus; see `Window::on_focus` and `on_blur`.
+    pub fn is_window_focused() -> bool {
+        *Caribou::instance().window.focused.get()
+    }
+
+    /// Physical pixels per logical unit of the window.
+    pub fn scale_factor() -> f32 {
+        *Caribou::instance().window.scale_factor.get()
+    }
+
+    /// Round a logical coordinate to the nearest physical pixel boundary, so that hairlines
+    /// and edges stay sharp at any scale factor.
+    pub fn snap_to_pixel(value: f32) -> f32 {
+        let scale_factor = Caribou::scale_factor();
+        (value * scale_factor).round() / scale_factor
+    }
+
+    pub fn snap_pair_to_pixel(pair: ScalarPair) -> ScalarPair {
+        ScalarPair::new(Caribou::snap_to_pixel(pair.x), Caribou::snap_to_pixel(pair.y))
     }
 
+    /// Schedule a repaint. Can be called from any thread, and before launch.
     pub fn request_redraw() {
         skia::skia_request_redraw();
     }
 
+    /// Make the font in `bytes` usable as `Font::family`, ahead of system fonts of the same
+    /// name. Returns whether the data could be read as a font.
+    pub fn register_font(bytes: &[u8], family: &str) -> bool {
+        let registered = skia::font::skia_register_font(bytes, family);
+        if registered {
+            Caribou::request_redraw();
+        }
+        registered
+    }
+
+    /// Register the font stored in a file; see `register_font`.
+    pub fn register_font_file<P: AsRef<std::path::Path>>(path: P, family: &str) -> bool {
+        std::fs::read(path).map_or(false, |bytes| Caribou::register_font(&bytes, family))
+    }
+
+    /// Horizontal advance of a line of text drawn in the given font.
+    pub fn measure_text(text: &str, font: &Font) -> f32 {
+        skia::skia_measure_text(text, font)
+    }
+
+    /// Area covered by a text op's glyphs, in the op's coordinates.
+    pub fn text_bounds(text: &str, font: &Font, alignment: &TextAlignment,
+                       layout: &TextLayout) -> Option<Region> {
+        skia::skia_text_bounds(text, font, alignment, layout)
+    }
+
+    pub fn rich_text_bounds(runs: &[TextRun], alignment: &TextAlignment,
+                            layout: &TextLayout) -> Option<Region> {
+        skia::skia_rich_text_bounds(runs, alignment, layout)
+    }
+
+    pub fn path_contains(path: &Path, point: ScalarPair) -> bool {
+        skia::skia_path_contains(path, point)
+    }
+
+    pub fn path_stroke_contains(path: &Path, point: ScalarPair, brush: &Brush) -> bool {
+        skia::skia_path_stroke_contains(path, point, brush)
+    }
+
+    /// Record a batch with the backend so that it can be replayed cheaply.
+    pub fn record(batch: Batch) -> Recording {
+        skia::skia_record_batch(batch)
+    }
+
+    /// An image of the window as currently laid out, in physical pixels. The frame is drawn
+    /// again rather than read back, as the GPU buffer is undefined once presented.
+    pub fn capture_frame() -> Pict {
+        let layers = vec![
+            Caribou::root_component().draw().consolidate(),
+            Caribou::overlay().draw().consolidate(),
+        ];
+        let size = *Caribou::instance().window.size.get();
+        let background = Caribou::theme().material("window.background");
+        skia::skia_capture_frame(layers, background, size, Caribou::scale_factor())
+    }
+
+    /// Encode a pict as PNG, e.g. one from `capture_frame`.
+    pub fn encode_png(pict: &Pict) -> Option<Vec<u8>> {
+        skia::skia_encode_png(pict)
+    }
+
+    /// Resolve the layout of the whole tree again, e.g. after the scale factor changed.
+    pub fn relayout() {
+        Transaction::run(|| {
+            widgets::relayout(&Caribou::root_component());
+            widgets::relayout(&Caribou::overlay());
+        });
+    }
+
+    /// Let the whole tree know that the scale factor changed, e.g. so that cached text
+    /// metrics are measured again.
+    pub fn notify_dpi_changed(scale_factor: f32) {
+        widgets::dpi_changed(&Caribou::root_component(), scale_factor);
+        widgets::dpi_changed(&Caribou::overlay(), scale_factor);
+    }
+
+    pub fn theme() -> Rc<Theme> {
+        Caribou::instance().theme.get_cloned()
+    }
+
+    /// Replace the theme and restyle every widget, so that they are drawn with it next frame.
+    pub fn set_theme(theme: Theme) {
+        Caribou::instance().theme.set(Rc::new(theme));
+        widgets::restyle(&Caribou::root_component());
+        widgets::restyle(&Caribou::overlay());
+    }
+
+    /// Switch to the built-in theme of a color scheme. Unless `Window::follow_color_scheme` is
+    /// turned off, the next change of the platform's preference switches again.
+    pub fn set_color_scheme(scheme: ColorScheme) {
+        Caribou::set_theme(Theme::for_scheme(scheme));
+    }
+
     pub fn register_auto_tab_order(rc: &Widget) {
         INSTANCE.with(|instance| {
             instance.auto_tab_order.borrow_mut().push(Rc::downgrade(rc));
         });
     }
 
+    /// Move the focus to a widget, asking the current one to give it up first.
+    pub fn request_focus(widget: &Widget) -> bool {
+        let instance = Caribou::instance();
+        let current = instance.focused_component.get().upgrade();
+        if let Some(current) = current {
+            if Rc::ptr_eq(&current, widget) {
+                return true;
+            }
+            if current.on_lose_focus.any_false() {
+                return false;
+            }
+        }
+        if widget.is_interactive() && widget.on_gain_focus.none_false() {
+            instance.focused_component.set(Rc::downgrade(widget));
+            true
+        } else {
+            instance.focused_component.reset();
+            false
+        }
+    }
+
     pub fn circulate_focus() -> bool {
         INSTANCE.with(|ins| {
             // Retain only valid components
@@ -111,7 +367,7 @@ impl Caribou {
             loop {
                 let next = tab_order[next_index].upgrade().unwrap();
                 // Ask the next component to take focus
-                if next.on_gain_focus.none_false() {
+                if next.is_interactive() && next.on_gain_focus.none_false() {
                     println!("Focus on #{}", next_index);
                     *cur_ref = tab_order[next_index].clone();
                     return true;
@@ -131,8 +387,29 @@ pub struct Instance {
     pub manual_tab_order: RefCell<Vec<WidgetRef>>,
     pub auto_tab_order: RefCell<Vec<WidgetRef>>,
     pub focused_component: Property<WidgetRef>,
-    pub on_key_down: SingleArgEvent<KeyEvent>,
-    pub on_key_up: SingleArgEvent<KeyEvent>,
+    // Application-level handlers of keys nobody else handled
+    pub on_key_down: SingleArgEvent<KeyEvent, bool>,
+    pub on_key_up: SingleArgEvent<KeyEvent, bool>,
+    /// Broadcast before each frame is drawn with the time since the previous one. Returning
+    /// true asks for another frame, keeping the clock running like an animation does.
+    pub on_frame: SingleArgEvent<Duration, bool>,
+    /// Broadcast with the `tag` of a notification the user clicked, where the desktop
+    /// reports clicks
+    pub on_notification_clicked: SingleArgEvent<String>,
+    /// Read by the default widget styles; replace it with `Caribou::set_theme`
+    pub theme: Property<Rc<Theme>>,
+    /// Replace it with `Caribou::set_style_sheet`
+    pub style_sheet: Property<Rc<StyleSheet>>,
+    pub window: Window,
+    pointer: Cell<IntPair>,
+    pressed_keys: RefCell<Vec<Key>>,
+    captured: RefCell<Option<WidgetRef>>,
+    // The widgets under the pointer as of its last move, outermost first
+    hovered: RefCell<Vec<WidgetRef>>,
+    // Time and place of the last primary press, and how many presses led up to it
+    last_click: Cell<Option<(Instant, IntPair, u32)>>,
+    // The widget accepting the files currently dragged over the window
+    file_target: RefCell<Option<WidgetRef>>,
 }
 
 impl Instance {
@@ -145,6 +422,17 @@ impl Instance {
             focused_component: dummy.init_default_property(),
             on_key_down: dummy.init_event(),
             on_key_up: dummy.init_event(),
+            on_frame: dummy.init_event(),
+            on_notification_clicked: dummy.init_event(),
+            theme: dummy.init_property(Rc::new(Theme::light())),
+            style_sheet: dummy.init_property(Rc::new(StyleSheet::new())),
+            window: Window::new(),
+            pointer: Cell::new(IntPair::default()),
+            pressed_keys: RefCell::new(vec![]),
+            captured: RefCell::new(None),
+            hovered: RefCell::new(vec![]),
+            last_click: Cell::new(None),
+            file_target: RefCell::new(None),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use log::warn;
+use crate::caribou::Caribou;
+
+/// A message shown by the desktop outside the window.
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    /// Icon name from the desktop theme, or path of an image file
+    pub icon: Option<String>,
+    /// Passed to `Instance::on_notification_clicked` to tell which notification was clicked
+    pub tag: String,
+}
+
+impl Notification {
+    pub fn new(title: &str, body: &str) -> Notification {
+        Notification {
+            title: title.to_string(),
+            body: body.to_string(),
+            icon: None,
+            tag: String::new(),
+        }
+    }
+
+    pub fn icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_string());
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = tag.to_string();
+        self
+    }
+}
+
+impl Caribou {
+    /// Show a notification, returning whether the desktop accepted it.
+    pub fn notify(notification: Notification) -> bool {
+        let mut native = notify_rust::Notification::new();
+        native.summary(&notification.title).body(&notification.body);
+        if let Some(icon) = &notification.icon {
+            native.icon(icon);
+        }
+        // Only desktops following the freedesktop.org specification report clicks
+        #[cfg(all(unix, not(target_os = "macos")))]
+        native.action("default", "Open");
+        let shown = native.show();
+        if let Err(err) = &shown {
+            warn!("Cannot show notification: {}", err);
+            return false;
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if let Ok(handle) = shown {
+            let tag = notification.tag;
+            // Waiting for the action blocks until the notification is closed
+            std::thread::spawn(move || handle.wait_for_action(|action| {
+                if action == "default" {
+                    crate::caribou::store::Store::post_task(Box::new(move || {
+                        Caribou::instance().on_notification_clicked.broadcast(tag);
+                    }));
+                }
+            }));
+        }
+        true
+    }
+}
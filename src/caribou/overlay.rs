@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::caribou::Caribou;
+use crate::caribou::math::ScalarPair;
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetRef, WidgetRefer, WidgetRefVec};
+use crate::caribou::widgets::{hit_test, window_position, z_ordered, Layout};
+
+thread_local! {
+    static OVERLAY: Widget = {
+        let overlay = Layout::create();
+        // The visual of a drag goes above the popups
+        overlay.on_draw.subscribe(Box::new(|_| Caribou::drag_ghost()));
+        overlay
+    };
+    static LIGHT_DISMISS: RefCell<Vec<WidgetRef>> = RefCell::new(Vec::new());
+}
+
+/// Where a popup is placed when opened.
+#[derive(Clone)]
+pub enum PopupAnchor {
+    /// A point in window coordinates
+    Point(ScalarPair),
+    /// An offset from the top-left corner of a widget
+    Widget(WidgetRef, ScalarPair),
+}
+
+impl PopupAnchor {
+    pub fn resolve(&self) -> ScalarPair {
+        match self {
+            PopupAnchor::Point(point) => *point,
+            PopupAnchor::Widget(widget, offset) => match widget.acquire() {
+                Some(widget) => window_position(&widget) + *offset,
+                None => *offset,
+            },
+        }
+    }
+}
+
+impl Caribou {
+    /// The layer drawn above the root component, hosting popups.
+    pub fn overlay() -> Widget {
+        OVERLAY.with(|overlay| overlay.clone())
+    }
+
+    /// Open a popup on the overlay. A light-dismiss popup closes when clicking outside of it.
+    pub fn open_popup(popup: Widget, anchor: PopupAnchor, light_dismiss: bool) {
+        popup.position.set(anchor.resolve());
+        if light_dismiss {
+            LIGHT_DISMISS.with(|list| list.borrow_mut().push(popup.refer()));
+        }
+        Caribou::overlay().children.push(popup);
+        Caribou::request_redraw();
+    }
+
+    pub fn close_popup(popup: &Widget) {
+        let overlay = Caribou::overlay();
+        let index = overlay.children.get().iter()
+            .position(|child| Rc::ptr_eq(child, popup));
+        if let Some(index) = index {
+            overlay.children.remove(index);
+            Caribou::request_redraw();
+        }
+        LIGHT_DISMISS.with(|list| list.borrow_mut().clean());
+    }
+
+    /// The topmost popup under a point in window coordinates.
+    pub fn popup_at(pos: ScalarPair) -> Option<Widget> {
+        z_ordered(&Caribou::overlay().children.get()).into_iter().rev()
+            .find(|popup| popup.is_interactive() && hit_test(popup, pos).is_some())
+    }
+
+    /// Close every light-dismiss popup that does not contain the point.
+    pub(crate) fn light_dismiss_outside(pos: ScalarPair) {
+        let hit = Caribou::popup_at(pos);
+        let dismissed: Vec<Widget> = LIGHT_DISMISS.with(|list| {
+            let mut list = list.borrow_mut();
+            list.clean();
+            list.acquire()
+                .filter(|popup| !hit.as_ref().map_or(false, |hit| Rc::ptr_eq(hit, popup)))
+                .collect()
+        });
+        for popup in dismissed {
+            Caribou::close_popup(&popup);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::caribou::batch::{Batch, BatchOp, Brush, Fit, Font, Material, Path, PathOp, Pict, Sampling, Shadow, TextAlignment, TextLayout, Transform};
+use crate::caribou::math::{Region, ScalarPair};
+
+/// Fluent construction of a batch, e.g.
+/// `Painter::new().rect(region).fill(color).stroke(border, 1.0).text("OK", font, brush).finish()`.
+///
+/// Shape methods set the current path, which `fill`, `stroke`, `paint` and `shadow` then draw
+/// as many times as needed.
+pub struct Painter {
+    batch: Batch,
+    transform: Transform,
+    path: Path,
+}
+
+impl Painter {
+    pub fn new() -> Painter {
+        Painter::on(Batch::new())
+    }
+
+    /// Paint into an existing batch.
+    pub fn on(batch: Batch) -> Painter {
+        Painter {
+            batch,
+            transform: Transform::default(),
+            path: Path::new(),
+        }
+    }
+
+    pub fn finish(self) -> Batch {
+        self.batch
+    }
+
+    /// Use `transform` for everything painted afterwards.
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn path(mut self, path: Path) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn rect(self, region: Region) -> Self {
+        self.path(Path::from_vec(vec![PathOp::Rect(region.origin, region.size)]))
+    }
+
+    pub fn round_rect(self, region: Region, radius: f32) -> Self {
+        self.path(Path::from_vec(vec![PathOp::RoundRect(region.origin, region.size, radius)]))
+    }
+
+    pub fn oval(self, region: Region) -> Self {
+        self.path(Path::from_vec(vec![PathOp::Oval(region.origin, region.size)]))
+    }
+
+    pub fn line(self, begin: ScalarPair, end: ScalarPair) -> Self {
+        self.path(Path::from_vec(vec![PathOp::Line(begin, end)]))
+    }
+
+    pub fn polygon(self, vertices: Vec<ScalarPair>, close: bool) -> Self {
+        self.path(Path::from_vec(vec![PathOp::Polygon(vertices, close)]))
+    }
+
+    /// Draw the current path with `brush`.
+    pub fn paint(self, brush: Brush) -> Self {
+        if !brush.is_transparent() {
+            self.batch.add_op(BatchOp::Path {
+                transform: self.transform,
+                path: self.path.clone(),
+                brush,
+            });
+        }
+        self
+    }
+
+    pub fn fill(self, mat: Material) -> Self {
+        self.paint(Brush::solid_fill(mat))
+    }
+
+    pub fn stroke(self, mat: Material, width: f32) -> Self {
+        self.paint(Brush::solid_stroke(mat, width))
+    }
+
+    /// Cast a shadow of the current path.
+    pub fn shadow(self, shadow: Shadow) -> Self {
+        self.batch.add_op(shadow.cast(self.transform, self.path.clone()));
+        self
+    }
+
+    /// Draw a single line of text positioned at the origin of the current transform.
+    pub fn text(self, text: &str, font: Font, brush: Brush) -> Self {
+        self.text_with(text, font, brush, TextAlignment::default(), TextLayout::default())
+    }
+
+    pub fn text_with(self, text: &str, font: Font, brush: Brush,
+                     alignment: TextAlignment, layout: TextLayout) -> Self {
+        self.batch.add_op(BatchOp::Text {
+            transform: self.transform,
+            text: text.to_string(),
+            font,
+            alignment,
+            layout,
+            decoration: Default::default(),
+            brush,
+        });
+        self
+    }
+
+    pub fn pict(self, pict: Pict, dest: Region) -> Self {
+        self.pict_with(pict, dest, Fit::default(), Sampling::default())
+    }
+
+    pub fn pict_with(self, pict: Pict, dest: Region, fit: Fit, sampling: Sampling) -> Self {
+        self.batch.add_op(BatchOp::PictFit {
+            transform: self.transform,
+            pict,
+            dest,
+            fit,
+            sampling,
+        });
+        self
+    }
+
+    /// Erase what was painted so far within the region.
+    pub fn clear(self, region: Region) -> Self {
+        self.batch.add_op(BatchOp::Clear { transform: self.transform, region });
+        self
+    }
+
+    pub fn op(self, op: BatchOp) -> Self {
+        self.batch.add_op(op);
+        self
+    }
+
+    /// Paint a nested batch, moved by `offset`.
+    pub fn with_translate<F>(self, offset: ScalarPair, paint: F) -> Self
+        where F: FnOnce(Painter) -> Painter {
+        self.with_transform(Transform { translate: offset, ..Transform::default() }, paint)
+    }
+
+    /// Paint a nested batch drawn with `transform` on top of the current one.
+    pub fn with_transform<F>(self, transform: Transform, paint: F) -> Self
+        where F: FnOnce(Painter) -> Painter {
+        let nested = paint(Painter::new()).finish();
+        let outer = Batch::new();
+        outer.add_op(BatchOp::Batch { transform, batch: nested, filter: None });
+        self.batch.add_op(BatchOp::Batch { transform: self.transform, batch: outer, filter: None });
+        self
+    }
+}
@@ -1,8 +1,13 @@
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::any::Any;
+use std::collections::HashMap;
 use std::ops::{Add, AddAssign, Deref};
 use std::rc::{Rc, Weak};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::caribou::event::SubscriptionGuard;
 use crate::caribou::math::{IntPair, ScalarPair};
+use crate::caribou::store::Store;
 use crate::caribou::widget::{Widget, WidgetRef};
 use crate::WidgetInner;
 
@@ -14,13 +19,13 @@ pub type VecProperty<T> = Property<Vec<T>>;
 
 pub type DynamicProperty = OptionalProperty<Box<dyn Any>>;
 
-impl<T> Property<T> where T: Add<Output=T>, T: Copy {
+impl<T> Property<T> where T: Add<Output=T>, T: Copy + 'static {
     pub fn offset_by(&self, offset: T) {
         self.set(self.get().add(offset));
     }
 }
 
-impl<T> Property<T> where T: Default {
+impl<T> Property<T> where T: Default + 'static {
     pub fn reset(&self) {
         self.set(T::default());
     }
@@ -40,35 +45,54 @@ impl BoolProperty {
     }
 }
 
-impl<T> OptionalProperty<T> {
+impl<T> Property<T> where T: Clone + 'static {
+    /// Keep `target` set to the value of this property, starting now.
+    pub fn bind_to(&self, target: &Property<T>) -> Listener<T> {
+        target.set(self.get_cloned());
+        self.forward_to(target, Rc::new(Cell::new(false)))
+    }
+
+    /// Keep both properties equal whichever of them is set, `other` taking the value of this
+    /// one first. Unlisten both returned listeners to unbind.
+    pub fn bind_bidirectional(&self, other: &Property<T>) -> (Listener<T>, Listener<T>) {
+        other.set(self.get_cloned());
+        // Shared so that forwarding a change does not bounce back and forth
+        let forwarding = Rc::new(Cell::new(false));
+        (self.forward_to(other, forwarding.clone()), other.forward_to(self, forwarding))
+    }
+
+    fn forward_to(&self, target: &Property<T>, forwarding: Rc<Cell<bool>>) -> Listener<T> {
+        let target = target.clone();
+        self.listen(Box::new(move |value| {
+            if !forwarding.replace(true) {
+                target.set(value.clone());
+                forwarding.set(false);
+            }
+        }))
+    }
+}
+
+impl<T: 'static> OptionalProperty<T> {
     pub fn is_some(&self) -> bool {
         self.value.borrow().is_some()
     }
 
     pub fn put(&self, value: T) {
-        self.value.replace(Some(value));
-        for listener in self.listeners.borrow().iter() {
-            listener.invoke(&self.value.borrow());
-        }
+        self.set(Some(value));
     }
 
     pub fn take(&self) -> Option<T> {
         let value = self.value.borrow_mut().take();
-        for listener in self.listeners.borrow().iter() {
-            listener.invoke(&self.value.borrow());
-        }
+        self.inform();
         value
     }
 
     pub fn clear(&self) {
-        self.value.replace(None);
-        for listener in self.listeners.borrow().iter() {
-            listener.invoke(&self.value.borrow());
-        }
+        self.set(None);
     }
 }
 
-impl<T> VecProperty<T> {
+impl<T: 'static> VecProperty<T> {
     pub fn push(&self, value: T) {
         self.value.borrow_mut().push(value);
         self.inform();
@@ -128,7 +152,20 @@ impl<T> PropertyInit<T> for Widget {
     }
 }
 
-type ListenerFunc<T> = Box<dyn Fn(&T)>;
+/// The values of a property around a change.
+pub struct Change<'a, T> {
+    pub old: &'a T,
+    pub new: &'a T,
+}
+
+impl<'a, T> Change<'a, T> where T: PartialEq {
+    /// Whether the value is actually different, as setting an equal value still notifies.
+    pub fn is_changed(&self) -> bool {
+        self.old != self.new
+    }
+}
+
+type ListenerFunc<T> = Box<dyn Fn(Change<T>)>;
 
 pub struct Listener<T> {
     func: Rc<ListenerFunc<T>>,
@@ -149,8 +186,8 @@ impl<T> Listener<T> {
         }
     }
 
-    pub fn invoke(&self, value: &T) {
-        (self.func)(value);
+    pub fn invoke(&self, old: &T, new: &T) {
+        (self.func)(Change { old, new });
     }
 }
 
@@ -160,10 +197,69 @@ impl<T> PartialEq for Listener<T> {
     }
 }
 
+/// Takes over a `set`, e.g. to animate towards the value instead of writing it.
+type TransitionFunc<T> = Rc<dyn Fn(&Property<T>, T)>;
+
+/// What `set` does with a value before it is written.
+struct SetHooks<T> {
+    coercer: Option<Rc<dyn Fn(T) -> T>>,
+    validator: Option<Rc<dyn Fn(&T) -> bool>>,
+    validation_failed: Option<Rc<dyn Fn(&T)>>,
+    transition: Option<TransitionFunc<T>>,
+    // Nesting of `begin_update`, and the notification owed once it ends: the value from
+    // before the first deferred change, or None if the value was changed in place
+    deferred: u32,
+    owed: Option<Option<T>>,
+}
+
+thread_local! {
+    static TRANSACTION_DEPTH: Cell<u32> = Cell::new(0);
+    // One notification per property changed during the transaction, by property identity
+    static PENDING: RefCell<Vec<(usize, Box<dyn FnOnce()>)>> = RefCell::new(Vec::new());
+}
+
+/// Defers the notifications of all properties while running a closure, e.g. while laying
+/// out, so that each changed property notifies its listeners only once at the end. The
+/// listeners then see the value from before the transaction as old and the final one as new.
+pub struct Transaction;
+
+impl Transaction {
+    pub fn run<R>(f: impl FnOnce() -> R) -> R {
+        TRANSACTION_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        let guard = TransactionGuard;
+        let result = f();
+        drop(guard);
+        if !Transaction::is_running() {
+            for (_, notify) in PENDING.with(|pending| pending.take()) {
+                notify();
+            }
+        }
+        result
+    }
+
+    pub fn is_running() -> bool {
+        TRANSACTION_DEPTH.with(|depth| depth.get()) > 0
+    }
+}
+
+/// Leaves the transaction when dropped, also if its closure panics. The notifications owed
+/// are then dropped rather than sent while unwinding.
+struct TransactionGuard;
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        TRANSACTION_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        if std::thread::panicking() && !Transaction::is_running() {
+            PENDING.with(|pending| pending.take());
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Property<T> {
     value: Rc<RefCell<T>>,
     listeners: Rc<RefCell<Vec<Listener<T>>>>,
+    hooks: Rc<RefCell<SetHooks<T>>>,
     back_ref: WidgetRef,
 }
 
@@ -172,10 +268,23 @@ impl<T> Property<T> {
         Property {
             value: RefCell::new(initial).into(),
             listeners: RefCell::new(Vec::new()).into(),
+            hooks: RefCell::new(SetHooks {
+                coercer: None,
+                validator: None,
+                validation_failed: None,
+                transition: None,
+                deferred: 0,
+                owed: None,
+            }).into(),
             back_ref,
         }
     }
 
+    /// Identifies the property; clones share it.
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.value) as *const () as usize
+    }
+
     pub fn get(&self) -> Ref<T> {
         self.value.borrow()
     }
@@ -192,20 +301,169 @@ impl<T> Property<T> {
         self.value.borrow_mut()
     }
 
-    pub fn set(&self, value: T) {
-        for listener in self.listeners.borrow().iter() {
-            listener.invoke(&value);
+    /// Replace the value once coerced, unless the validator rejects it. The value is handed
+    /// to the transition of the property instead if it has one.
+    pub fn set(&self, value: T) where T: 'static {
+        let (coercer, validator, validation_failed, transition) = {
+            let hooks = self.hooks.borrow();
+            (hooks.coercer.clone(), hooks.validator.clone(),
+             hooks.validation_failed.clone(), hooks.transition.clone())
+        };
+        let value = match coercer {
+            Some(coercer) => coercer(value),
+            None => value,
+        };
+        if let Some(validator) = validator {
+            if !validator(&value) {
+                if let Some(validation_failed) = validation_failed {
+                    validation_failed(&value);
+                }
+                return;
+            }
+        }
+        match transition {
+            Some(transition) => transition(self, value),
+            None => self.set_immediate(value),
+        }
+    }
+
+    /// Replace the value right away, bypassing any transition.
+    pub fn set_immediate(&self, value: T) where T: 'static {
+        let old = std::mem::replace(&mut *self.value.borrow_mut(), value);
+        if self.is_update_deferred() {
+            self.owe_update(Some(old));
+        } else if Transaction::is_running() {
+            self.owe_notification(Some(old));
+        } else {
+            self.notify(&old);
+        }
+    }
+
+    /// Tell the listeners that the value replaced `old`.
+    fn notify(&self, old: &T) where T: 'static {
+        // Listeners see the new value through `get` and may (un)listen while notified
+        let listeners = self.listeners.borrow().clone();
+        for listener in listeners.iter() {
+            listener.invoke(old, &self.value.borrow());
+        }
+        self.discard_recordings();
+    }
+
+    /// Like `set`, but leaves the value alone and notifies nobody if it is unchanged. Returns
+    /// whether the value was set, e.g. to decide on a redraw.
+    pub fn set_distinct(&self, value: T) -> bool where T: PartialEq + 'static {
+        if *self.value.borrow() == value {
+            return false;
         }
-        *self.value.borrow_mut() = value;
+        self.set(value);
+        true
     }
 
-    pub fn inform(&self) {
-        for listener in self.listeners.borrow().iter() {
-            listener.invoke(&self.value.borrow());
+    /// Notify the listeners after the value was changed in place, the old value being gone.
+    pub fn inform(&self) where T: 'static {
+        if self.is_update_deferred() {
+            self.owe_update(None);
+            return;
+        }
+        if Transaction::is_running() {
+            self.owe_notification(None);
+            return;
         }
+        let listeners = self.listeners.borrow().clone();
+        for listener in listeners.iter() {
+            let value = self.value.borrow();
+            listener.invoke(&value, &value);
+        }
+        self.discard_recordings();
+    }
+
+    /// The owning widget may be drawn differently now, so a recording of it is stale.
+    fn discard_recordings(&self) {
+        if let Some(owner) = self.back_ref.upgrade() {
+            owner.discard_recordings();
+        }
+    }
+
+    /// Hold back notifications until the matching `end_update`, e.g. while setting the
+    /// property repeatedly.
+    pub fn begin_update(&self) {
+        self.hooks.borrow_mut().deferred += 1;
+    }
+
+    /// Notify the listeners once if the value was set since `begin_update`, with the value
+    /// from before `begin_update` as old.
+    pub fn end_update(&self) where T: 'static {
+        let owed = {
+            let mut hooks = self.hooks.borrow_mut();
+            hooks.deferred = hooks.deferred.saturating_sub(1);
+            if hooks.deferred == 0 { hooks.owed.take() } else { None }
+        };
+        match owed {
+            Some(Some(old)) if Transaction::is_running() => self.owe_notification(Some(old)),
+            Some(Some(old)) => self.notify(&old),
+            Some(None) => self.inform(),
+            None => {}
+        }
+    }
+
+    /// Whether notifications wait for `end_update`.
+    fn is_update_deferred(&self) -> bool {
+        self.hooks.borrow().deferred > 0
+    }
+
+    /// Notify the listeners once `end_update` ends the deferral, keeping the value from before
+    /// the first deferred change.
+    fn owe_update(&self, old: Option<T>) {
+        let mut hooks = self.hooks.borrow_mut();
+        if hooks.owed.is_none() {
+            hooks.owed = Some(old);
+        }
+    }
+
+    /// Notify the listeners at the end of the running transaction, with the value from before
+    /// its first change if it was not changed in place.
+    fn owe_notification(&self, old: Option<T>) where T: 'static {
+        let id = self.id();
+        PENDING.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            if pending.iter().any(|(pending_id, _)| *pending_id == id) {
+                return;
+            }
+            let property = self.clone();
+            pending.push((id, Box::new(move || match old {
+                Some(old) => property.notify(&old),
+                None => property.inform(),
+            })));
+        });
+    }
+
+    pub fn set_transition(&self, transition: Option<TransitionFunc<T>>) {
+        self.hooks.borrow_mut().transition = transition;
+    }
+
+    /// Adjust every value passed to `set`, e.g. clamp it into a range.
+    pub fn set_coercer(&self, coercer: Option<Box<dyn Fn(T) -> T>>) {
+        self.hooks.borrow_mut().coercer = coercer.map(Rc::from);
+    }
+
+    /// Reject values passed to `set` for which the validator returns false, keeping the
+    /// current one.
+    pub fn set_validator(&self, validator: Option<Box<dyn Fn(&T) -> bool>>) {
+        self.hooks.borrow_mut().validator = validator.map(Rc::from);
+    }
+
+    /// Called with each value the validator rejects.
+    pub fn on_validation_failed(&self, callback: Option<Box<dyn Fn(&T)>>) {
+        self.hooks.borrow_mut().validation_failed = callback.map(Rc::from);
     }
 
+    /// Listen to the new values only.
     pub fn listen(&self, listener: Box<dyn Fn(&T)>) -> Listener<T> {
+        self.listen_change(Box::new(move |change| listener(change.new)))
+    }
+
+    /// Listen to both the value being replaced and the one replacing it.
+    pub fn listen_change(&self, listener: Box<dyn Fn(Change<T>)>) -> Listener<T> {
         let listener = Listener::new(listener);
         self.listeners.borrow_mut().push(listener.clone());
         listener
@@ -214,4 +472,219 @@ impl<T> Property<T> {
     pub fn unlisten(&self, listener: &Listener<T>) {
         self.listeners.borrow_mut().retain(|l| l != listener);
     }
+
+    /// Listen until the returned guard is dropped.
+    pub fn listen_guarded(&self, listener: Box<dyn Fn(&T)>) -> SubscriptionGuard where T: 'static {
+        let listener = self.listen(listener);
+        let listeners = Rc::downgrade(&self.listeners);
+        SubscriptionGuard::new(move || {
+            if let Some(listeners) = listeners.upgrade() {
+                listeners.borrow_mut().retain(|l| l != &listener);
+            }
+        })
+    }
+
+    /// Listen for as long as `owner` lives, e.g. to a property of another widget or of the
+    /// `Store` that outlives it.
+    pub fn listen_for(&self, owner: &Widget, listener: Box<dyn Fn(&T)>) where T: 'static {
+        owner.own(self.listen_guarded(listener));
+    }
+}
+
+/// Serialized as the bare value.
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for Property<T> where T: serde::Serialize {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+/// Deserialized properties belong to no widget; their values are meant to be `set` on the
+/// properties of live widgets.
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for Property<T> where T: serde::Deserialize<'de> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(|value| Property::new(value, Weak::new()))
+    }
+}
+
+thread_local! {
+    // Listeners of sync properties, which live on the UI thread: property id to listener ids
+    // and the listeners, each a `Box<dyn Fn(&T)>` of the property's value type
+    static SYNC_LISTENERS: RefCell<HashMap<usize, Vec<(usize, Rc<dyn Any>)>>> = RefCell::new(HashMap::new());
+    static NEXT_SYNC_LISTENER: Cell<usize> = Cell::new(0);
+}
+
+/// A property that can be set from any thread, e.g. the progress of a download running on
+/// the Dispatcher. Listeners are called on the UI thread before the next frame, once for any
+/// number of changes in between, with the latest value.
+pub struct SyncProperty<T> {
+    value: Arc<RwLock<T>>,
+    queued: Arc<AtomicBool>,
+}
+
+impl<T> Clone for SyncProperty<T> {
+    fn clone(&self) -> Self {
+        SyncProperty { value: self.value.clone(), queued: self.queued.clone() }
+    }
+}
+
+/// Returned by `SyncProperty::listen` to stop listening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncListener(usize);
+
+impl<T> SyncProperty<T> where T: Clone + Send + Sync + 'static {
+    pub fn new(initial: T) -> SyncProperty<T> {
+        SyncProperty {
+            value: Arc::new(RwLock::new(initial)),
+            queued: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.value.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.value.write().unwrap() = value;
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            let property = self.clone();
+            Store::post_task(Box::new(move || {
+                property.queued.store(false, Ordering::SeqCst);
+                property.notify();
+            }));
+        }
+    }
+
+    /// Must be called on the UI thread.
+    pub fn listen(&self, listener: Box<dyn Fn(&T)>) -> SyncListener {
+        let id = NEXT_SYNC_LISTENER.with(|next| next.replace(next.get() + 1));
+        let listener: Rc<dyn Any> = Rc::new(listener);
+        SYNC_LISTENERS.with(|listeners| listeners.borrow_mut()
+            .entry(self.id())
+            .or_default()
+            .push((id, listener)));
+        SyncListener(id)
+    }
+
+    pub fn unlisten(&self, listener: SyncListener) {
+        SYNC_LISTENERS.with(|listeners| {
+            let mut listeners = listeners.borrow_mut();
+            if let Some(entries) = listeners.get_mut(&self.id()) {
+                entries.retain(|(id, _)| *id != listener.0);
+                if entries.is_empty() {
+                    listeners.remove(&self.id());
+                }
+            }
+        });
+    }
+
+    /// Keep a UI property, such as the value of a progress bar, set to this one.
+    pub fn bind_to(&self, target: &Property<T>) -> SyncListener {
+        target.set(self.get());
+        let target = target.clone();
+        self.listen(Box::new(move |value| target.set(value.clone())))
+    }
+
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.value) as *const () as usize
+    }
+
+    fn notify(&self) {
+        let listeners: Vec<Rc<dyn Any>> = SYNC_LISTENERS.with(|listeners| listeners.borrow()
+            .get(&self.id())
+            .map(|entries| entries.iter().map(|(_, listener)| listener.clone()).collect())
+            .unwrap_or_default());
+        let value = self.get();
+        for listener in listeners {
+            if let Some(listener) = listener.downcast_ref::<Box<dyn Fn(&T)>>() {
+                listener(&value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::{Rc, Weak};
+    use super::{Property, Transaction};
+
+    fn record_changes(property: &Property<i32>) -> Rc<RefCell<Vec<(i32, i32)>>> {
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let record = changes.clone();
+        property.listen_change(Box::new(move |change| {
+            record.borrow_mut().push((*change.old, *change.new));
+        }));
+        changes
+    }
+
+    #[test]
+    fn transaction_notifies_once_at_the_end() {
+        let property = Property::new(1, Weak::new());
+        let changes = record_changes(&property);
+        Transaction::run(|| {
+            property.set(2);
+            property.set(3);
+            assert!(changes.borrow().is_empty());
+        });
+        assert_eq!(*changes.borrow(), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn deferred_update_notifies_once_at_the_end() {
+        let property = Property::new(1, Weak::new());
+        let changes = record_changes(&property);
+        property.begin_update();
+        property.set(2);
+        property.set(3);
+        assert!(changes.borrow().is_empty());
+        property.end_update();
+        assert_eq!(*changes.borrow(), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn nested_transactions_flush_with_the_outermost() {
+        let property = Property::new(1, Weak::new());
+        let changes = record_changes(&property);
+        Transaction::run(|| {
+            Transaction::run(|| property.set(2));
+            assert!(changes.borrow().is_empty());
+            property.set(4);
+        });
+        assert_eq!(*changes.borrow(), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn panicking_transaction_is_left() {
+        let property = Property::new(1, Weak::new());
+        let changes = record_changes(&property);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            Transaction::run(|| {
+                property.set(2);
+                panic!("inside the transaction");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(!Transaction::is_running());
+        assert!(changes.borrow().is_empty());
+        property.set(3);
+        assert_eq!(*changes.borrow(), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn bidirectional_binding_does_not_bounce() {
+        let first = Property::new(1, Weak::new());
+        let second = Property::new(0, Weak::new());
+        let notified = Rc::new(Cell::new(0));
+        let counter = notified.clone();
+        second.listen(Box::new(move |_| counter.set(counter.get() + 1)));
+        first.bind_bidirectional(&second);
+        assert_eq!(*second.get(), 1);
+        first.set(5);
+        assert_eq!(*second.get(), 5);
+        second.set(7);
+        assert_eq!(*first.get(), 7);
+        assert_eq!(notified.get(), 3);
+    }
 }
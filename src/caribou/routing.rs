@@ -0,0 +1,440 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::caribou::Caribou;
+use crate::caribou::event::ZeroArgEvent;
+use crate::caribou::input::{CursorIcon, KeyEvent};
+use crate::caribou::math::{IntPair, ScalarPair};
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetInner, WidgetRef, WidgetRefer};
+use crate::caribou::widgets::{cursor_at, mouse_position, widgets_at, window_position};
+
+/// Longest pause between the presses of a double click
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(500);
+/// Farthest the cursor may move between the presses of a double click, in logical units
+const DOUBLE_CLICK_DISTANCE: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerButton {
+    Primary,
+    Secondary,
+    Tertiary,
+}
+
+/// The input a routed event carries.
+#[derive(Debug, Clone)]
+pub enum RoutedInput {
+    ButtonDown(PointerButton),
+    ButtonUp(PointerButton),
+    DoubleClick,
+    /// The pointer moved while staying on the target
+    PointerMove,
+    /// The pointer got onto the target, handed to it alone
+    PointerEnter,
+    /// The pointer left the target, handed to it alone
+    PointerLeave,
+    Scroll(ScalarPair),
+    KeyDown(KeyEvent),
+    KeyUp(KeyEvent),
+    Char(char),
+}
+
+/// Where a routed event is on its way: going down from the root towards the target, at the
+/// target, or going back up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutePhase {
+    Capture,
+    Target,
+    Bubble,
+}
+
+/// An input event travelling along the widget tree, handed to `on_routed` of the target's
+/// ancestors root first, then to the target, then to the ancestors again target first.
+pub struct RoutedEvent {
+    pub input: RoutedInput,
+    /// The innermost widget under the pointer, or the focused one for keyboard input
+    pub target: WidgetRef,
+    /// Pointer position in window coordinates
+    pub position: ScalarPair,
+    phase: Cell<RoutePhase>,
+    current: RefCell<WidgetRef>,
+}
+
+impl RoutedEvent {
+    pub fn phase(&self) -> RoutePhase {
+        self.phase.get()
+    }
+
+    /// The widget the event is being handed to.
+    pub fn current(&self) -> Option<Widget> {
+        self.current.borrow().acquire()
+    }
+
+    pub fn is_at_target(&self) -> bool {
+        self.phase.get() == RoutePhase::Target
+    }
+
+    /// Pointer position in the coordinates of the widget the event is being handed to.
+    pub fn local_position(&self) -> ScalarPair {
+        match self.current() {
+            Some(current) => self.position - window_position(&current),
+            None => self.position,
+        }
+    }
+}
+
+/// The widget preceded by its ancestors, outermost first.
+fn ancestry(widget: &Widget) -> Vec<Widget> {
+    let mut path = vec![widget.clone()];
+    while let Some(parent) = path.last().unwrap().parent.get_cloned()
+        .and_then(|parent| parent.acquire()) {
+        path.push(parent);
+    }
+    path.reverse();
+    path
+}
+
+impl Caribou {
+    /// The widgets stacked at a point in window coordinates, outermost first, starting from
+    /// the popup there or the root.
+    pub fn widgets_at(pos: ScalarPair) -> Vec<Widget> {
+        let base = Caribou::popup_at(pos).unwrap_or_else(Caribou::root_component);
+        widgets_at(&base, pos - *base.position.get())
+    }
+
+    /// Route an event along a path of widgets, outermost first and ending at the target.
+    /// Returns whether a subscriber of `on_routed` handled it, which stops it there.
+    pub fn route_along(path: &[Widget], input: RoutedInput) -> bool {
+        let target = match path.last() {
+            Some(target) => target,
+            None => return false,
+        };
+        let event = Rc::new(RoutedEvent {
+            input,
+            target: target.refer(),
+            position: Caribou::mouse_position().to_scalar(),
+            phase: Cell::new(RoutePhase::Capture),
+            current: RefCell::new(target.refer()),
+        });
+        let ancestors = &path[..path.len() - 1];
+        let steps = ancestors.iter().map(|widget| (widget, RoutePhase::Capture))
+            .chain(std::iter::once((target, RoutePhase::Target)))
+            .chain(ancestors.iter().rev().map(|widget| (widget, RoutePhase::Bubble)));
+        for (widget, phase) in steps {
+            event.phase.set(phase);
+            event.current.replace(widget.refer());
+            if widget.on_routed.any_true(event.clone()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Route pointer input to the capturing widget, or else to the innermost interactive
+    /// widget under the pointer, through their ancestors.
+    pub fn route_pointer(input: RoutedInput) -> bool {
+        let path = match Caribou::captured_widget() {
+            Some(widget) => ancestry(&widget),
+            None => Caribou::widgets_at(Caribou::mouse_position().to_scalar()),
+        };
+        Caribou::route_along(&path, input)
+    }
+
+    /// Route keyboard input to the focused widget through its ancestors.
+    pub fn route_focused(input: RoutedInput) -> bool {
+        match Caribou::instance().focused_component.get().upgrade()
+            .filter(|widget| widget.is_interactive()) {
+            Some(widget) => Caribou::route_along(&ancestry(&widget), input),
+            None => false,
+        }
+    }
+
+    /// Send all mouse motion and buttons to the widget until `release_mouse`, wherever the
+    /// cursor is, e.g. while dragging a slider thumb. Positions stay relative to the widget.
+    pub fn capture_mouse(widget: &Widget) {
+        Caribou::instance().captured.replace(Some(widget.refer()));
+    }
+
+    pub fn release_mouse() {
+        Caribou::instance().captured.replace(None);
+    }
+
+    pub fn captured_widget() -> Option<Widget> {
+        Caribou::instance().captured.borrow().as_ref().and_then(|widget| widget.acquire())
+    }
+
+    /// Route pointer motion along the widgets under the pointer, or along the capturing widget
+    /// and its ancestors. Unless handled there, it goes to the capturing widget, or else to the
+    /// widgets the pointer stayed on.
+    pub fn route_mouse_move(pos: IntPair) {
+        Caribou::instance().pointer.set(pos);
+        if Caribou::route_drag_move(pos) {
+            return;
+        }
+        let (path, receivers) = match Caribou::captured_widget() {
+            Some(widget) => (ancestry(&widget), vec![widget]),
+            None => {
+                let path = Caribou::widgets_at(pos.to_scalar());
+                let stayed = Caribou::update_hovered(&path);
+                (path, stayed)
+            }
+        };
+        if Caribou::route_along(&path, RoutedInput::PointerMove) {
+            return;
+        }
+        for widget in receivers {
+            widget.on_mouse_move.broadcast(mouse_position(&widget).to_int());
+        }
+    }
+
+    pub fn route_mouse_leave() {
+        Caribou::update_hovered(&[]);
+    }
+
+    /// Make the path the hovered widgets. The widgets no longer on it are left innermost
+    /// first and the new ones entered outermost first. Returns the ones that stayed.
+    fn update_hovered(path: &[Widget]) -> Vec<Widget> {
+        let previous: Vec<Widget> = Caribou::instance().hovered
+            .replace(path.iter().map(|widget| widget.refer()).collect())
+            .iter()
+            .filter_map(|widget| widget.acquire())
+            .collect();
+        let contains = |widgets: &[Widget], widget: &Widget| {
+            widgets.iter().any(|other| Rc::ptr_eq(other, widget))
+        };
+        for widget in previous.iter().rev().filter(|widget| !contains(path, widget)) {
+            if !Caribou::route_along(&[widget.clone()], RoutedInput::PointerLeave) {
+                widget.on_mouse_leave.broadcast();
+            }
+        }
+        for widget in path.iter().filter(|widget| !contains(&previous, widget)) {
+            if !Caribou::route_along(&[widget.clone()], RoutedInput::PointerEnter) {
+                widget.on_mouse_enter.broadcast();
+            }
+        }
+        path.iter().filter(|widget| contains(&previous, widget)).cloned().collect()
+    }
+
+    pub fn route_primary_down() {
+        let pos = Caribou::mouse_position().to_scalar();
+        Caribou::light_dismiss_outside(pos);
+        let count = Caribou::count_click();
+        Caribou::route_button(RoutedInput::ButtonDown(PointerButton::Primary),
+                              |widget| &widget.on_primary_down);
+        if count == 2 {
+            Caribou::route_button(RoutedInput::DoubleClick, |widget| &widget.on_double_click);
+        }
+    }
+
+    /// Number of primary presses in the current series of quick presses close to each other,
+    /// e.g. 3 during a triple click.
+    pub fn click_count() -> u32 {
+        Caribou::instance().last_click.get().map_or(0, |(_, _, count)| count)
+    }
+
+    fn count_click() -> u32 {
+        let instance = Caribou::instance();
+        let now = Instant::now();
+        let pos = Caribou::mouse_position();
+        let count = match instance.last_click.get() {
+            Some((time, last_pos, count))
+                if now.duration_since(time) <= DOUBLE_CLICK_TIME
+                    && (pos.x - last_pos.x).abs() <= DOUBLE_CLICK_DISTANCE
+                    && (pos.y - last_pos.y).abs() <= DOUBLE_CLICK_DISTANCE => count + 1,
+            _ => 1,
+        };
+        instance.last_click.set(Some((now, pos, count)));
+        count
+    }
+
+    pub fn route_primary_up() {
+        if Caribou::route_drag_release() {
+            return;
+        }
+        Caribou::route_button(RoutedInput::ButtonUp(PointerButton::Primary),
+                              |widget| &widget.on_primary_up);
+    }
+
+    pub fn route_secondary_down() {
+        let pos = Caribou::mouse_position().to_scalar();
+        Caribou::light_dismiss_outside(pos);
+        Caribou::route_button(RoutedInput::ButtonDown(PointerButton::Secondary),
+                              |widget| &widget.on_secondary_down);
+    }
+
+    pub fn route_secondary_up() {
+        Caribou::route_button(RoutedInput::ButtonUp(PointerButton::Secondary),
+                              |widget| &widget.on_secondary_up);
+    }
+
+    pub fn route_tertiary_down() {
+        Caribou::route_button(RoutedInput::ButtonDown(PointerButton::Tertiary),
+                              |widget| &widget.on_tertiary_down);
+    }
+
+    pub fn route_tertiary_up() {
+        Caribou::route_button(RoutedInput::ButtonUp(PointerButton::Tertiary),
+                              |widget| &widget.on_tertiary_up);
+    }
+
+    /// Route a wheel movement along the widgets under the pointer. Unless handled there, offer
+    /// it to them innermost first, each passing on what it could not consume.
+    pub fn route_scroll(delta: ScalarPair) {
+        if Caribou::route_pointer(RoutedInput::Scroll(delta)) {
+            return;
+        }
+        let mut rest = delta;
+        for widget in Caribou::widgets_at(Caribou::mouse_position().to_scalar()).iter().rev() {
+            if rest == ScalarPair::default() {
+                break;
+            }
+            rest = widget.on_scroll.route(rest);
+        }
+    }
+
+    /// Route a button event along the widgets under the pointer. Unless handled there, deliver
+    /// it to the capturing widget, or else to every widget under the pointer, outermost first.
+    fn route_button(input: RoutedInput, event: fn(&WidgetInner) -> &ZeroArgEvent) {
+        if Caribou::route_pointer(input) {
+            return;
+        }
+        let receivers = match Caribou::captured_widget() {
+            Some(widget) => vec![widget],
+            None => Caribou::widgets_at(Caribou::mouse_position().to_scalar()),
+        };
+        for widget in receivers {
+            event(&widget).broadcast();
+        }
+    }
+
+    /// Offer a file dragged over the window to the widgets under the cursor, innermost first.
+    pub fn route_file_hover(path: PathBuf) {
+        let pos = Caribou::mouse_position().to_scalar();
+        let target = Caribou::widgets_at(pos).into_iter().rev()
+            .find(|widget| widget.on_file_hover.any_true(path.clone()));
+        let previous = Caribou::instance().file_target
+            .replace(target.as_ref().map(|widget| widget.refer()));
+        if let Some(previous) = previous.and_then(|widget| widget.acquire()) {
+            if !target.map_or(false, |target| Rc::ptr_eq(&target, &previous)) {
+                previous.on_file_leave.broadcast();
+            }
+        }
+    }
+
+    pub fn route_file_hover_cancelled() {
+        let previous = Caribou::instance().file_target.replace(None);
+        if let Some(previous) = previous.and_then(|widget| widget.acquire()) {
+            previous.on_file_leave.broadcast();
+        }
+    }
+
+    /// Hand a dropped file to the innermost widget under the cursor that takes it.
+    pub fn route_file_drop(path: PathBuf) {
+        Caribou::route_file_hover_cancelled();
+        let pos = Caribou::mouse_position().to_scalar();
+        Caribou::widgets_at(pos).into_iter().rev()
+            .any(|widget| widget.on_file_drop.any_true(path.clone()));
+    }
+
+    /// The cursor shape to show at a point in window coordinates.
+    pub fn cursor_at(pos: ScalarPair) -> CursorIcon {
+        let base = Caribou::popup_at(pos).unwrap_or_else(Caribou::root_component);
+        let icon = cursor_at(&base, pos - *base.position.get());
+        if icon == CursorIcon::Inherit { CursorIcon::Arrow } else { icon }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::Instant;
+    use crate::caribou::Caribou;
+    use crate::caribou::math::IntPair;
+    use crate::caribou::widget::{create_widget, Widget, WidgetRefer};
+    use super::{RoutePhase, RoutedInput, DOUBLE_CLICK_DISTANCE, DOUBLE_CLICK_TIME};
+
+    type Trace = Rc<RefCell<Vec<(&'static str, RoutePhase)>>>;
+
+    /// A widget recording the phases it is handed routed events in, handling them in
+    /// `handled_in` if given.
+    fn traced(label: &'static str, trace: &Trace, handled_in: Option<RoutePhase>) -> Widget {
+        let widget = create_widget();
+        let trace = trace.clone();
+        widget.on_routed.subscribe(Box::new(move |_, event| {
+            trace.borrow_mut().push((label, event.phase()));
+            Some(event.phase()) == handled_in
+        }));
+        widget
+    }
+
+    #[test]
+    fn routes_down_to_the_target_and_back_up() {
+        let trace = Trace::default();
+        let path = [traced("root", &trace, None), traced("middle", &trace, None),
+            traced("target", &trace, None)];
+        assert!(!Caribou::route_along(&path, RoutedInput::DoubleClick));
+        assert_eq!(*trace.borrow(), vec![
+            ("root", RoutePhase::Capture),
+            ("middle", RoutePhase::Capture),
+            ("target", RoutePhase::Target),
+            ("middle", RoutePhase::Bubble),
+            ("root", RoutePhase::Bubble),
+        ]);
+    }
+
+    #[test]
+    fn handling_stops_the_route() {
+        let trace = Trace::default();
+        let path = [traced("root", &trace, None),
+            traced("middle", &trace, Some(RoutePhase::Capture)),
+            traced("target", &trace, None)];
+        assert!(Caribou::route_along(&path, RoutedInput::DoubleClick));
+        assert_eq!(*trace.borrow(),
+                   vec![("root", RoutePhase::Capture), ("middle", RoutePhase::Capture)]);
+    }
+
+    #[test]
+    fn captured_pointer_input_goes_through_the_ancestors() {
+        let trace = Trace::default();
+        let parent = traced("parent", &trace, None);
+        let child = traced("child", &trace, None);
+        child.parent.put(parent.refer());
+        Caribou::capture_mouse(&child);
+        Caribou::route_pointer(RoutedInput::DoubleClick);
+        Caribou::release_mouse();
+        assert_eq!(*trace.borrow(), vec![
+            ("parent", RoutePhase::Capture),
+            ("child", RoutePhase::Target),
+            ("parent", RoutePhase::Bubble),
+        ]);
+    }
+    #[test]
+    fn quick_presses_in_place_count_up() {
+        let instance = Caribou::instance();
+        instance.pointer.set(IntPair::new(100, 100));
+        assert_eq!(Caribou::count_click(), 1);
+        instance.pointer.set(IntPair::new(100 + DOUBLE_CLICK_DISTANCE, 100));
+        assert_eq!(Caribou::count_click(), 2);
+        assert_eq!(Caribou::count_click(), 3);
+        assert_eq!(Caribou::click_count(), 3);
+        instance.pointer.set(IntPair::new(110 + DOUBLE_CLICK_DISTANCE, 100));
+        assert_eq!(Caribou::count_click(), 1);
+        let (_, position, count) = instance.last_click.get().unwrap();
+        instance.last_click.set(Some((Instant::now() - DOUBLE_CLICK_TIME * 2, position, count)));
+        assert_eq!(Caribou::count_click(), 1);
+    }
+
+    #[test]
+    fn second_quick_press_is_a_double_click() {
+        let root = Caribou::root_component();
+        let double_clicks = Rc::new(Cell::new(0));
+        let counter = double_clicks.clone();
+        root.on_double_click.subscribe(Box::new(move |_| counter.set(counter.get() + 1)));
+        for _ in 0..3 {
+            Caribou::route_primary_down();
+            Caribou::route_primary_up();
+        }
+        assert_eq!(double_clicks.get(), 1);
+    }
+}
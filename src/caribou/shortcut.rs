@@ -0,0 +1,194 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use crate::caribou::Caribou;
+use crate::caribou::input::{Key, KeyEvent, Modifier};
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetRef, WidgetRefer};
+
+/// A key together with the exact set of modifiers held with it, e.g. Ctrl+Shift+S.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortcut {
+    pub key: Key,
+    pub modifiers: Vec<Modifier>,
+}
+
+impl Shortcut {
+    pub fn new(key: Key, modifiers: &[Modifier]) -> Shortcut {
+        Shortcut { key, modifiers: modifiers.to_vec() }
+    }
+
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        self.key == event.key
+            && self.modifiers.iter().all(|modifier| event.modifiers.contains(modifier))
+            && event.modifiers.iter().all(|modifier| self.modifiers.contains(modifier))
+    }
+
+    fn same_as(&self, other: &Shortcut) -> bool {
+        self.key == other.key
+            && self.modifiers.iter().all(|modifier| other.modifiers.contains(modifier))
+            && other.modifiers.iter().all(|modifier| self.modifiers.contains(modifier))
+    }
+}
+
+/// Identifies a registered shortcut for `Caribou::unregister_shortcut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortcutHandle(u64);
+
+/// The shortcut is already registered for the same scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortcutConflict(pub Shortcut);
+
+impl Display for ShortcutConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shortcut {:?} is already registered in this scope", self.0)
+    }
+}
+
+impl std::error::Error for ShortcutConflict {}
+
+struct Registration {
+    handle: ShortcutHandle,
+    shortcut: Shortcut,
+    /// Only active while the focus is inside this widget, or always when absent
+    scope: Option<WidgetRef>,
+    callback: Rc<dyn Fn()>,
+}
+
+thread_local! {
+    static SHORTCUTS: RefCell<Vec<Registration>> = RefCell::new(Vec::new());
+    static NEXT_HANDLE: Cell<u64> = Cell::new(0);
+}
+
+impl Caribou {
+    /// Run `callback` whenever the shortcut is pressed, before the focused widget sees the key.
+    pub fn register_shortcut<F>(shortcut: Shortcut, callback: F) -> Result<ShortcutHandle, ShortcutConflict>
+        where F: Fn() + 'static {
+        Caribou::add_shortcut(shortcut, None, Rc::new(callback))
+    }
+
+    /// Like `register_shortcut`, but only while the focus is on `scope` or one of its
+    /// descendants. Shortcuts of inner scopes take precedence over outer ones.
+    pub fn register_scoped_shortcut<F>(shortcut: Shortcut, scope: &Widget, callback: F)
+        -> Result<ShortcutHandle, ShortcutConflict> where F: Fn() + 'static {
+        Caribou::add_shortcut(shortcut, Some(scope.refer()), Rc::new(callback))
+    }
+
+    pub fn unregister_shortcut(handle: ShortcutHandle) {
+        SHORTCUTS.with(|shortcuts| shortcuts.borrow_mut()
+            .retain(|registration| registration.handle != handle));
+    }
+
+    fn add_shortcut(shortcut: Shortcut, scope: Option<WidgetRef>, callback: Rc<dyn Fn()>)
+        -> Result<ShortcutHandle, ShortcutConflict> {
+        SHORTCUTS.with(|shortcuts| {
+            let mut shortcuts = shortcuts.borrow_mut();
+            // Scopes of destroyed widgets can never become active again
+            shortcuts.retain(|registration| registration.scope.as_ref()
+                .map_or(true, |scope| scope.upgrade().is_some()));
+            let conflict = shortcuts.iter().any(|registration| {
+                let same_scope = match (&registration.scope, &scope) {
+                    (None, None) => true,
+                    (Some(a), Some(b)) => a.ptr_eq(b),
+                    _ => false,
+                };
+                same_scope && registration.shortcut.same_as(&shortcut)
+            });
+            if conflict {
+                return Err(ShortcutConflict(shortcut));
+            }
+            let handle = ShortcutHandle(NEXT_HANDLE.with(|next| next.replace(next.get() + 1)));
+            shortcuts.push(Registration { handle, shortcut, scope, callback });
+            Ok(handle)
+        })
+    }
+
+    /// Run the shortcut matching a key press, searching the scopes from the focused widget
+    /// outwards and the unscoped shortcuts last. Returns whether one ran.
+    pub(crate) fn dispatch_shortcut(event: &KeyEvent) -> bool {
+        let find = |scope: Option<&Widget>| SHORTCUTS.with(|shortcuts| {
+            shortcuts.borrow().iter()
+                .find(|registration| registration.shortcut.matches(event)
+                    && match (&registration.scope, scope) {
+                        (None, None) => true,
+                        (Some(registered), Some(scope)) => registered.ptr_eq(&scope.refer()),
+                        _ => false,
+                    })
+                .map(|registration| registration.callback.clone())
+        });
+        let mut target = Caribou::instance().focused_component.get().upgrade();
+        while let Some(widget) = target {
+            if let Some(callback) = find(Some(&widget)) {
+                callback();
+                return true;
+            }
+            target = widget.parent.get_cloned().and_then(|parent| parent.acquire());
+        }
+        match find(None) {
+            Some(callback) => {
+                callback();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::caribou::Caribou;
+    use crate::caribou::input::{Key, KeyEvent, Modifier};
+    use crate::caribou::widget::{create_widget, WidgetRefer};
+    use super::{Shortcut, ShortcutConflict};
+
+    fn press(key: Key, modifiers: &[Modifier]) -> KeyEvent {
+        KeyEvent { key, modifiers: modifiers.to_vec(), repeat: false }
+    }
+
+    #[test]
+    fn modifiers_must_match_exactly() {
+        let save = Shortcut::new(Key::S, &[Modifier::Control, Modifier::Shift]);
+        assert!(save.matches(&press(Key::S, &[Modifier::Shift, Modifier::Control])));
+        assert!(!save.matches(&press(Key::S, &[Modifier::Control])));
+        assert!(!save.matches(&press(Key::S, &[Modifier::Control, Modifier::Shift, Modifier::Alt])));
+    }
+
+    #[test]
+    fn same_shortcut_conflicts_only_within_a_scope() {
+        let save = || Shortcut::new(Key::S, &[Modifier::Control, Modifier::Shift]);
+        let reordered = Shortcut::new(Key::S, &[Modifier::Shift, Modifier::Control]);
+        let handle = Caribou::register_shortcut(save(), || {}).unwrap();
+        assert_eq!(Caribou::register_shortcut(reordered.clone(), || {}),
+                   Err(ShortcutConflict(reordered)));
+        let scope = create_widget();
+        assert!(Caribou::register_scoped_shortcut(save(), &scope, || {}).is_ok());
+        assert!(Caribou::register_scoped_shortcut(save(), &scope, || {}).is_err());
+        Caribou::unregister_shortcut(handle);
+        assert!(Caribou::register_shortcut(save(), || {}).is_ok());
+    }
+
+    #[test]
+    fn innermost_scope_of_the_focus_wins() {
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let log = |label: &'static str| {
+            let ran = ran.clone();
+            move || ran.borrow_mut().push(label)
+        };
+        let (outer, inner, sibling) = (create_widget(), create_widget(), create_widget());
+        inner.parent.put(outer.refer());
+        sibling.parent.put(outer.refer());
+        let find = Shortcut::new(Key::F, &[Modifier::Control]);
+        Caribou::register_shortcut(find.clone(), log("global")).unwrap();
+        Caribou::register_scoped_shortcut(find.clone(), &outer, log("outer")).unwrap();
+        Caribou::register_scoped_shortcut(find, &inner, log("inner")).unwrap();
+        let ctrl_f = press(Key::F, &[Modifier::Control]);
+        assert!(Caribou::dispatch_shortcut(&ctrl_f));
+        Caribou::request_focus(&inner);
+        assert!(Caribou::dispatch_shortcut(&ctrl_f));
+        Caribou::request_focus(&sibling);
+        assert!(Caribou::dispatch_shortcut(&ctrl_f));
+        assert!(!Caribou::dispatch_shortcut(&press(Key::F, &[])));
+        assert_eq!(*ran.borrow(), vec!["global", "inner", "outer"]);
+    }
+}
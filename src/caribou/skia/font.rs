@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use skia_safe::{FontHinting, FontMgr, FontStyle, GlyphId, Point, Shaper, TextBlob, Typeface};
+use skia_safe::font::Edging;
+use skia_safe::shaper::run_handler::{Buffer, RunInfo};
+use skia_safe::shaper::RunHandler;
+use skia_safe::font_style::{Slant, Weight, Width};
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Font, FontSlant, TextEdging, TextHinting};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TypefaceKey {
+    family: String,
+    weight: i32,
+    slant: FontSlant,
+}
+
+thread_local! {
+    static FONT_MGR: FontMgr = FontMgr::default();
+    static SHAPER: Shaper = Shaper::new(None);
+    static TYPEFACES: RefCell<HashMap<TypefaceKey, Typeface>> = RefCell::new(HashMap::new());
+    // Keyed by the typeface key, the bits of the size and the rendering settings
+    static FONTS: RefCell<HashMap<(TypefaceKey, u32, TextEdging, TextHinting), skia_safe::Font>> =
+        RefCell::new(HashMap::new());
+    // Faces loaded by the application, by the family name they were registered under
+    static REGISTERED: RefCell<HashMap<String, Vec<Typeface>>> = RefCell::new(HashMap::new());
+}
+
+/// Make a font file (TrueType, OpenType, ...) available under `family`, taking precedence
+/// over system fonts of that name. Several faces can be registered under one family for
+/// its weights and slants. Returns whether the data could be read as a font.
+pub fn skia_register_font(bytes: &[u8], family: &str) -> bool {
+    let face = match FONT_MGR.with(|mgr| mgr.new_from_data(bytes, None)) {
+        Some(face) => face,
+        None => return false,
+    };
+    REGISTERED.with(|registered| registered.borrow_mut()
+        .entry(family.to_string())
+        .or_default()
+        .push(face));
+    skia_clear_font_cache();
+    true
+}
+
+/// The registered face of a family closest to the style, preferring the right slant over
+/// the right weight.
+fn skia_registered_typeface(family: &str, style: FontStyle) -> Option<Typeface> {
+    REGISTERED.with(|registered| {
+        registered.borrow().get(family)?.iter()
+            .min_by_key(|face| {
+                let face_style = face.font_style();
+                let slant_mismatch = (face_style.slant() != style.slant()) as i32;
+                let weight_distance = (*face_style.weight() - *style.weight()).abs();
+                (slant_mismatch, weight_distance)
+            })
+            .cloned()
+    })
+}
+
+/// The Skia font for a font description, matched against the system fonts once per family,
+/// weight and slant, and rendered as set on the font or else on the window.
+pub fn skia_cached_font(font: &Font) -> skia_safe::Font {
+    let key = TypefaceKey {
+        family: font.family.to_string(),
+        weight: font.weight,
+        slant: font.slant,
+    };
+    let rendering = *Caribou::instance().window.text_rendering.get();
+    let edging = font.edging.unwrap_or(rendering.edging);
+    let hinting = font.hinting.unwrap_or(rendering.hinting);
+    let size_key = (key.clone(), font.size.to_bits(), edging, hinting);
+    if let Some(cached) = FONTS.with(|fonts| fonts.borrow().get(&size_key).cloned()) {
+        return cached;
+    }
+    let typeface = skia_cached_typeface(key);
+    let mut made = skia_safe::Font::from_typeface(typeface, font.size);
+    made.set_edging(match edging {
+        TextEdging::Alias => Edging::Alias,
+        TextEdging::Antialias => Edging::AntiAlias,
+        TextEdging::SubpixelAntialias => Edging::SubpixelAntiAlias,
+    });
+    // Glyphs placed at fractional positions go along with coverage per color element
+    made.set_subpixel(edging == TextEdging::SubpixelAntialias);
+    made.set_hinting(match hinting {
+        TextHinting::None => FontHinting::None,
+        TextHinting::Slight => FontHinting::Slight,
+        TextHinting::Normal => FontHinting::Normal,
+        TextHinting::Full => FontHinting::Full,
+    });
+    FONTS.with(|fonts| fonts.borrow_mut().insert(size_key, made.clone()));
+    made
+}
+
+fn skia_cached_typeface(key: TypefaceKey) -> Typeface {
+    if let Some(cached) = TYPEFACES.with(|faces| faces.borrow().get(&key).cloned()) {
+        return cached;
+    }
+    let style = FontStyle::new(
+        Weight::from(key.weight),
+        Width::NORMAL,
+        match key.slant {
+            FontSlant::Normal => Slant::Upright,
+            FontSlant::Italic => Slant::Italic,
+            FontSlant::Oblique => Slant::Oblique
+        });
+    let face = skia_registered_typeface(&key.family, style).or_else(|| FONT_MGR.with(|mgr| mgr
+        .match_family_style(&key.family, style)
+        // Missing families fall back to the platform default rather than failing to draw
+        .or_else(|| mgr.legacy_make_typeface(None, style))))
+        .unwrap();
+    TYPEFACES.with(|faces| faces.borrow_mut().insert(key, face.clone()));
+    face
+}
+
+/// Forget every matched typeface, e.g. after the set of available fonts changed.
+pub fn skia_clear_font_cache() {
+    TYPEFACES.with(|faces| faces.borrow_mut().clear());
+    FONTS.with(|fonts| fonts.borrow_mut().clear());
+}
+
+/// Whether a paragraph reads left to right, decided by its first strongly directional
+/// character as in the Unicode bidirectional algorithm. Text without one counts as such.
+pub fn skia_is_left_to_right(text: &str) -> bool {
+    let is_right_to_left = |c: char| matches!(c as u32,
+        // Hebrew, Arabic, Syriac, Thaana, NKo, Samaritan, Mandaic and their supplements
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF);
+    text.chars()
+        .find(|c| c.is_alphabetic())
+        .map_or(true, |c| !is_right_to_left(c))
+}
+
+/// Shape a single line of text into glyphs with HarfBuzz, so that scripts like Arabic and
+/// Devanagari, ligatures and emoji sequences come out right. Characters the font lacks are
+/// taken from fallback fonts. The blob is placed with its baseline at the origin.
+pub fn skia_shape_text(text: &str, font: &skia_safe::Font) -> Option<TextBlob> {
+    let (_, metrics) = font.metrics();
+    let left_to_right = skia_is_left_to_right(text);
+    SHAPER.with(|shaper| shaper
+        .shape_text_blob(text, font, left_to_right, f32::INFINITY, (0.0, metrics.ascent))
+        .map(|(blob, _)| blob))
+}
+
+/// Sums the advances of the shaped runs, without keeping the glyphs.
+#[derive(Default)]
+struct AdvanceHandler {
+    width: f32,
+    glyphs: Vec<GlyphId>,
+    positions: Vec<Point>,
+}
+
+impl RunHandler for AdvanceHandler {
+    fn begin_line(&mut self) {}
+
+    fn run_info(&mut self, info: &RunInfo) {
+        self.width += info.advance.x;
+    }
+
+    fn commit_run_info(&mut self) {}
+
+    fn run_buffer(&mut self, info: &RunInfo) -> Buffer {
+        self.glyphs.resize(info.glyph_count, 0);
+        self.positions.resize(info.glyph_count, Point::default());
+        Buffer::new(&mut self.glyphs, &mut self.positions, None)
+    }
+
+    fn commit_run_buffer(&mut self, _info: &RunInfo) {}
+
+    fn commit_line(&mut self) {}
+}
+
+/// The advance of a single line of text as `skia_shape_text` lays it out, which differs
+/// from the sum of the character advances where glyphs are combined or taken from
+/// fallback fonts.
+pub fn skia_measure_shaped(text: &str, font: &skia_safe::Font) -> f32 {
+    if text.is_empty() {
+        return 0.0;
+    }
+    let mut handler = AdvanceHandler::default();
+    let left_to_right = skia_is_left_to_right(text);
+    SHAPER.with(|shaper| shaper.shape(text, font, left_to_right, f32::INFINITY, &mut handler));
+    handler.width
+}
@@ -0,0 +1,197 @@
+use std::ptr::{null, null_mut};
+use glutin::dpi::PhysicalSize;
+use glutin::event_loop::EventLoop;
+use glutin::platform::windows::WindowExtWindows;
+use glutin::window::{Window, WindowBuilder};
+use log::warn;
+use skia_safe::gpu::d3d;
+use skia_safe::gpu::{BackendRenderTarget, DirectContext, FlushInfo, Protected, SurfaceOrigin};
+use skia_safe::surface::BackendSurfaceAccess;
+use skia_safe::{Canvas, ColorType, Surface};
+use winapi::Interface;
+use winapi::shared::dxgi::{CreateDXGIFactory1, IDXGIAdapter1, DXGI_ADAPTER_DESC1, DXGI_ADAPTER_FLAG_SOFTWARE, DXGI_SWAP_EFFECT_FLIP_DISCARD};
+use winapi::shared::dxgi1_2::{IDXGISwapChain1, DXGI_ALPHA_MODE_UNSPECIFIED, DXGI_SCALING_STRETCH, DXGI_SWAP_CHAIN_DESC1};
+use winapi::shared::dxgi1_4::{IDXGIFactory4, IDXGISwapChain3};
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_UNKNOWN};
+use winapi::shared::dxgitype::{DXGI_SAMPLE_DESC, DXGI_STANDARD_MULTISAMPLE_QUALITY_PATTERN, DXGI_USAGE_RENDER_TARGET_OUTPUT};
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::winerror::{HRESULT, SUCCEEDED};
+use winapi::um::d3d12::{D3D12CreateDevice, ID3D12CommandQueue, ID3D12Device, ID3D12Resource, D3D12_COMMAND_LIST_TYPE_DIRECT, D3D12_COMMAND_QUEUE_DESC, D3D12_COMMAND_QUEUE_FLAG_NONE, D3D12_RESOURCE_STATE_COMMON};
+use winapi::um::d3dcommon::D3D_FEATURE_LEVEL_11_0;
+use winapi::um::unknwnbase::IUnknown;
+use wio::com::ComPtr;
+use crate::caribou::skia::gpu::{skia_wrap_render_target, SkiaTarget};
+
+/// Back buffers of the swapchain
+const BUFFER_COUNT: u32 = 2;
+
+/// Drawing through Direct3D 12, onto the back buffers of a DXGI swapchain of the window.
+pub struct D3dTarget {
+    /// One per back buffer
+    surfaces: Vec<Surface>,
+    gr_context: DirectContext,
+    swap_chain: ComPtr<IDXGISwapChain3>,
+    _queue: ComPtr<ID3D12CommandQueue>,
+    _device: ComPtr<ID3D12Device>,
+    _adapter: ComPtr<IDXGIAdapter1>,
+    window: Window,
+}
+
+impl D3dTarget {
+    pub fn new(wb: WindowBuilder, el: &EventLoop<()>) -> Result<D3dTarget, String> {
+        let window = wb.build(el).map_err(|e| e.to_string())?;
+        unsafe {
+            let mut factory: *mut IDXGIFactory4 = null_mut();
+            check(CreateDXGIFactory1(&IDXGIFactory4::uuidof(), &mut factory as *mut _ as *mut _),
+                  "CreateDXGIFactory1")?;
+            let factory = ComPtr::from_raw(factory);
+            let (adapter, device) = Self::create_device(&factory)?;
+            let queue_desc = D3D12_COMMAND_QUEUE_DESC {
+                Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+                Priority: 0,
+                Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
+                NodeMask: 0,
+            };
+            let mut queue: *mut ID3D12CommandQueue = null_mut();
+            check(device.CreateCommandQueue(&queue_desc, &ID3D12CommandQueue::uuidof(),
+                                            &mut queue as *mut _ as *mut _),
+                  "CreateCommandQueue")?;
+            let queue = ComPtr::from_raw(queue);
+            let size = window.inner_size();
+            let swap_chain_desc = DXGI_SWAP_CHAIN_DESC1 {
+                Width: size.width,
+                Height: size.height,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                Stereo: FALSE,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+                BufferCount: BUFFER_COUNT,
+                Scaling: DXGI_SCALING_STRETCH,
+                SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+                AlphaMode: DXGI_ALPHA_MODE_UNSPECIFIED,
+                Flags: 0,
+            };
+            let mut swap_chain: *mut IDXGISwapChain1 = null_mut();
+            check(factory.CreateSwapChainForHwnd(queue.as_raw() as *mut IUnknown, window.hwnd() as _,
+                                                 &swap_chain_desc, null(), null_mut(),
+                                                 &mut swap_chain),
+                  "CreateSwapChainForHwnd")?;
+            let swap_chain = ComPtr::from_raw(swap_chain).cast::<IDXGISwapChain3>()
+                .map_err(|hr| format!("The swapchain has no IDXGISwapChain3: {:#010x}", hr))?;
+            let backend_context = d3d::BackendContext {
+                adapter: adapter.clone(),
+                device: device.clone(),
+                queue: queue.clone(),
+                memory_allocator: None,
+                protected_context: Protected::No,
+            };
+            let gr_context = DirectContext::new_d3d(&backend_context, None)
+                .ok_or_else(|| "Cannot create a Skia context for the device".to_string())?;
+            let mut target = D3dTarget {
+                surfaces: Vec::new(),
+                gr_context,
+                swap_chain,
+                _queue: queue,
+                _device: device,
+                _adapter: adapter,
+                window,
+            };
+            target.wrap_buffers(size)?;
+            Ok(target)
+        }
+    }
+
+    /// Pick the first hardware adapter that supports Direct3D 12.
+    unsafe fn create_device(factory: &ComPtr<IDXGIFactory4>)
+                            -> Result<(ComPtr<IDXGIAdapter1>, ComPtr<ID3D12Device>), String> {
+        for index in 0.. {
+            let mut adapter: *mut IDXGIAdapter1 = null_mut();
+            if !SUCCEEDED(factory.EnumAdapters1(index, &mut adapter)) {
+                break;
+            }
+            let adapter = ComPtr::from_raw(adapter);
+            let mut desc: DXGI_ADAPTER_DESC1 = std::mem::zeroed();
+            adapter.GetDesc1(&mut desc);
+            if desc.Flags & DXGI_ADAPTER_FLAG_SOFTWARE != 0 {
+                continue;
+            }
+            let mut device: *mut ID3D12Device = null_mut();
+            if SUCCEEDED(D3D12CreateDevice(adapter.as_raw() as *mut IUnknown, D3D_FEATURE_LEVEL_11_0,
+                                           &ID3D12Device::uuidof(), &mut device as *mut _ as *mut _)) {
+                return Ok((adapter, ComPtr::from_raw(device)));
+            }
+        }
+        Err("No Direct3D 12 adapter".to_string())
+    }
+
+    /// Wrap the back buffers of the swapchain in surfaces.
+    unsafe fn wrap_buffers(&mut self, size: PhysicalSize<u32>) -> Result<(), String> {
+        for index in 0..BUFFER_COUNT {
+            let mut resource: *mut ID3D12Resource = null_mut();
+            check(self.swap_chain.GetBuffer(index, &ID3D12Resource::uuidof(),
+                                            &mut resource as *mut _ as *mut _),
+                  "GetBuffer")?;
+            let info = d3d::TextureResourceInfo {
+                resource: ComPtr::from_raw(resource),
+                alloc: None,
+                resource_state: D3D12_RESOURCE_STATE_COMMON,
+                format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                sample_count: 1,
+                level_count: 1,
+                sample_quality_pattern: DXGI_STANDARD_MULTISAMPLE_QUALITY_PATTERN,
+                protected: Protected::No,
+            };
+            let render_target = BackendRenderTarget::new_d3d(
+                (size.width as i32, size.height as i32), &info);
+            let surface = skia_wrap_render_target(&mut self.gr_context, &render_target,
+                                                  SurfaceOrigin::TopLeft, ColorType::RGBA8888)
+                .ok_or_else(|| "Cannot wrap a back buffer in a surface".to_string())?;
+            self.surfaces.push(surface);
+        }
+        Ok(())
+    }
+}
+
+impl SkiaTarget for D3dTarget {
+    fn window(&self) -> &Window {
+        &self.window
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        // The buffers can only be resized once nothing refers to them
+        self.surfaces.clear();
+        self.gr_context.submit(true);
+        let result = unsafe {
+            check(self.swap_chain.ResizeBuffers(BUFFER_COUNT, size.width, size.height,
+                                                DXGI_FORMAT_UNKNOWN, 0),
+                  "ResizeBuffers")
+                .and_then(|_| self.wrap_buffers(size))
+        };
+        if let Err(error) = result {
+            warn!("Cannot resize the swapchain: {}", error);
+        }
+    }
+
+    fn draw_frame(&mut self, draw: &mut dyn FnMut(&mut Canvas)) {
+        let index = unsafe { self.swap_chain.GetCurrentBackBufferIndex() } as usize;
+        let surface = match self.surfaces.get_mut(index) {
+            Some(surface) => surface,
+            None => return,
+        };
+        draw(surface.canvas());
+        surface.flush_with_access_info(BackendSurfaceAccess::Present, &FlushInfo::default());
+        // Waiting for the GPU to finish keeps the back buffers free of fences
+        self.gr_context.submit(true);
+        if let Err(error) = check(unsafe { self.swap_chain.Present(1, 0) }, "Present") {
+            warn!("{}", error);
+        }
+    }
+}
+
+fn check(hr: HRESULT, call: &str) -> Result<(), String> {
+    if SUCCEEDED(hr) {
+        Ok(())
+    } else {
+        Err(format!("{} failed with {:#010x}", call, hr))
+    }
+}
@@ -0,0 +1,86 @@
+use cocoa::appkit::NSView;
+use cocoa::base::{id as cocoa_id, YES};
+use core_graphics_types::geometry::CGSize;
+use foreign_types_shared::{ForeignType, ForeignTypeRef};
+use glutin::dpi::PhysicalSize;
+use glutin::event_loop::EventLoop;
+use glutin::platform::macos::WindowExtMacOS;
+use glutin::window::{Window, WindowBuilder};
+use metal::{CommandQueue, Device, MTLPixelFormat, MetalLayer};
+use objc::rc::autoreleasepool;
+use skia_safe::gpu::mtl;
+use skia_safe::gpu::{BackendRenderTarget, DirectContext, SurfaceOrigin};
+use skia_safe::{Canvas, ColorType};
+use crate::caribou::skia::gpu::{skia_wrap_render_target, SkiaTarget};
+
+/// Drawing through Metal, onto the drawables of a layer backing the window's view.
+pub struct MetalTarget {
+    gr_context: DirectContext,
+    queue: CommandQueue,
+    layer: MetalLayer,
+    _device: Device,
+    window: Window,
+}
+
+impl MetalTarget {
+    pub fn new(wb: WindowBuilder, el: &EventLoop<()>) -> Result<MetalTarget, String> {
+        let window = wb.build(el).map_err(|e| e.to_string())?;
+        let device = Device::system_default().ok_or_else(|| "No Metal device".to_string())?;
+        let queue = device.new_command_queue();
+        let layer = MetalLayer::new();
+        layer.set_device(&device);
+        layer.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
+        layer.set_presents_with_transaction(false);
+        unsafe {
+            let view = window.ns_view() as cocoa_id;
+            view.setWantsLayer(YES);
+            view.setLayer(layer.as_ref() as *const _ as _);
+        }
+        let size = window.inner_size();
+        layer.set_drawable_size(CGSize::new(size.width as f64, size.height as f64));
+        let backend_context = unsafe {
+            mtl::BackendContext::new(
+                device.as_ptr() as mtl::Handle,
+                queue.as_ptr() as mtl::Handle,
+                std::ptr::null(),
+            )
+        };
+        let gr_context = DirectContext::new_metal(&backend_context, None)
+            .ok_or_else(|| "Cannot create a Skia context for the Metal device".to_string())?;
+        Ok(MetalTarget { gr_context, queue, layer, _device: device, window })
+    }
+}
+
+impl SkiaTarget for MetalTarget {
+    fn window(&self) -> &Window {
+        &self.window
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.layer.set_drawable_size(CGSize::new(size.width as f64, size.height as f64));
+    }
+
+    fn draw_frame(&mut self, draw: &mut dyn FnMut(&mut Canvas)) {
+        // Drawables are autoreleased, and the event loop has no pool of its own to drain them
+        autoreleasepool(|| {
+            let drawable = match self.layer.next_drawable() {
+                Some(drawable) => drawable,
+                None => return,
+            };
+            let size = self.layer.drawable_size();
+            let texture_info = unsafe {
+                mtl::TextureInfo::new(drawable.texture().as_ptr() as mtl::Handle)
+            };
+            let render_target = BackendRenderTarget::new_metal(
+                (size.width as i32, size.height as i32), 1, &texture_info);
+            if let Some(mut surface) = skia_wrap_render_target(
+                &mut self.gr_context, &render_target, SurfaceOrigin::TopLeft, ColorType::BGRA8888) {
+                draw(surface.canvas());
+                surface.flush_and_submit();
+            }
+            let command_buffer = self.queue.new_command_buffer();
+            command_buffer.present_drawable(drawable);
+            command_buffer.commit();
+        });
+    }
+}
@@ -0,0 +1,198 @@
+use glutin::{ContextWrapper, GlProfile, NotCurrent, PossiblyCurrent};
+use glutin::dpi::PhysicalSize;
+use glutin::event_loop::EventLoop;
+use glutin::window::{Window, WindowBuilder};
+use gl::types::*;
+use log::{info, warn};
+use skia_safe::gpu::{BackendRenderTarget, DirectContext, SurfaceOrigin};
+use skia_safe::gpu::gl::{Format, FramebufferInfo};
+use skia_safe::{Canvas, ColorType, PixelGeometry, Surface, SurfaceProps, SurfacePropsFlags};
+use crate::caribou::Caribou;
+use crate::caribou::batch::LcdOrder;
+use crate::caribou::skia::skia_make_color_space;
+use crate::caribou::window::RenderBackend;
+
+#[cfg(feature = "vulkan")]
+pub mod vulkan;
+#[cfg(all(target_os = "macos", feature = "metal"))]
+pub mod metal;
+#[cfg(all(target_os = "windows", feature = "d3d"))]
+pub mod d3d;
+
+type WindowedContext = ContextWrapper<PossiblyCurrent, Window>;
+
+/// A window together with the GPU context and surfaces its contents are drawn with.
+pub trait SkiaTarget {
+    fn window(&self) -> &Window;
+
+    /// Recreate the surfaces for a new size in physical pixels. The size is passed in, as the
+    /// window may not report its new size yet while it changes.
+    fn resize(&mut self, size: PhysicalSize<u32>);
+
+    /// Draw a frame onto the window surface and present it.
+    fn draw_frame(&mut self, draw: &mut dyn FnMut(&mut Canvas));
+}
+
+/// Create the window and the target for a backend, falling back to OpenGL when the backend
+/// cannot be set up on this machine. Returns the backend actually used.
+pub fn skia_make_target(backend: RenderBackend, wb: WindowBuilder,
+                        el: &EventLoop<()>) -> (Box<dyn SkiaTarget>, RenderBackend) {
+    let fallback = match backend {
+        RenderBackend::OpenGl => None,
+        #[cfg(feature = "vulkan")]
+        RenderBackend::Vulkan => match vulkan::VulkanTarget::new(wb.clone(), el) {
+            Ok(target) => return (Box::new(target), backend),
+            Err(error) => Some(error),
+        },
+        #[cfg(all(target_os = "macos", feature = "metal"))]
+        RenderBackend::Metal => match self::metal::MetalTarget::new(wb.clone(), el) {
+            Ok(target) => return (Box::new(target), backend),
+            Err(error) => Some(error),
+        },
+        #[cfg(all(target_os = "windows", feature = "d3d"))]
+        RenderBackend::Direct3D => match d3d::D3dTarget::new(wb.clone(), el) {
+            Ok(target) => return (Box::new(target), backend),
+            Err(error) => Some(error),
+        },
+        #[allow(unreachable_patterns)]
+        _ => Some(format!("{:?} is not compiled in", backend)),
+    };
+    if let Some(error) = fallback {
+        warn!("Cannot render with {:?}, using OpenGL instead: {}", backend, error);
+    }
+    (Box::new(GlTarget::new(wb, el)), RenderBackend::OpenGl)
+}
+
+/// Surface properties shared by the backends. Subpixel antialiased text follows the element
+/// order of the display.
+pub(crate) fn skia_surface_props() -> SurfaceProps {
+    let geometry = match Caribou::instance().window.text_rendering.get().lcd_order {
+        LcdOrder::Rgb => PixelGeometry::RGBH,
+        LcdOrder::Bgr => PixelGeometry::BGRH,
+        LcdOrder::VerticalRgb => PixelGeometry::RGBV,
+        LcdOrder::VerticalBgr => PixelGeometry::BGRV,
+    };
+    SurfaceProps::new(SurfacePropsFlags::default(), geometry)
+}
+
+/// Wrap a render target of the window in a surface with the color space of the window.
+pub(crate) fn skia_wrap_render_target(gr_context: &mut DirectContext,
+                                      target: &BackendRenderTarget, origin: SurfaceOrigin,
+                                      color_type: ColorType) -> Option<Surface> {
+    Surface::from_backend_render_target(
+        gr_context,
+        target,
+        origin,
+        color_type,
+        skia_make_color_space(*Caribou::instance().window.color_space.get()),
+        Some(&skia_surface_props()),
+    )
+}
+
+/// Drawing through a GL context created by glutin.
+pub struct GlTarget {
+    // Fields drop in order. `WindowedContext` _must_ be dropped after `DirectContext`.
+    //
+    // https://github.com/rust-skia/rust-skia/issues/476
+    surface: Surface,
+    gr_context: DirectContext,
+    windowed_context: WindowedContext,
+    fb_info: FramebufferInfo,
+}
+
+impl GlTarget {
+    pub fn new(wb: WindowBuilder, el: &EventLoop<()>) -> GlTarget {
+        let windowed_context = skia_build_context(wb, el);
+        let windowed_context = unsafe { windowed_context.make_current().unwrap() };
+        info!("Pixel format of the window's GL context: {:?}",
+              windowed_context.get_pixel_format());
+
+        gl::load_with(|s| windowed_context.get_proc_address(s));
+
+        let mut gr_context = DirectContext::new_gl(None, None).unwrap();
+
+        let fb_info = {
+            let mut fboid: GLint = 0;
+            unsafe { gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut fboid) };
+
+            FramebufferInfo {
+                fboid: fboid.try_into().unwrap(),
+                format: Format::RGBA8.into(),
+            }
+        };
+        let size = windowed_context.window().inner_size();
+        let surface = Self::create_surface(&windowed_context, size, &fb_info, &mut gr_context);
+        GlTarget { surface, gr_context, windowed_context, fb_info }
+    }
+
+    fn create_surface(
+        windowed_context: &WindowedContext,
+        size: PhysicalSize<u32>,
+        fb_info: &FramebufferInfo,
+        gr_context: &mut DirectContext,
+    ) -> Surface {
+        let pixel_format = windowed_context.get_pixel_format();
+        let backend_render_target = BackendRenderTarget::new_gl(
+            (
+                size.width.try_into().unwrap(),
+                size.height.try_into().unwrap(),
+            ),
+            pixel_format.multisampling.map(|s| s.try_into().unwrap()),
+            pixel_format.stencil_bits.try_into().unwrap(),
+            *fb_info,
+        );
+        skia_wrap_render_target(gr_context, &backend_render_target, SurfaceOrigin::BottomLeft,
+                                ColorType::RGBA8888)
+            .unwrap()
+    }
+}
+
+impl SkiaTarget for GlTarget {
+    fn window(&self) -> &Window {
+        self.windowed_context.window()
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.surface = Self::create_surface(&self.windowed_context, size, &self.fb_info,
+                                            &mut self.gr_context);
+        self.windowed_context.resize(size);
+    }
+
+    fn draw_frame(&mut self, draw: &mut dyn FnMut(&mut Canvas)) {
+        draw(self.surface.canvas());
+        self.surface.canvas().flush();
+        self.windowed_context.swap_buffers().unwrap();
+    }
+}
+
+/// Create the GL context with the multisampling and stencil requested on the window, giving
+/// up multisampling and then the custom stencil size if the platform has no such config.
+fn skia_build_context(wb: WindowBuilder, el: &EventLoop<()>) -> ContextWrapper<NotCurrent, Window> {
+    // glutin only takes powers of two, so e.g. 6 samples become 4
+    let samples = match *Caribou::instance().window.msaa_samples.get() {
+        0 => 0,
+        samples => 1 << (u16::BITS - 1 - samples.leading_zeros()),
+    };
+    let stencil_bits = *Caribou::instance().window.stencil_bits.get();
+    let mut attempts = vec![(samples, stencil_bits), (0, stencil_bits), (0, 8)];
+    attempts.dedup();
+    let last = attempts.len() - 1;
+    for (index, (samples, stencil_bits)) in attempts.into_iter().enumerate() {
+        let cb = glutin::ContextBuilder::new()
+            .with_depth_buffer(0)
+            .with_stencil_buffer(stencil_bits)
+            .with_multisampling(samples)
+            .with_pixel_format(24, 8)
+            .with_gl_profile(GlProfile::Core);
+        #[cfg(not(feature = "wayland"))]
+            let cb = cb
+            .with_double_buffer(Some(true));
+        match cb.build_windowed(wb.clone(), el) {
+            Ok(context) => return context,
+            Err(error) if index < last => warn!(
+                "No GL config with {} samples and {} stencil bits: {}", samples, stencil_bits, error),
+            Err(error) => panic!("Cannot create a GL context: {}", error),
+        }
+    }
+    unreachable!()
+}
@@ -0,0 +1,310 @@
+use std::ffi::c_void;
+use ash::extensions::khr;
+use ash::vk::{self, Handle};
+use ash::{Device, Entry, Instance};
+use glutin::dpi::PhysicalSize;
+use glutin::event_loop::EventLoop;
+use glutin::window::{Window, WindowBuilder};
+use log::warn;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use skia_safe::gpu::vk as skia_vk;
+use skia_safe::gpu::{BackendRenderTarget, DirectContext, FlushInfo, SurfaceOrigin};
+use skia_safe::surface::BackendSurfaceAccess;
+use skia_safe::{Canvas, ColorType, Surface};
+use crate::caribou::skia::gpu::{skia_wrap_render_target, SkiaTarget};
+
+/// Drawing through Vulkan, onto the images of a swapchain of the window surface.
+pub struct VulkanTarget {
+    /// One per swapchain image
+    surfaces: Vec<Surface>,
+    gr_context: DirectContext,
+    swapchain: vk::SwapchainKHR,
+    format: vk::SurfaceFormatKHR,
+    /// Signaled once an acquired image is free to draw on
+    fence: vk::Fence,
+    queue: vk::Queue,
+    swapchain_loader: khr::Swapchain,
+    device: Device,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    surface_loader: khr::Surface,
+    instance: Instance,
+    _entry: Entry,
+    window: Window,
+}
+
+impl VulkanTarget {
+    pub fn new(wb: WindowBuilder, el: &EventLoop<()>) -> Result<VulkanTarget, String> {
+        let window = wb.build(el).map_err(|e| e.to_string())?;
+        unsafe {
+            let entry = Entry::load().map_err(|e| e.to_string())?;
+            let extensions = ash_window::enumerate_required_extensions(window.raw_display_handle())
+                .map_err(|e| e.to_string())?;
+            let app_info = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_1);
+            let instance_info = vk::InstanceCreateInfo::builder()
+                .application_info(&app_info)
+                .enabled_extension_names(extensions);
+            let instance = entry.create_instance(&instance_info, None).map_err(|e| e.to_string())?;
+            let surface_loader = khr::Surface::new(&entry, &instance);
+            let surface = match ash_window::create_surface(
+                &entry, &instance, window.raw_display_handle(), window.raw_window_handle(), None) {
+                Ok(surface) => surface,
+                Err(error) => {
+                    instance.destroy_instance(None);
+                    return Err(error.to_string());
+                }
+            };
+            let (physical_device, queue_family, device) =
+                match Self::create_device(&instance, &surface_loader, surface) {
+                    Ok(created) => created,
+                    Err(error) => {
+                        surface_loader.destroy_surface(surface, None);
+                        instance.destroy_instance(None);
+                        return Err(error);
+                    }
+                };
+            let queue = device.get_device_queue(queue_family, 0);
+            let formats = surface_loader
+                .get_physical_device_surface_formats(physical_device, surface)
+                .unwrap_or_default();
+            let format = formats.into_iter().find(|f| skia_color_type(f.format).is_some());
+            let gr_context = {
+                let get_proc = |of: skia_vk::GetProcOf| -> *const c_void {
+                    let function = match of {
+                        skia_vk::GetProcOf::Instance(raw, name) =>
+                            entry.get_instance_proc_addr(vk::Instance::from_raw(raw as _), name),
+                        skia_vk::GetProcOf::Device(raw, name) => {
+                            let get_device_proc_addr = instance.fp_v1_0().get_device_proc_addr;
+                            get_device_proc_addr(vk::Device::from_raw(raw as _), name)
+                        }
+                    };
+                    function.map_or(std::ptr::null(), |function| function as _)
+                };
+                let backend_context = skia_vk::BackendContext::new(
+                    instance.handle().as_raw() as _,
+                    physical_device.as_raw() as _,
+                    device.handle().as_raw() as _,
+                    (queue.as_raw() as _, queue_family as usize),
+                    &get_proc,
+                );
+                DirectContext::new_vulkan(&backend_context, None)
+            };
+            let (format, gr_context) = match (format, gr_context) {
+                (Some(format), Some(gr_context)) => (format, gr_context),
+                (format, _) => {
+                    device.destroy_device(None);
+                    surface_loader.destroy_surface(surface, None);
+                    instance.destroy_instance(None);
+                    return Err(match format {
+                        None => "No surface format Skia can draw to".to_string(),
+                        Some(_) => "Cannot create a Skia context for the device".to_string(),
+                    });
+                }
+            };
+            let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)
+                .map_err(|e| e.to_string());
+            let swapchain_loader = khr::Swapchain::new(&instance, &device);
+            // From here on, dropping the target releases whatever has been created
+            let mut target = VulkanTarget {
+                surfaces: Vec::new(),
+                gr_context,
+                swapchain: vk::SwapchainKHR::null(),
+                format,
+                fence: vk::Fence::null(),
+                queue,
+                swapchain_loader,
+                device,
+                physical_device,
+                surface,
+                surface_loader,
+                instance,
+                _entry: entry,
+                window,
+            };
+            target.fence = fence?;
+            target.create_swapchain(target.window.inner_size())?;
+            Ok(target)
+        }
+    }
+
+    /// Pick the first GPU with a queue that can both draw and present to the surface.
+    unsafe fn create_device(
+        instance: &Instance,
+        surface_loader: &khr::Surface,
+        surface: vk::SurfaceKHR,
+    ) -> Result<(vk::PhysicalDevice, u32, Device), String> {
+        let physical_devices = instance.enumerate_physical_devices().map_err(|e| e.to_string())?;
+        let (physical_device, queue_family) = physical_devices.into_iter()
+            .find_map(|physical_device| {
+                let families = instance.get_physical_device_queue_family_properties(physical_device);
+                (0..families.len() as u32)
+                    .find(|&index| {
+                        families[index as usize].queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                            && surface_loader.get_physical_device_surface_support(
+                                physical_device, index, surface).unwrap_or(false)
+                    })
+                    .map(|index| (physical_device, index))
+            })
+            .ok_or_else(|| "No GPU can present to the window".to_string())?;
+        let priorities = [1.0];
+        let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family)
+            .queue_priorities(&priorities)
+            .build()];
+        let extensions = [khr::Swapchain::name().as_ptr()];
+        let device_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_infos)
+            .enabled_extension_names(&extensions);
+        let device = instance.create_device(physical_device, &device_info, None)
+            .map_err(|e| e.to_string())?;
+        Ok((physical_device, queue_family, device))
+    }
+
+    /// Replace the swapchain and the surfaces wrapping its images.
+    unsafe fn create_swapchain(&mut self, size: PhysicalSize<u32>) -> Result<(), String> {
+        let _ = self.device.device_wait_idle();
+        self.surfaces.clear();
+        let caps = self.surface_loader
+            .get_physical_device_surface_capabilities(self.physical_device, self.surface)
+            .map_err(|e| e.to_string())?;
+        // Some platforms dictate the size, others take the window's within the limits
+        let extent = if caps.current_extent.width != u32::MAX {
+            caps.current_extent
+        } else {
+            vk::Extent2D {
+                width: size.width.clamp(caps.min_image_extent.width, caps.max_image_extent.width),
+                height: size.height.clamp(caps.min_image_extent.height, caps.max_image_extent.height),
+            }
+        };
+        let mut image_count = caps.min_image_count + 1;
+        if caps.max_image_count > 0 {
+            image_count = image_count.min(caps.max_image_count);
+        }
+        let old_swapchain = self.swapchain;
+        let info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(self.surface)
+            .min_image_count(image_count)
+            .image_format(self.format.format)
+            .image_color_space(self.format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(caps.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(vk::PresentModeKHR::FIFO)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        self.swapchain = self.swapchain_loader.create_swapchain(&info, None)
+            .map_err(|e| e.to_string())?;
+        self.swapchain_loader.destroy_swapchain(old_swapchain, None);
+        let images = self.swapchain_loader.get_swapchain_images(self.swapchain)
+            .map_err(|e| e.to_string())?;
+        let (skia_format, color_type) = skia_color_type(self.format.format).unwrap();
+        for image in images {
+            let image_info = skia_vk::ImageInfo::new(
+                image.as_raw() as _,
+                skia_vk::Alloc::default(),
+                skia_vk::ImageTiling::OPTIMAL,
+                skia_vk::ImageLayout::UNDEFINED,
+                skia_format,
+                1,
+                None,
+                None,
+                None,
+                None,
+            );
+            let render_target = BackendRenderTarget::new_vulkan(
+                (extent.width as i32, extent.height as i32), 1, &image_info);
+            let surface = skia_wrap_render_target(&mut self.gr_context, &render_target,
+                                                  SurfaceOrigin::TopLeft, color_type)
+                .ok_or_else(|| "Cannot wrap a swapchain image in a surface".to_string())?;
+            self.surfaces.push(surface);
+        }
+        Ok(())
+    }
+
+    fn recreate_swapchain(&mut self) {
+        let size = self.window.inner_size();
+        self.resize(size);
+    }
+}
+
+impl SkiaTarget for VulkanTarget {
+    fn window(&self) -> &Window {
+        &self.window
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        if let Err(error) = unsafe { self.create_swapchain(size) } {
+            warn!("Cannot recreate the Vulkan swapchain: {}", error);
+        }
+    }
+
+    fn draw_frame(&mut self, draw: &mut dyn FnMut(&mut Canvas)) {
+        if self.surfaces.is_empty() {
+            return;
+        }
+        unsafe {
+            let acquired = self.swapchain_loader.acquire_next_image(
+                self.swapchain, u64::MAX, vk::Semaphore::null(), self.fence);
+            let index = match acquired {
+                Ok((index, _)) => index,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.recreate_swapchain();
+                    return;
+                }
+                Err(error) => {
+                    warn!("Cannot acquire a swapchain image: {}", error);
+                    return;
+                }
+            };
+            // The image may still be read by the presentation engine until the fence signals
+            let _ = self.device.wait_for_fences(&[self.fence], true, u64::MAX);
+            let _ = self.device.reset_fences(&[self.fence]);
+            let surface = &mut self.surfaces[index as usize];
+            draw(surface.canvas());
+            surface.flush_with_access_info(BackendSurfaceAccess::Present, &FlushInfo::default());
+            // Waiting for the GPU to finish spares the semaphores between drawing and presenting
+            self.gr_context.submit(true);
+            let swapchains = [self.swapchain];
+            let indices = [index];
+            let present_info = vk::PresentInfoKHR::builder()
+                .swapchains(&swapchains)
+                .image_indices(&indices);
+            match self.swapchain_loader.queue_present(self.queue, &present_info) {
+                Ok(false) => {}
+                // Suboptimal or out of date, e.g. after a resize not reported yet
+                Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.recreate_swapchain(),
+                Err(error) => warn!("Cannot present a swapchain image: {}", error),
+            }
+        }
+    }
+}
+
+impl Drop for VulkanTarget {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.surfaces.clear();
+            // Skia must not touch the device once it is destroyed below
+            self.gr_context.abandon();
+            self.swapchain_loader.destroy_swapchain(self.swapchain, None);
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_device(None);
+            self.surface_loader.destroy_surface(self.surface, None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// The Skia format and color type for a swapchain format Skia can draw to directly.
+fn skia_color_type(format: vk::Format) -> Option<(skia_vk::Format, ColorType)> {
+    match format {
+        vk::Format::B8G8R8A8_UNORM => Some((skia_vk::Format::B8G8R8A8_UNORM, ColorType::BGRA8888)),
+        vk::Format::R8G8B8A8_UNORM => Some((skia_vk::Format::R8G8B8A8_UNORM, ColorType::RGBA8888)),
+        _ => None,
+    }
+}
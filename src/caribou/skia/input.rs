@@ -1,5 +1,5 @@
-use glutin::event::VirtualKeyCode;
-use crate::caribou::input::Key;
+use glutin::event::{ModifiersState, VirtualKeyCode};
+use crate::caribou::input::{CursorIcon, Key, Modifier};
 
 pub fn gl_virtual_to_key(vir: VirtualKeyCode) -> Key {
     match vir {
@@ -167,4 +167,37 @@ pub fn gl_virtual_to_key(vir: VirtualKeyCode) -> Key {
         VirtualKeyCode::Paste => Key::Paste,
         VirtualKeyCode::Cut => Key::Cut,
     }
-}
\ No newline at end of file
+}
+
+pub fn gl_cursor_icon(icon: CursorIcon) -> glutin::window::CursorIcon {
+    match icon {
+        CursorIcon::Inherit | CursorIcon::Arrow => glutin::window::CursorIcon::Default,
+        CursorIcon::IBeam => glutin::window::CursorIcon::Text,
+        CursorIcon::Hand => glutin::window::CursorIcon::Hand,
+        CursorIcon::Crosshair => glutin::window::CursorIcon::Crosshair,
+        CursorIcon::Move => glutin::window::CursorIcon::Move,
+        CursorIcon::NotAllowed => glutin::window::CursorIcon::NotAllowed,
+        CursorIcon::Wait => glutin::window::CursorIcon::Wait,
+        CursorIcon::ResizeHorizontal => glutin::window::CursorIcon::EwResize,
+        CursorIcon::ResizeVertical => glutin::window::CursorIcon::NsResize,
+        CursorIcon::ResizeNwse => glutin::window::CursorIcon::NwseResize,
+        CursorIcon::ResizeNesw => glutin::window::CursorIcon::NeswResize,
+    }
+}
+
+pub fn gl_modifiers(state: ModifiersState) -> Vec<Modifier> {
+    let mut modifiers = Vec::new();
+    if state.shift() {
+        modifiers.push(Modifier::Shift);
+    }
+    if state.ctrl() {
+        modifiers.push(Modifier::Control);
+    }
+    if state.alt() {
+        modifiers.push(Modifier::Alt);
+    }
+    if state.logo() {
+        modifiers.push(Modifier::Meta);
+    }
+    modifiers
+}
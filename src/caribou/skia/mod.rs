@@ -1,28 +1,82 @@
 use std::any::Any;
-use skia_safe::{Canvas, ClipOp, Codec, Color, Data, FontMgr, FontStyle, Image, Paint, PaintStyle, Rect, TextBlob};
+use skia_safe::{image_filters, named_gamut, named_transfer_fn, svg, AlphaType, Canvas, ClipOp, Codec, Color, Color4f, ColorSpace, ColorType, CubicResampler, Data, EncodedImageFormat, EncodedOrigin, FilterMode, IRect, Image, ImageInfo, MipmapMode, Paint, PaintCap, PaintStyle, PathEffect, Picture, Point, Rect, SamplingOptions, Surface};
 use std::cell::Ref;
 use std::fmt::{Debug, Formatter};
-use skia_safe::font_style::{Slant, Weight, Width};
+use std::sync::atomic::Ordering;
+use skia_safe::canvas::{SaveLayerRec, SrcRectConstraint};
+use skia_safe::image::CachingHint;
 use std::fs::File;
 use std::io::Read;
 use std::sync::{Arc, Mutex, RwLock};
-use crate::caribou::batch::{Batch, BatchOp, Brush, Font, FontSlant, Material, Path, PathOp, Pict, PictImpl, TextAlignment, Transform};
-use crate::caribou::math::ScalarPair;
-use crate::caribou::skia::runtime::SKIA_ENV;
+use crate::caribou::batch::{Batch, BatchOp, BlendMode, Brush, Filter, Font, HorizontalAlignment, ImageError, Material, Path, PathOp, Pict, PictImpl, Recording, RecordingImpl, Sampling, TextAlignment, TextDecoration, TextLayout, TextOverflow, TextRun, TextWrap, Transform, VerticalAlignment};
+use crate::caribou::math::{IntPair, Matrix, Region, ScalarPair};
+use crate::caribou::window::SurfaceColorSpace;
+use crate::caribou::skia::font::{skia_cached_font, skia_measure_shaped, skia_shape_text};
+use crate::caribou::skia::runtime::{skia_build_picture, EXIT_PENDING, REDRAW_PENDING, REDRAW_PROXY};
 
 pub mod runtime;
 pub mod input;
+pub mod font;
+pub mod gpu;
 
 pub fn skia_render_batch(canvas: &mut Canvas, batch: Batch) {
     for op in batch.data().unwrap().iter() {
         match op {
-            BatchOp::Pict { transform, pict } => {
+            BatchOp::Pict { transform, pict, sampling } => {
                 let save = canvas.save();
                 skia_apply_transform(canvas, transform);
                 let image_guard = pict.data().unwrap();
                 let image = image_guard.get();
                 let image: &Image = image.downcast_ref().unwrap();
-                canvas.draw_image(image, (0.0, 0.0), None);
+                canvas.draw_image_with_sampling_options(
+                    image, (0.0, 0.0), skia_make_sampling(*sampling), None);
+                canvas.restore_to_count(save);
+            }
+            BatchOp::PictRegion { transform, pict, src_rect, dest, sampling } => {
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                let image_guard = pict.data().unwrap();
+                let image = image_guard.get();
+                let image: &Image = image.downcast_ref().unwrap();
+                // Strict keeps neighbouring cells of an atlas from bleeding in
+                canvas.draw_image_rect_with_sampling_options(
+                    image,
+                    Some((&skia_make_rect(src_rect), SrcRectConstraint::Strict)),
+                    skia_make_rect(dest),
+                    skia_make_sampling(*sampling),
+                    &Paint::default());
+                canvas.restore_to_count(save);
+            }
+            BatchOp::PictFit { transform, pict, dest, fit, sampling } => {
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                let image_guard = pict.data().unwrap();
+                let image = image_guard.get();
+                let image: &Image = image.downcast_ref().unwrap();
+                let size = ScalarPair::new(image.width() as f32, image.height() as f32);
+                let (src_rect, dest) = fit.resolve(size, *dest);
+                canvas.draw_image_rect_with_sampling_options(
+                    image,
+                    Some((&skia_make_rect(&src_rect), SrcRectConstraint::Fast)),
+                    skia_make_rect(&dest),
+                    skia_make_sampling(*sampling),
+                    &Paint::default());
+                canvas.restore_to_count(save);
+            }
+            BatchOp::PictNine { transform, pict, center, dest, sampling } => {
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                let image_guard = pict.data().unwrap();
+                let image = image_guard.get();
+                let image: &Image = image.downcast_ref().unwrap();
+                let center = IRect::from_xywh(
+                    center.origin.x as i32, center.origin.y as i32,
+                    center.size.x as i32, center.size.y as i32);
+                let filter = match sampling {
+                    Sampling::Nearest => FilterMode::Nearest,
+                    _ => FilterMode::Linear,
+                };
+                canvas.draw_image_nine(image, center, skia_make_rect(dest), filter, None);
                 canvas.restore_to_count(save);
             }
             BatchOp::Path { transform, path, brush } => {
@@ -39,6 +93,8 @@ pub fn skia_render_batch(canvas: &mut Canvas, batch: Batch) {
                 text,
                 font,
                 alignment,
+                layout,
+                decoration,
                 brush
             } => {
                 if text.is_empty() {
@@ -46,27 +102,60 @@ pub fn skia_render_batch(canvas: &mut Canvas, batch: Batch) {
                 }
                 let save = canvas.save();
                 skia_apply_transform(canvas, transform);
-                let (stroke, fill) = skia_make_paint(&brush);
-                let skia_font = skia_make_font(font);
-                //let skia_font = skia_default_font();
-                let (_, bounds) = skia_font
-                    .measure_str(&*text, None);
-                canvas.translate(match alignment {
-                    TextAlignment::Origin => (0.0, bounds.height()),
-                    TextAlignment::Center => (-bounds.width() / 2.0, bounds.height() / 2.0),
-                });
-                let blob = TextBlob::from_str(&*text, &skia_font).unwrap();
-                if let Material::Transparent = brush.stroke_mat {} else {
-                    canvas.draw_text_blob(&blob, (0.0, 0.0), &stroke);
+                skia_draw_text(canvas, text, font, alignment, layout, decoration, brush);
+                canvas.restore_to_count(save);
+            }
+            BatchOp::RichText { transform, runs, alignment, layout } => {
+                if runs.iter().all(|run| run.text.is_empty()) {
+                    continue;
                 }
-                if let Material::Transparent = brush.fill_mat {} else {
-                    canvas.draw_text_blob(&blob, (0.0, 0.0), &fill);
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                skia_draw_rich_text(canvas, runs, alignment, layout);
+                canvas.restore_to_count(save);
+            }
+            BatchOp::Shadow { transform, path, shadow } => {
+                if let Material::Transparent = shadow.color {
+                    continue;
                 }
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                canvas.translate((shadow.offset.x, shadow.offset.y));
+                let mut paint = Paint::default();
+                paint.set_anti_alias(true);
+                skia_set_material(&mut paint, shadow.color);
+                if shadow.blur_radius > 0.0 {
+                    // Skia's conventional conversion from a blur radius to a Gaussian sigma
+                    let sigma = shadow.blur_radius * 0.57735 + 0.5;
+                    paint.set_image_filter(image_filters::blur((sigma, sigma), None, None, None));
+                }
+                canvas.draw_path(&skia_make_path(path), &paint);
+                canvas.restore_to_count(save);
+            }
+            BatchOp::Clear { transform, region } => {
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                canvas.clip_rect(skia_make_rect(region), ClipOp::Intersect, true);
+                canvas.clear(Color::TRANSPARENT);
                 canvas.restore_to_count(save);
             }
-            BatchOp::Batch { transform, batch } => {
+            BatchOp::Recording { transform, recording } => {
+                let picture = recording.data().get();
+                let picture: &Picture = match picture.downcast_ref() {
+                    Some(picture) => picture,
+                    None => continue,
+                };
                 let save = canvas.save();
                 skia_apply_transform(canvas, transform);
+                canvas.draw_picture(picture, None, None);
+                canvas.restore_to_count(save);
+            }
+            BatchOp::Batch { transform, batch, filter } => {
+                let save = canvas.save();
+                skia_apply_transform(canvas, transform);
+                if let Some(filter) = filter {
+                    skia_apply_filter(canvas, *filter);
+                }
                 // println!("{:?}", canvas.local_to_device_as_3x3());
                 skia_render_batch(canvas, batch.clone());
                 canvas.restore_to_count(save);
@@ -75,6 +164,370 @@ pub fn skia_render_batch(canvas: &mut Canvas, batch: Batch) {
     }
 }
 
+/// A line of plain text positioned by its alignment.
+pub struct PlacedLine {
+    pub text: String,
+    pub x: f32,
+    pub baseline: f32,
+    pub width: f32,
+    /// Whether the spaces are stretched so that the line fills the layout width
+    pub justify: bool,
+}
+
+/// Break and position the lines of a text op. Clipping for `TextOverflow::Clip` is up to
+/// the caller.
+pub fn skia_layout_text(text: &str, skia_font: &skia_safe::Font, alignment: &TextAlignment,
+                        layout: &TextLayout) -> Vec<PlacedLine> {
+    let (spacing, metrics) = skia_font.metrics();
+    let line_height = spacing * layout.line_spacing;
+    let mut lines = skia_break_lines(text, skia_font, layout);
+    if let Some(room) = layout.width {
+        match layout.overflow {
+            TextOverflow::Visible | TextOverflow::Clip => {}
+            overflow => for line in lines.iter_mut() {
+                line.text = skia_ellipsize(&line.text, skia_font, room, overflow);
+            },
+        }
+    }
+    // Offsets of the first baseline for the vertical alignments, from real font metrics
+    let block_height = line_height * (lines.len() - 1) as f32 + metrics.descent - metrics.ascent;
+    let first_baseline = match alignment.vertical {
+        VerticalAlignment::Top => -metrics.ascent,
+        VerticalAlignment::Middle => -metrics.ascent - block_height / 2.0,
+        VerticalAlignment::Baseline => 0.0,
+        VerticalAlignment::Bottom => -metrics.ascent - block_height,
+    };
+    let room = layout.width.unwrap_or(0.0);
+    lines.into_iter().enumerate().map(|(index, line)| {
+        let width = skia_measure_shaped(&line.text, skia_font);
+        let justify = alignment.horizontal == HorizontalAlignment::Justify
+            && layout.width.is_some() && !line.ends_paragraph && line.text.contains(' ');
+        let x = match alignment.horizontal {
+            HorizontalAlignment::Left | HorizontalAlignment::Justify => 0.0,
+            HorizontalAlignment::Center => (room - width) / 2.0,
+            HorizontalAlignment::Right => room - width,
+        };
+        PlacedLine {
+            text: line.text,
+            x,
+            baseline: first_baseline + line_height * index as f32,
+            width: if justify { room } else { width },
+            justify,
+        }
+    }).collect()
+}
+
+pub fn skia_draw_text(canvas: &mut Canvas, text: &str, font: &Font, alignment: &TextAlignment,
+                      layout: &TextLayout, decoration: &TextDecoration, brush: &Brush) {
+    let (stroke, fill) = skia_make_paint(brush);
+    let skia_font = skia_make_font(font);
+    if let (Some(room), TextOverflow::Clip) = (layout.width, layout.overflow) {
+        canvas.clip_rect(Rect::new(0.0, f32::MIN / 2.0, room, f32::MAX / 2.0),
+                         ClipOp::Intersect, true);
+    }
+    let draw = |canvas: &mut Canvas, run: &str, origin: (f32, f32)| {
+        if let Some(blob) = skia_shape_text(run, &skia_font) {
+            if let Material::Transparent = brush.stroke_mat {} else {
+                canvas.draw_text_blob(&blob, origin, &stroke);
+            }
+            if let Material::Transparent = brush.fill_mat {} else {
+                canvas.draw_text_blob(&blob, origin, &fill);
+            }
+        }
+    };
+    for line in skia_layout_text(text, &skia_font, alignment, layout) {
+        if line.text.is_empty() {
+            continue;
+        }
+        skia_draw_text_decoration(canvas, &skia_font, decoration, brush,
+                                  (line.x, line.baseline), line.width);
+        if !line.justify {
+            draw(canvas, &line.text, (line.x, line.baseline));
+            continue;
+        }
+        let words: Vec<&str> = line.text.split(' ').collect();
+        let words_width = skia_measure_shaped(&words.concat(), &skia_font);
+        let gap = (line.width - words_width) / (words.len() - 1) as f32;
+        let mut x = line.x;
+        for word in words {
+            draw(canvas, word, (x, line.baseline));
+            x += skia_measure_shaped(word, &skia_font) + gap;
+        }
+    }
+}
+
+/// The area covered by the glyphs of a text op, in its own coordinates.
+pub fn skia_text_bounds(text: &str, font: &Font, alignment: &TextAlignment,
+                        layout: &TextLayout) -> Option<Region> {
+    let skia_font = skia_make_font(font);
+    let (_, metrics) = skia_font.metrics();
+    let bounds = skia_layout_text(text, &skia_font, alignment, layout).iter()
+        .filter(|line| !line.text.is_empty())
+        .map(|line| Region::origin_size(
+            (line.x, line.baseline + metrics.ascent).into(),
+            (line.width, metrics.descent - metrics.ascent).into()))
+        .reduce(|a, b| a.union(&b));
+    match (bounds, layout.width, layout.overflow) {
+        (Some(bounds), Some(room), TextOverflow::Clip) => bounds.intersection(
+            &Region::origin_size((0.0, bounds.origin.y).into(), (room, bounds.size.y).into())),
+        (bounds, _, _) => bounds,
+    }
+}
+
+/// A span of a rich text run positioned on its line.
+pub struct PlacedSpan {
+    pub run: usize,
+    pub text: String,
+    pub origin: (f32, f32),
+    pub advance: f32,
+    /// Extent of the span's line above (negative) and below its baseline
+    pub ascent: f32,
+    pub descent: f32,
+}
+
+/// Lays out the runs with per-line metrics taken from the largest font on each line.
+/// Wrapping happens between pieces of the runs; justified text is aligned to the left.
+pub fn skia_layout_rich_text(runs: &[TextRun], fonts: &[skia_safe::Font],
+                             alignment: &TextAlignment, layout: &TextLayout) -> Vec<PlacedSpan> {
+    let width_of = |run: usize, text: &str| skia_measure_shaped(text, &fonts[run]);
+    // Pieces are the units lines are broken between, a `None` piece being a newline
+    let mut pieces: Vec<Option<(usize, &str)>> = Vec::new();
+    for (index, run) in runs.iter().enumerate() {
+        for (n, paragraph) in run.text.split('\n').enumerate() {
+            if n > 0 {
+                pieces.push(None);
+            }
+            match layout.wrap {
+                TextWrap::None => pieces.push(Some((index, paragraph))),
+                TextWrap::Word => pieces.extend(paragraph.split_inclusive(' ')
+                    .map(|piece| Some((index, piece)))),
+                TextWrap::Character => pieces.extend(paragraph.char_indices()
+                    .map(|(i, c)| Some((index, &paragraph[i..i + c.len_utf8()])))),
+            }
+        }
+    }
+    let mut lines: Vec<Vec<(usize, String)>> = vec![vec![]];
+    let mut line_width = 0.0;
+    for piece in pieces {
+        let (run, text) = match piece {
+            Some(piece) => piece,
+            None => {
+                lines.push(vec![]);
+                line_width = 0.0;
+                continue;
+            }
+        };
+        let width = width_of(run, text);
+        let current = lines.last_mut().unwrap();
+        if let Some(room) = layout.width.filter(|_| layout.wrap != TextWrap::None) {
+            if !current.is_empty() && line_width + width_of(run, text.trim_end()) > room {
+                lines.push(vec![]);
+                line_width = 0.0;
+            }
+        }
+        let current = lines.last_mut().unwrap();
+        match current.last_mut() {
+            Some((last_run, last_text)) if *last_run == run => last_text.push_str(text),
+            _ => current.push((run, text.to_string())),
+        }
+        line_width += width;
+    }
+    // Vertical metrics of each line: (ascent, descent, advance to the next line)
+    let metrics: Vec<(f32, f32, f32)> = lines.iter().map(|line| {
+        let used: Vec<usize> = if line.is_empty() { vec![0] } else {
+            line.iter().map(|(run, _)| *run).collect()
+        };
+        used.iter().fold((0.0f32, 0.0f32, 0.0f32), |(ascent, descent, height), run| {
+            let (spacing, metrics) = fonts[*run].metrics();
+            (ascent.min(metrics.ascent), descent.max(metrics.descent),
+             height.max(spacing * layout.line_spacing))
+        })
+    }).collect();
+    let block_height: f32 = metrics.iter().map(|(_, _, height)| height).sum();
+    let top = match alignment.vertical {
+        VerticalAlignment::Top => 0.0,
+        VerticalAlignment::Middle => -block_height / 2.0,
+        VerticalAlignment::Baseline => metrics[0].0,
+        VerticalAlignment::Bottom => -block_height,
+    };
+    let room = layout.width.unwrap_or(0.0);
+    let mut spans = Vec::new();
+    let mut y = top;
+    for (line, (ascent, descent, height)) in lines.into_iter().zip(metrics.into_iter()) {
+        let baseline = y - ascent;
+        y += height;
+        let width: f32 = line.iter().map(|(run, text)| width_of(*run, text.trim_end())).sum();
+        let mut x = match alignment.horizontal {
+            HorizontalAlignment::Left | HorizontalAlignment::Justify => 0.0,
+            HorizontalAlignment::Center => (room - width) / 2.0,
+            HorizontalAlignment::Right => room - width,
+        };
+        for (run, text) in line {
+            let advance = width_of(run, &text);
+            spans.push(PlacedSpan { run, text, origin: (x, baseline), advance, ascent, descent });
+            x += advance;
+        }
+    }
+    spans
+}
+
+pub fn skia_draw_rich_text(canvas: &mut Canvas, runs: &[TextRun],
+                           alignment: &TextAlignment, layout: &TextLayout) {
+    let fonts: Vec<skia_safe::Font> = runs.iter().map(|run| skia_make_font(&run.font)).collect();
+    for span in skia_layout_rich_text(runs, &fonts, alignment, layout) {
+        let brush = &runs[span.run].brush;
+        let font = &fonts[span.run];
+        let (stroke, fill) = skia_make_paint(brush);
+        if let Some(blob) = skia_shape_text(&span.text, font) {
+            if let Material::Transparent = brush.stroke_mat {} else {
+                canvas.draw_text_blob(&blob, span.origin, &stroke);
+            }
+            if let Material::Transparent = brush.fill_mat {} else {
+                canvas.draw_text_blob(&blob, span.origin, &fill);
+            }
+        }
+        skia_draw_text_decoration(canvas, font, &runs[span.run].decoration, brush,
+                                  span.origin, span.advance);
+    }
+}
+
+pub fn skia_rich_text_bounds(runs: &[TextRun], alignment: &TextAlignment,
+                             layout: &TextLayout) -> Option<Region> {
+    if runs.is_empty() {
+        return None;
+    }
+    let fonts: Vec<skia_safe::Font> = runs.iter().map(|run| skia_make_font(&run.font)).collect();
+    skia_layout_rich_text(runs, &fonts, alignment, layout).iter()
+        .filter(|span| !span.text.is_empty())
+        .map(|span| Region::origin_size(
+            (span.origin.0, span.origin.1 + span.ascent).into(),
+            (span.advance, span.descent - span.ascent).into()))
+        .reduce(|a, b| a.union(&b))
+}
+
+/// Draw the underline and strikethrough of a span of text starting at `origin` on its baseline.
+pub fn skia_draw_text_decoration(canvas: &mut Canvas, font: &skia_safe::Font,
+                                 decoration: &TextDecoration, brush: &Brush,
+                                 origin: (f32, f32), width: f32) {
+    if decoration.is_none() || width <= 0.0 {
+        return;
+    }
+    let material = decoration.color.unwrap_or(match brush.fill_mat {
+        Material::Transparent => brush.stroke_mat,
+        fill => fill,
+    });
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+    paint.set_style(PaintStyle::Fill);
+    skia_set_material(&mut paint, material);
+    let (_, metrics) = font.metrics();
+    let (x, baseline) = origin;
+    let mut draw_line = |offset: Option<f32>, fallback_offset: f32, thickness: Option<f32>| {
+        let thickness = decoration.thickness.or(thickness).unwrap_or(font.size() / 14.0);
+        let y = baseline + offset.unwrap_or(fallback_offset);
+        canvas.draw_rect(Rect::from_xywh(x, y, width, thickness), &paint);
+    };
+    if decoration.underline {
+        draw_line(metrics.underline_position(), font.size() / 10.0,
+                  metrics.underline_thickness());
+    }
+    if decoration.strikethrough {
+        draw_line(metrics.strikeout_position(), metrics.ascent / 3.0,
+                  metrics.strikeout_thickness());
+    }
+}
+
+/// Shorten a line that is wider than `width` by replacing part of it with an ellipsis,
+/// keeping as many characters as fit.
+pub fn skia_ellipsize(text: &str, font: &skia_safe::Font, width: f32, overflow: TextOverflow) -> String {
+    const ELLIPSIS: &str = "\u{2026}";
+    if skia_measure_shaped(text, font) <= width {
+        return text.to_string();
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let shortened = |kept: usize| -> String {
+        let head: String = chars[..kept].iter().collect();
+        let tail: String = chars[chars.len() - kept..].iter().collect();
+        match overflow {
+            TextOverflow::EllipsisStart => ELLIPSIS.to_string() + tail.trim_start(),
+            TextOverflow::EllipsisMiddle => {
+                let head: String = chars[..(kept + 1) / 2].iter().collect();
+                let tail: String = chars[chars.len() - kept / 2..].iter().collect();
+                head.trim_end().to_string() + ELLIPSIS + tail.trim_start()
+            }
+            _ => head.trim_end().to_string() + ELLIPSIS,
+        }
+    };
+    // The width only grows with the number of kept characters, so search for the largest fit
+    let (mut low, mut high) = (0, chars.len());
+    while low < high {
+        let mid = (low + high + 1) / 2;
+        if skia_measure_shaped(&shortened(mid), font) <= width {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    shortened(low)
+}
+
+/// A line of text as drawn, after breaking.
+pub struct TextLine {
+    pub text: String,
+    /// Whether the line is the last one of its paragraph, i.e. not ended by wrapping
+    pub ends_paragraph: bool,
+}
+
+/// Split text into the lines it is drawn as, honoring newlines and the wrapping mode.
+pub fn skia_break_lines(text: &str, font: &skia_safe::Font, layout: &TextLayout) -> Vec<TextLine> {
+    let width = match (layout.width, layout.wrap) {
+        (Some(width), TextWrap::Word | TextWrap::Character) => width,
+        _ => return text.split('\n')
+            .map(|line| TextLine { text: line.to_string(), ends_paragraph: true })
+            .collect(),
+    };
+    let fits = |line: &str| skia_measure_shaped(line, font) <= width;
+    let wrapped = |line: &str| TextLine { text: line.trim_end().to_string(), ends_paragraph: false };
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let pieces: Vec<&str> = match layout.wrap {
+            TextWrap::Word => paragraph.split_inclusive(' ').collect(),
+            _ => paragraph.char_indices()
+                .map(|(i, c)| &paragraph[i..i + c.len_utf8()])
+                .collect(),
+        };
+        for piece in pieces {
+            let candidate = line.clone() + piece;
+            if fits(candidate.trim_end()) {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(wrapped(&line));
+                line = String::new();
+            }
+            if fits(piece.trim_end()) {
+                line.push_str(piece);
+                continue;
+            }
+            // A word wider than the line is broken between characters
+            for c in piece.chars() {
+                let mut candidate = line.clone();
+                candidate.push(c);
+                if !line.is_empty() && !fits(candidate.trim_end()) {
+                    lines.push(wrapped(&line));
+                    line = c.to_string();
+                } else {
+                    line = candidate;
+                }
+            }
+        }
+        lines.push(TextLine { text: line.trim_end().to_string(), ends_paragraph: true });
+    }
+    lines
+}
+
 pub fn skia_apply_transform(canvas: &mut Canvas, transform: &Transform) {
     canvas.translate((transform.translate.x,
                       transform.translate.y));
@@ -84,7 +537,84 @@ pub fn skia_apply_transform(canvas: &mut Canvas, transform: &Transform) {
                          true);
     }
     canvas.scale((transform.scale.x, transform.scale.y));
-    canvas.rotate(transform.rotate, None);
+    canvas.rotate(transform.rotate, Some(Point::new(transform.rotate_center.x,
+                                                     transform.rotate_center.y)));
+    if !transform.matrix.is_identity() {
+        canvas.concat(&skia_make_matrix(&transform.matrix));
+    }
+    if transform.opacity < 1.0 {
+        // Restored along with the rest of the canvas state by the caller
+        canvas.save_layer_alpha(None, (transform.opacity.max(0.0) * 255.0) as u32);
+    }
+}
+
+pub fn skia_make_matrix(matrix: &Matrix) -> skia_safe::Matrix {
+    let m = &matrix.values;
+    skia_safe::Matrix::new_all(m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8])
+}
+
+/// Render a batch on the CPU, without a window, returning unpremultiplied RGBA rows.
+pub fn skia_rasterize(batch: Batch, size: IntPair) -> Vec<u8> {
+    let mut surface = skia_rasterize_surface(batch, size);
+    let info = ImageInfo::new((size.x, size.y), ColorType::RGBA8888, AlphaType::Unpremul, None);
+    let row_bytes = size.x as usize * 4;
+    let mut pixels = vec![0u8; row_bytes * size.y as usize];
+    surface.read_pixels(&info, &mut pixels, row_bytes, (0, 0));
+    pixels
+}
+
+/// Render a batch on the CPU, without a window, encoded as PNG.
+pub fn skia_rasterize_png(batch: Batch, size: IntPair) -> Vec<u8> {
+    skia_rasterize_surface(batch, size).image_snapshot()
+        .encode_to_data(EncodedImageFormat::PNG)
+        .map(|data| data.as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+/// Draw the layers of a frame over the background on the CPU, `size` being in physical pixels.
+pub fn skia_capture_frame(
+    layers: Vec<Batch>, background: Material, size: IntPair, scale_factor: f32
+) -> Pict {
+    let mut surface = Surface::new_raster_n32_premul((size.x.max(1), size.y.max(1))).unwrap();
+    let canvas = surface.canvas();
+    canvas.clear(skia_make_color(background));
+    canvas.scale((scale_factor, scale_factor));
+    for layer in layers {
+        skia_render_batch(canvas, layer);
+    }
+    Pict::new(Box::new(SkiaPict { image: surface.image_snapshot() }))
+}
+
+pub fn skia_encode_png(pict: &Pict) -> Option<Vec<u8>> {
+    let image_guard = pict.data().unwrap();
+    let image = image_guard.get();
+    let image: &Image = image.downcast_ref()?;
+    image.encode_to_data(EncodedImageFormat::PNG)
+        .map(|data| data.as_bytes().to_vec())
+}
+
+fn skia_rasterize_surface(batch: Batch, size: IntPair) -> Surface {
+    let mut surface = Surface::new_raster_n32_premul((size.x.max(1), size.y.max(1))).unwrap();
+    let canvas = surface.canvas();
+    canvas.clear(Color::TRANSPARENT);
+    skia_render_batch(canvas, batch);
+    surface
+}
+
+/// Begin a layer applying the filter; restored along with the rest of the canvas state.
+pub fn skia_apply_filter(canvas: &mut Canvas, filter: Filter) {
+    match filter {
+        Filter::Blur(sigma) => {
+            let mut paint = Paint::default();
+            paint.set_image_filter(image_filters::blur((sigma, sigma), None, None, None));
+            canvas.save_layer(&SaveLayerRec::default().paint(&paint));
+        }
+        Filter::BackdropBlur(sigma) => {
+            if let Some(backdrop) = image_filters::blur((sigma, sigma), None, None, None) {
+                canvas.save_layer(&SaveLayerRec::default().backdrop(&backdrop));
+            }
+        }
+    }
 }
 
 pub fn skia_make_path(path: &Path) -> skia_safe::Path {
@@ -120,38 +650,125 @@ pub fn skia_make_path(path: &Path) -> skia_safe::Path {
                                     size.x, size.y),
                     None);
             }
+            PathOp::RoundRect(position, size, radius) => {
+                skia_path.add_round_rect(
+                    Rect::from_xywh(position.x, position.y,
+                                    size.x, size.y),
+                    (*radius, *radius),
+                    None);
+            }
             PathOp::Oval(position, size) => {
                 skia_path.add_oval(
                     Rect::from_xywh(position.x, position.y,
                                     size.x, size.y),
                     None);
             }
+            PathOp::Arc { oval, start_angle, sweep } => {
+                skia_path.add_arc(skia_make_rect(oval), *start_angle, *sweep);
+            }
+            PathOp::ArcTo { oval, start_angle, sweep } => {
+                skia_path.arc_to(skia_make_rect(oval), *start_angle, *sweep, false);
+            }
+            PathOp::ArcToTangent(pair1, pair2, radius) => {
+                skia_path.arc_to_tangent((pair1.x, pair1.y),
+                                         (pair2.x, pair2.y),
+                                         *radius);
+            }
+            PathOp::Polygon(points, closed) => {
+                let points: Vec<Point> = points.iter()
+                    .map(|pair| Point::new(pair.x, pair.y))
+                    .collect();
+                skia_path.add_poly(&points, *closed);
+            }
         }
     }
     skia_path
 }
 
+pub fn skia_path_contains(path: &Path, point: ScalarPair) -> bool {
+    skia_make_path(path).contains(Point::new(point.x, point.y))
+}
+
+pub fn skia_path_stroke_contains(path: &Path, point: ScalarPair, brush: &Brush) -> bool {
+    let (stroke_paint, _) = skia_make_paint(brush);
+    stroke_paint.get_fill_path(&skia_make_path(path), None, None)
+        .map_or(false, |outline| outline.contains(Point::new(point.x, point.y)))
+}
+
 pub fn skia_make_paint(brush: &Brush) -> (Paint, Paint) {
     let mut stroke_paint = Paint::default();
     stroke_paint.set_style(PaintStyle::Stroke);
     stroke_paint.set_anti_alias(true);
     stroke_paint.set_stroke_width(brush.stroke_width);
+    if let Some(dash) = brush.dash {
+        if dash.is_dotted() {
+            stroke_paint.set_stroke_cap(PaintCap::Round);
+        }
+        stroke_paint.set_path_effect(PathEffect::dash(&[dash.on, dash.off], dash.phase));
+    }
     let mut fill_paint = Paint::default();
     fill_paint.set_style(PaintStyle::Fill);
     fill_paint.set_anti_alias(true);
-    stroke_paint.set_color(match brush.stroke_mat {
-        Material::Transparent => Color::TRANSPARENT,
-        Material::Solid(r, g, b, a) => Color::from_argb(
-            (a * 255.0) as u8, (r * 255.0) as u8,
-            (g * 255.0) as u8, (b * 255.0) as u8),
-    });
-    fill_paint.set_color(match brush.fill_mat {
+    let blend_mode = skia_make_blend_mode(brush.blend_mode);
+    stroke_paint.set_blend_mode(blend_mode);
+    fill_paint.set_blend_mode(blend_mode);
+    skia_set_material(&mut stroke_paint, brush.stroke_mat);
+    skia_set_material(&mut fill_paint, brush.fill_mat);
+    (stroke_paint, fill_paint)
+}
+
+/// Set a paint to a material color, tagged as sRGB so that Skia converts it to the color
+/// space of the surface.
+pub fn skia_set_material(paint: &mut Paint, material: Material) {
+    let color = match material {
+        Material::Transparent => Color4f::new(0.0, 0.0, 0.0, 0.0),
+        Material::Solid(r, g, b, a) => Color4f::new(r, g, b, a),
+    };
+    paint.set_color4f(color, Some(&ColorSpace::new_srgb()));
+}
+
+/// The color space a surface is tagged with, `None` leaving colors unconverted.
+pub fn skia_make_color_space(color_space: SurfaceColorSpace) -> Option<ColorSpace> {
+    match color_space {
+        SurfaceColorSpace::Srgb => Some(ColorSpace::new_srgb()),
+        SurfaceColorSpace::DisplayP3 => ColorSpace::new_rgb(
+            &named_transfer_fn::SRGB, &named_gamut::DISPLAY_P3),
+        SurfaceColorSpace::Unmanaged => None,
+    }
+}
+
+pub fn skia_make_color(material: Material) -> Color {
+    match material {
         Material::Transparent => Color::TRANSPARENT,
         Material::Solid(r, g, b, a) => Color::from_argb(
             (a * 255.0) as u8, (r * 255.0) as u8,
             (g * 255.0) as u8, (b * 255.0) as u8),
-    });
-    (stroke_paint, fill_paint)
+    }
+}
+
+pub fn skia_make_rect(region: &Region) -> Rect {
+    Rect::from_xywh(region.origin.x, region.origin.y, region.size.x, region.size.y)
+}
+
+pub fn skia_make_blend_mode(blend_mode: BlendMode) -> skia_safe::BlendMode {
+    match blend_mode {
+        BlendMode::SrcOver => skia_safe::BlendMode::SrcOver,
+        BlendMode::Multiply => skia_safe::BlendMode::Multiply,
+        BlendMode::Screen => skia_safe::BlendMode::Screen,
+        BlendMode::Overlay => skia_safe::BlendMode::Overlay,
+        BlendMode::Darken => skia_safe::BlendMode::Darken,
+        BlendMode::Lighten => skia_safe::BlendMode::Lighten,
+        BlendMode::Clear => skia_safe::BlendMode::Clear,
+    }
+}
+
+pub fn skia_make_sampling(sampling: Sampling) -> SamplingOptions {
+    match sampling {
+        Sampling::Nearest => SamplingOptions::new(FilterMode::Nearest, MipmapMode::None),
+        Sampling::Linear => SamplingOptions::new(FilterMode::Linear, MipmapMode::None),
+        Sampling::Mipmap => SamplingOptions::new(FilterMode::Linear, MipmapMode::Linear),
+        Sampling::Cubic => CubicResampler::mitchell().into(),
+    }
 }
 
 #[derive(Debug)]
@@ -163,39 +780,275 @@ impl PictImpl for SkiaPict {
     fn get(&self) -> Box<dyn Any> {
         Box::new(self.image.clone())
     }
+
+    fn size(&self) -> ScalarPair {
+        ScalarPair::new(self.image.width() as f32, self.image.height() as f32)
+    }
+}
+
+#[derive(Debug)]
+pub struct SkiaRecording {
+    picture: Picture,
 }
 
-pub fn skia_read_pict(path: &str) -> Pict {
-    let mut img = File::open(path).unwrap();
+impl RecordingImpl for SkiaRecording {
+    fn get(&self) -> Box<dyn Any> {
+        Box::new(self.picture.clone())
+    }
+}
+
+pub fn skia_record_batch(batch: Batch) -> Recording {
+    let bounds = batch.bounds();
+    // Without known bounds nothing may be culled
+    let rect = bounds.as_ref().map_or(
+        Rect::new(f32::MIN / 2.0, f32::MIN / 2.0, f32::MAX / 2.0, f32::MAX / 2.0),
+        skia_make_rect);
+    let picture = skia_build_picture(rect, |canvas| skia_render_batch(canvas, batch.clone()));
+    Recording::new(Box::new(SkiaRecording { picture }), bounds)
+}
+
+pub fn skia_read_pict(path: &str) -> Result<Pict, ImageError> {
+    let mut img = File::open(path)?;
     let mut buf = Vec::new();
-    img.read_to_end(&mut buf).unwrap();
-    let mut codec = Codec::from_data(Data::new_copy(&buf)).unwrap();
-    let img = codec.get_image(None, None).unwrap();
-    Pict::new(Box::new(SkiaPict { image: img }))
+    img.read_to_end(&mut buf)?;
+    skia_decode_pict(&buf)
+}
+
+/// Decodes an encoded image (PNG, JPEG, WebP, ...) held in memory.
+pub fn skia_decode_pict(bytes: &[u8]) -> Result<Pict, ImageError> {
+    let mut codec = Codec::from_data(Data::new_copy(bytes)).ok_or(ImageError::UnsupportedFormat)?;
+    // Decoding into sRGB converts from any embedded ICC profile
+    let info = codec.info().with_color_space(ColorSpace::new_srgb());
+    let img = codec.get_image(info, None).map_err(|_| ImageError::Corrupt)?;
+    let img = skia_apply_origin(img, codec.origin());
+    // Mipmaps keep downscaled images from shimmering when sampled with `Sampling::Mipmap`
+    let img = img.with_default_mipmaps().unwrap_or(img);
+    Ok(Pict::new(Box::new(SkiaPict { image: img })))
+}
+
+/// The pixels of a pict as unpremultiplied RGBA rows, along with its size.
+pub fn skia_pict_pixels(pict: &Pict) -> Option<(Vec<u8>, IntPair)> {
+    let image_guard = pict.data().unwrap();
+    let image = image_guard.get();
+    let image: &Image = image.downcast_ref()?;
+    let size = IntPair::new(image.width(), image.height());
+    let info = ImageInfo::new((size.x, size.y), ColorType::RGBA8888, AlphaType::Unpremul, None);
+    let row_bytes = size.x as usize * 4;
+    let mut pixels = vec![0u8; row_bytes * size.y as usize];
+    if !image.read_pixels(&info, &mut pixels, row_bytes, (0, 0), CachingHint::Disallow) {
+        return None;
+    }
+    Some((pixels, size))
+}
+
+/// Rasterizes an SVG document to a pict of `size` pixels. Icons stay crisp at any DPI when
+/// the size is given in physical pixels, i.e. multiplied by the window's scale factor.
+pub fn skia_read_svg(path: &str, size: ScalarPair) -> Result<Pict, ImageError> {
+    let pixels = (size.x.ceil() as i32, size.y.ceil() as i32);
+    if pixels.0 <= 0 || pixels.1 <= 0 {
+        return Err(ImageError::EmptySize);
+    }
+    let mut file = File::open(path)?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    let mut dom = svg::Dom::from_bytes(&buf).map_err(|_| ImageError::Corrupt)?;
+    dom.set_container_size((size.x, size.y));
+    let mut surface = Surface::new_raster_n32_premul(pixels).ok_or(ImageError::EmptySize)?;
+    dom.render(surface.canvas());
+    Ok(Pict::new(Box::new(SkiaPict { image: surface.image_snapshot() })))
+}
+
+/// Bakes the EXIF orientation of a decoded image into its pixels.
+pub fn skia_apply_origin(image: Image, origin: EncodedOrigin) -> Image {
+    if origin == EncodedOrigin::TopLeft {
+        return image;
+    }
+    let (width, height) = (image.width(), image.height());
+    let size = if origin.swaps_width_height() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let mut surface = Surface::new_raster_n32_premul(size).unwrap();
+    let canvas = surface.canvas();
+    canvas.concat(&origin.to_matrix((width, height)));
+    canvas.draw_image(&image, (0.0, 0.0), None);
+    surface.image_snapshot()
 }
 
 pub fn skia_make_font(font: &Font) -> skia_safe::Font {
-    let mgr = FontMgr::default();
-    let style = FontStyle::new(
-        Weight::from(font.weight),
-        Width::NORMAL,
-        match font.slant {
-            FontSlant::Normal => Slant::Upright,
-            FontSlant::Italic => Slant::Italic,
-            FontSlant::Oblique => Slant::Oblique
-        });
-    let face = mgr
-        .match_family_style(&*font.family, style)
-        .unwrap();
-    skia_safe::Font::from_typeface(face, font.size)
+    skia_cached_font(font)
+}
+
+pub fn skia_measure_text(text: &str, font: &Font) -> f32 {
+    skia_measure_shaped(text, &skia_make_font(font))
 }
 
 pub fn skia_default_font() -> skia_safe::Font {
     skia_safe::Font::default()
 }
 
+/// Leave the event loop at the end of the current iteration, without asking `on_close_requested`.
+pub fn skia_request_exit() {
+    EXIT_PENDING.store(true, Ordering::SeqCst);
+    if let Some(proxy) = REDRAW_PROXY.lock().unwrap().as_ref() {
+        let _ = proxy.send_event(());
+    }
+}
+
 pub fn skia_request_redraw() {
-    unsafe {
-        SKIA_ENV.as_ref().unwrap_unchecked().windowed_context.window().request_redraw();
+    REDRAW_PENDING.store(true, Ordering::SeqCst);
+    // Wake the event loop in case it is waiting; before launch the flag alone is kept
+    if let Some(proxy) = REDRAW_PROXY.lock().unwrap().as_ref() {
+        let _ = proxy.send_event(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{skia_break_lines, skia_ellipsize, skia_layout_text, skia_make_font,
+                skia_measure_shaped, skia_rasterize, skia_rasterize_png, PlacedLine};
+    use crate::caribou::batch::{Batch, BatchOp, Brush, Font, HorizontalAlignment, Material, Path,
+                                PathOp, TextAlignment, TextLayout, TextOverflow, TextWrap,
+                                Transform, VerticalAlignment};
+    use crate::caribou::math::{IntPair, ScalarPair};
+
+    fn width_of(text: &str) -> f32 {
+        skia_measure_shaped(text, &skia_make_font(&Font::default()))
+    }
+
+    /// The text of the lines and whether they end their paragraph.
+    fn break_lines(text: &str, layout: &TextLayout) -> Vec<(String, bool)> {
+        skia_break_lines(text, &skia_make_font(&Font::default()), layout).into_iter()
+            .map(|line| (line.text, line.ends_paragraph))
+            .collect()
+    }
+
+    #[test]
+    fn unwrapped_text_breaks_at_newlines_only() {
+        assert_eq!(break_lines("a long line\nnext", &TextLayout::default()),
+                   vec![("a long line".to_string(), true), ("next".to_string(), true)]);
+    }
+
+    #[test]
+    fn words_wrap_at_spaces() {
+        let layout = TextLayout::wrapped(width_of("hello world") + 0.5, TextWrap::Word);
+        assert_eq!(break_lines("hello world again\nbye", &layout), vec![
+            ("hello world".to_string(), false),
+            ("again".to_string(), true),
+            ("bye".to_string(), true),
+        ]);
+    }
+
+    #[test]
+    fn words_wider_than_the_line_break_between_characters() {
+        let layout = TextLayout::wrapped(width_of("mmmm") + 0.5, TextWrap::Word);
+        assert_eq!(break_lines("mmmmmmmmmm", &layout), vec![
+            ("mmmm".to_string(), false),
+            ("mmmm".to_string(), false),
+            ("mm".to_string(), true),
+        ]);
+    }
+
+    #[test]
+    fn characters_wrap_anywhere() {
+        let layout = TextLayout::wrapped(width_of("mmm") + 0.5, TextWrap::Character);
+        assert_eq!(break_lines("mmmmmmm", &layout), vec![
+            ("mmm".to_string(), false),
+            ("mmm".to_string(), false),
+            ("m".to_string(), true),
+        ]);
+    }
+
+    fn ellipsize(text: &str, width: f32, overflow: TextOverflow) -> String {
+        skia_ellipsize(text, &skia_make_font(&Font::default()), width, overflow)
+    }
+
+    #[test]
+    fn fitting_text_is_not_ellipsized() {
+        let width = width_of("abcdefghij");
+        assert_eq!(ellipsize("abcdefghij", width, TextOverflow::EllipsisEnd), "abcdefghij");
+    }
+
+    #[test]
+    fn ellipsis_replaces_the_chosen_part() {
+        let text = "abcdefghij";
+        assert_eq!(ellipsize(text, width_of("abcd\u{2026}") + 0.5, TextOverflow::EllipsisEnd),
+                   "abcd\u{2026}");
+        assert_eq!(ellipsize(text, width_of("\u{2026}ghij") + 0.5, TextOverflow::EllipsisStart),
+                   "\u{2026}ghij");
+        assert_eq!(ellipsize(text, width_of("ab\u{2026}ij") + 0.5, TextOverflow::EllipsisMiddle),
+                   "ab\u{2026}ij");
+    }
+
+    fn layout_text(text: &str, horizontal: HorizontalAlignment,
+                   layout: &TextLayout) -> Vec<PlacedLine> {
+        let alignment = TextAlignment::new(horizontal, VerticalAlignment::Top);
+        skia_layout_text(text, &skia_make_font(&Font::default()), &alignment, layout)
+    }
+
+    #[test]
+    fn justified_lines_fill_the_width_except_the_last() {
+        let room = width_of("hello world") + 10.0;
+        let lines = layout_text("hello world again", HorizontalAlignment::Justify,
+                                &TextLayout::wrapped(room, TextWrap::Word));
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].justify);
+        assert_eq!(lines[0].width, room);
+        assert!(!lines[1].justify);
+        assert_eq!(lines[1].width, width_of("again"));
+        assert!(lines.iter().all(|line| line.x == 0.0));
+    }
+
+    #[test]
+    fn lines_align_within_the_width() {
+        let room = 200.0;
+        let layout = TextLayout::wrapped(room, TextWrap::Word);
+        let right = layout_text("text", HorizontalAlignment::Right, &layout);
+        assert!((right[0].x + right[0].width - room).abs() < 1e-3);
+        let center = layout_text("text", HorizontalAlignment::Center, &layout);
+        assert!((center[0].x * 2.0 + center[0].width - room).abs() < 1e-3);
+        let left = layout_text("text", HorizontalAlignment::Left, &layout);
+        assert_eq!(left[0].x, 0.0);
+    }
+
+    #[test]
+    fn lines_are_spaced_by_the_font() {
+        let font = skia_make_font(&Font::default());
+        let (spacing, _) = font.metrics();
+        let layout = TextLayout { line_spacing: 1.5, ..TextLayout::default() };
+        let lines = layout_text("one\ntwo", HorizontalAlignment::Left, &layout);
+        assert!((lines[1].baseline - lines[0].baseline - spacing * 1.5).abs() < 1e-3);
+    }
+
+    /// A 16x16 batch with an opaque red square from (4, 4) to (12, 12).
+    fn red_square() -> Batch {
+        let batch = Batch::new();
+        batch.add_op(BatchOp::Path {
+            transform: Transform::default(),
+            path: Path::from_vec(vec![PathOp::Rect(ScalarPair::new(4.0, 4.0),
+                                                   ScalarPair::new(8.0, 8.0))]),
+            brush: Brush::solid_fill(Material::Solid(1.0, 0.0, 0.0, 1.0)),
+        });
+        batch
+    }
+
+    #[test]
+    fn rasterized_rect_covers_its_pixels() {
+        let pixels = skia_rasterize(red_square(), IntPair::new(16, 16));
+        assert_eq!(pixels.len(), 16 * 16 * 4);
+        let pixel = |x: usize, y: usize| &pixels[(y * 16 + x) * 4..][..4];
+        for (x, y) in [(4, 4), (8, 8), (11, 11)] {
+            assert_eq!(pixel(x, y), [255, 0, 0, 255], "inside at ({}, {})", x, y);
+        }
+        for (x, y) in [(0, 0), (3, 8), (12, 8), (8, 15)] {
+            assert_eq!(pixel(x, y), [0, 0, 0, 0], "outside at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn rasterized_png_is_a_png() {
+        let png = skia_rasterize_png(red_square(), IntPair::new(16, 16));
+        assert!(png.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]));
     }
 }
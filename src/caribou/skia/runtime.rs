@@ -1,218 +1,617 @@
+use std::collections::VecDeque;
 use std::convert::Into;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use glutin::{ContextWrapper, GlProfile, PossiblyCurrent};
-use glutin::event_loop::{ControlFlow, EventLoop};
-use glutin::window::{Window, WindowBuilder};
-use gl::types::*;
-use glutin::dpi::Position;
-use glutin::event::{ElementState, Event, Ime, KeyboardInput, ModifiersState, MouseButton, ScanCode, VirtualKeyCode, WindowEvent};
+use glutin::event_loop::{ControlFlow, EventLoop, EventLoopProxy};
+use glutin::window::{Fullscreen, Icon, Window, WindowBuilder};
+use glutin::dpi::{LogicalSize, PhysicalPosition, PhysicalSize, Position};
+use glutin::event::{ElementState, Event, Ime, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, ScanCode, VirtualKeyCode, WindowEvent};
 use log::{info, warn};
-use skia_safe::gpu::{BackendRenderTarget, DirectContext, SurfaceOrigin};
-use skia_safe::gpu::gl::{Format, FramebufferInfo};
-use skia_safe::{Canvas, Color, ColorType, FontMgr, FontStyle, Matrix, Paint, PaintStyle, Picture, PictureRecorder, Point, Rect, Size, Surface, TextBlob, TextBlobBuilder, Vector};
+use skia_safe::{Canvas, Color, FontMgr, FontStyle, Matrix, Paint, PaintStyle, Picture, PictureRecorder, Point, Rect, Size, TextBlob, TextBlobBuilder, Vector};
 use crate::caribou::widgets::Layout;
 use crate::caribou::Caribou;
-use crate::caribou::batch::{BatchConsolidation, BatchOp, Brush, FontSlant, Material, Path, PathOp, TextAlignment, Transform};
-use crate::caribou::input::{Key, KeyEvent};
-use crate::caribou::math::IntPair;
-use crate::caribou::skia::input::gl_virtual_to_key;
-use crate::caribou::skia::skia_render_batch;
+use crate::caribou::anim::Animator;
+use crate::caribou::store::Store;
+use crate::caribou::batch::{BatchConsolidation, BatchOp, Brush, FontSlant, Material, Path, PathOp, Pict, TextAlignment, Transform};
+use crate::caribou::input::{CursorIcon, KeyEvent};
+use crate::caribou::math::{IntPair, ScalarPair};
+use crate::caribou::skia::input::{gl_cursor_icon, gl_modifiers, gl_virtual_to_key};
+use crate::caribou::skia::{skia_make_color, skia_pict_pixels, skia_render_batch};
+use crate::caribou::skia::gpu::{skia_make_target, SkiaTarget};
+use crate::caribou::theme::{ColorScheme, Theme as CaribouTheme};
+use crate::caribou::window::{FullscreenMode, RenderBackend, WindowState};
 
-type WindowedContext = ContextWrapper<PossiblyCurrent, Window>;
-
-pub fn skia_build_picture<F>(op: F) -> Picture where F: Fn(&mut Canvas) {
+/// Record drawing commands into a picture. `bounds` must contain everything drawn, as Skia
+/// skips pictures whose bounds lie outside the clip.
+pub fn skia_build_picture<F>(bounds: Rect, op: F) -> Picture where F: Fn(&mut Canvas) {
     let mut rec = PictureRecorder::new();
     {
-        let canvas = rec.begin_recording(
-            Rect::default(), None);
+        let canvas = rec.begin_recording(bounds, None);
         op(canvas);
     }
-    rec.finish_recording_as_picture(
-        Some(&Rect::new(0.0, 0.0, 1.0, 1.0))).unwrap()
+    rec.finish_recording_as_picture(Some(&bounds)).unwrap()
 }
 
 pub struct SkiaEnv {
-    pub(crate) surface: Surface,
-    pub(crate) gr_context: DirectContext,
-    pub(crate) windowed_context: WindowedContext,
+    pub(crate) target: Box<dyn SkiaTarget>,
+}
+
+impl SkiaEnv {
+    pub fn window(&self) -> &Window {
+        self.target.window()
+    }
 }
 
 pub(crate) static mut SKIA_ENV: Option<SkiaEnv> = None;
 
-static mut MOUSE_POS: IntPair = IntPair::new(0, 0);
+/// Set by redraw requests from any thread, consumed once per loop iteration
+pub(crate) static REDRAW_PENDING: AtomicBool = AtomicBool::new(false);
+/// Set by `Caribou::quit`, ends the event loop at the end of the iteration
+pub(crate) static EXIT_PENDING: AtomicBool = AtomicBool::new(false);
+/// Wakes the event loop, present once it is running
+pub(crate) static REDRAW_PROXY: Mutex<Option<EventLoopProxy<()>>> = Mutex::new(None);
 
-pub fn skia_gl_set_env(env: SkiaEnv) {
+/// Pixels scrolled per line reported by line-based wheels
+const SCROLL_LINE_HEIGHT: f32 = 20.0;
+
+pub fn skia_set_env(env: SkiaEnv) {
     unsafe {
         SKIA_ENV = Some(env);
     }
 }
 
-pub fn skia_gl_get_env() -> &'static mut SkiaEnv {
+pub fn skia_get_env() -> &'static mut SkiaEnv {
     unsafe {
         SKIA_ENV.as_mut().unwrap()
     }
 }
 
-static mut KEY_RETAIN_VEC: Vec<Key> = Vec::new();
+fn skia_sync_window_state(gl_window: &Window) {
+    let instance = Caribou::instance();
+    let window = &instance.window;
+    let size = gl_window.inner_size();
+    // Minimization is reported as a zero-sized resize on most platforms
+    let state = if size.width == 0 || size.height == 0 {
+        WindowState::Minimized
+    } else if gl_window.is_maximized() {
+        WindowState::Maximized
+    } else {
+        WindowState::Normal
+    };
+    if window.state.set_distinct(state) {
+        window.on_state_changed.broadcast(state);
+    }
+    // The platform may leave fullscreen on its own, e.g. when switching applications
+    let fullscreen = skia_fullscreen_mode(gl_window);
+    if window.fullscreen.set_distinct(fullscreen) {
+        window.on_fullscreen_changed.broadcast(fullscreen);
+    }
+    let monitor = gl_window.current_monitor().and_then(|m| m.name());
+    if *window.monitor.get() != monitor {
+        window.monitor.set(monitor.clone());
+        window.on_monitor_changed.broadcast(monitor);
+    }
+}
 
-pub fn glut_cb_key_retain_vec() -> &'static mut Vec<Key> {
+/// The light or dark preference of the platform. Where winit cannot tell, the appearance of
+/// the application is asked on macOS, and the desktop settings elsewhere.
+fn skia_detect_color_scheme(gl_window: &Window) -> ColorScheme {
+    #[cfg(target_os = "windows")]
+    {
+        use glutin::platform::windows::WindowExtWindows;
+        return match gl_window.theme() {
+            glutin::window::Theme::Light => ColorScheme::Light,
+            glutin::window::Theme::Dark => ColorScheme::Dark,
+        };
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let _ = gl_window;
+        return skia_macos_color_scheme();
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        let _ = gl_window;
+        skia_desktop_color_scheme()
+            .or_else(skia_gnome_color_scheme)
+            .unwrap_or_else(|| match std::env::var("GTK_THEME") {
+                Ok(name) if name.to_ascii_lowercase().contains("dark") => ColorScheme::Dark,
+                _ => ColorScheme::Light,
+            })
+    }
+}
+
+/// The effective appearance of the application, which follows the system unless overridden.
+#[cfg(target_os = "macos")]
+fn skia_macos_color_scheme() -> ColorScheme {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use objc::{class, msg_send, sel, sel_impl};
+    use objc::runtime::{Object, BOOL, NO};
     unsafe {
-        &mut KEY_RETAIN_VEC
+        let app: *mut Object = msg_send![class!(NSApplication), sharedApplication];
+        // Appearances are available from macOS 10.14 on, which had no dark mode before
+        let supported: BOOL = msg_send![app, respondsToSelector: sel!(effectiveAppearance)];
+        if supported == NO {
+            return ColorScheme::Light;
+        }
+        let appearance: *mut Object = msg_send![app, effectiveAppearance];
+        if appearance.is_null() {
+            return ColorScheme::Light;
+        }
+        let name: *mut Object = msg_send![appearance, name];
+        let name: *const c_char = msg_send![name, UTF8String];
+        if !name.is_null() && CStr::from_ptr(name).to_string_lossy().contains("Dark") {
+            ColorScheme::Dark
+        } else {
+            ColorScheme::Light
+        }
     }
 }
 
-pub fn skia_bootstrap() {
-    let el = EventLoop::new();
-    let wb = WindowBuilder::new().with_title("Caribou");
+/// The `color-scheme` setting of the XDG desktop portal, if it is running and states a
+/// preference.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn skia_desktop_color_scheme() -> Option<ColorScheme> {
+    let output = std::process::Command::new("gdbus")
+        .args(["call", "--session",
+            "--dest", "org.freedesktop.portal.Desktop",
+            "--object-path", "/org/freedesktop/portal/desktop",
+            "--method", "org.freedesktop.portal.Settings.Read",
+            "org.freedesktop.appearance", "color-scheme"])
+        .output().ok()
+        .filter(|output| output.status.success())?;
+    // The reply looks like `(<<uint32 1>>,)`, 1 preferring dark and 2 light
+    let reply = String::from_utf8_lossy(&output.stdout);
+    match reply.trim().trim_start_matches("(<<uint32 ").chars().next()? {
+        '1' => Some(ColorScheme::Dark),
+        '2' => Some(ColorScheme::Light),
+        _ => None,
+    }
+}
 
-    let cb = glutin::ContextBuilder::new()
-        .with_depth_buffer(0)
-        .with_stencil_buffer(8)
-        .with_pixel_format(24, 8)
-        .with_gl_profile(GlProfile::Core);
-    #[cfg(not(feature = "wayland"))]
-        let cb = cb
-        .with_double_buffer(Some(true));
+/// The `color-scheme` key of the GNOME interface settings, for desktops without the portal.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn skia_gnome_color_scheme() -> Option<ColorScheme> {
+    let output = std::process::Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "color-scheme"])
+        .output().ok()
+        .filter(|output| output.status.success())?;
+    match String::from_utf8_lossy(&output.stdout).trim().trim_matches('\'') {
+        "prefer-dark" => Some(ColorScheme::Dark),
+        "prefer-light" => Some(ColorScheme::Light),
+        _ => None,
+    }
+}
 
-    let windowed_context = cb.build_windowed(wb, &el).unwrap();
+/// Record the platform's color scheme, switching the theme along if the window follows it.
+fn skia_apply_color_scheme(scheme: ColorScheme) {
+    let instance = Caribou::instance();
+    let window = &instance.window;
+    if !window.color_scheme.set_distinct(scheme) {
+        return;
+    }
+    if window.follow_color_scheme.is_true() {
+        Caribou::set_theme(CaribouTheme::for_scheme(scheme));
+    }
+    window.on_color_scheme_changed.broadcast(scheme);
+}
 
-    let windowed_context = unsafe { windowed_context.make_current().unwrap() };
-    let pixel_format = windowed_context.get_pixel_format();
+/// Update the physical and logical window sizes and size the root and the overlay to fill the
+/// window, laying the tree out again when they changed.
+fn skia_sync_window_size(gl_window: &Window) {
+    let instance = Caribou::instance();
+    let window = &instance.window;
+    let physical = gl_window.inner_size();
+    let scale_factor = gl_window.scale_factor() as f32;
+    let size = IntPair::new(physical.width as i32, physical.height as i32);
+    let logical_size = size.to_scalar().times(1.0 / scale_factor);
+    let changed = window.size.set_distinct(size) | window.logical_size.set_distinct(logical_size);
+    Caribou::root_component().size.set_distinct(logical_size);
+    Caribou::overlay().size.set_distinct(logical_size);
+    if changed {
+        Caribou::relayout();
+    }
+}
+
+/// A window builder reflecting the window properties set before launch.
+fn skia_make_window_builder() -> WindowBuilder {
+    let instance = Caribou::instance();
+    let window = &instance.window;
+    let mut wb = WindowBuilder::new()
+        .with_title(window.title.get().as_str())
+        .with_resizable(window.resizable.is_true())
+        .with_visible(window.visible.is_true())
+        .with_maximized(*window.state.get() == WindowState::Maximized);
+    if let Some(min_size) = *window.min_size.get() {
+        wb = wb.with_min_inner_size(LogicalSize::new(min_size.x, min_size.y));
+    }
+    if let Some(max_size) = *window.max_size.get() {
+        wb = wb.with_max_inner_size(LogicalSize::new(max_size.x, max_size.y));
+    }
+    if let Some(pict) = window.icon.get().as_ref() {
+        wb = wb.with_window_icon(skia_make_icon(pict));
+    }
+    let size = *window.size.get();
+    if size.x > 0 && size.y > 0 {
+        wb = wb.with_inner_size(PhysicalSize::new(size.x as u32, size.y as u32));
+    }
+    // The origin doubles as "unset", leaving the placement to the platform
+    let position = *window.position.get();
+    if position != IntPair::new(0, 0) {
+        wb = wb.with_position(PhysicalPosition::new(position.x, position.y));
+    }
+    wb
+}
 
-    println!(
-        "Pixel format of the window's GL context: {:#?}",
-        pixel_format
-    );
+/// Forward later changes of the window properties to the native window. Changes the backend
+/// makes itself while syncing are recognized as no-ops.
+fn skia_listen_window() {
+    let instance = Caribou::instance();
+    let window = &instance.window;
+    window.title.listen(Box::new(|title| {
+        skia_get_env().window().set_title(title);
+    }));
+    window.size.listen(Box::new(|size| {
+        // Minimized windows report a zero size, which is not a size to restore to
+        if size.x <= 0 || size.y <= 0 {
+            return;
+        }
+        let gl_window = skia_get_env().window();
+        let size = PhysicalSize::new(size.x as u32, size.y as u32);
+        if gl_window.inner_size() != size {
+            gl_window.set_inner_size(size);
+        }
+    }));
+    window.position.listen(Box::new(|position| {
+        let gl_window = skia_get_env().window();
+        let position = PhysicalPosition::new(position.x, position.y);
+        if gl_window.outer_position().ok() != Some(position) {
+            gl_window.set_outer_position(position);
+        }
+    }));
+    window.min_size.listen(Box::new(|min_size| {
+        skia_get_env().window()
+            .set_min_inner_size(min_size.map(|size| LogicalSize::new(size.x, size.y)));
+    }));
+    window.max_size.listen(Box::new(|max_size| {
+        skia_get_env().window()
+            .set_max_inner_size(max_size.map(|size| LogicalSize::new(size.x, size.y)));
+    }));
+    window.resizable.listen(Box::new(|resizable| {
+        skia_get_env().window().set_resizable(*resizable);
+    }));
+    window.visible.listen(Box::new(|visible| {
+        skia_get_env().window().set_visible(*visible);
+    }));
+    window.state.listen(Box::new(|state| {
+        let gl_window = skia_get_env().window();
+        match state {
+            WindowState::Normal => {
+                gl_window.set_minimized(false);
+                if gl_window.is_maximized() {
+                    gl_window.set_maximized(false);
+                }
+            }
+            WindowState::Maximized => {
+                gl_window.set_minimized(false);
+                if !gl_window.is_maximized() {
+                    gl_window.set_maximized(true);
+                }
+            }
+            WindowState::Minimized => gl_window.set_minimized(true),
+        }
+    }));
+    window.fullscreen.listen(Box::new(|mode| skia_apply_fullscreen(*mode)));
+    window.icon.listen(Box::new(|icon| {
+        let icon = icon.as_ref().and_then(skia_make_icon);
+        skia_get_env().window().set_window_icon(icon);
+    }));
+}
 
-    gl::load_with(|s| windowed_context.get_proc_address(s));
+/// Move the input method window below the caret of the focused widget, if it moved since
+/// the last call.
+fn skia_place_ime(gl_window: &Window, anchor: &mut Option<ScalarPair>) {
+    let current = Caribou::caret_area()
+        .map(|area| area.origin + ScalarPair::new(0.0, area.size.y));
+    if current != *anchor {
+        if let Some(position) = current {
+            gl_window.set_ime_position(Position::Logical((position.x as f64, position.y as f64).into()));
+        }
+        *anchor = current;
+    }
+}
 
-    let mut gr_context = DirectContext::new_gl(None, None).unwrap();
+fn skia_fullscreen_mode(gl_window: &Window) -> FullscreenMode {
+    match gl_window.fullscreen() {
+        None => FullscreenMode::Windowed,
+        Some(Fullscreen::Borderless(_)) => FullscreenMode::Borderless,
+        Some(Fullscreen::Exclusive(_)) => FullscreenMode::Exclusive,
+    }
+}
 
-    let fb_info = {
-        let mut fboid: GLint = 0;
-        unsafe { gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut fboid) };
+fn skia_apply_fullscreen(mode: FullscreenMode) {
+    let gl_window = skia_get_env().window();
+    if skia_fullscreen_mode(gl_window) == mode {
+        return;
+    }
+    let fullscreen = match mode {
+        FullscreenMode::Windowed => None,
+        FullscreenMode::Borderless => Some(Fullscreen::Borderless(gl_window.current_monitor())),
+        FullscreenMode::Exclusive => gl_window.current_monitor()
+            .and_then(|monitor| monitor.video_modes().max_by_key(|video_mode| {
+                let size = video_mode.size();
+                (size.width * size.height, video_mode.refresh_rate_millihertz())
+            }))
+            .map(Fullscreen::Exclusive),
+    };
+    gl_window.set_fullscreen(fullscreen);
+}
 
-        FramebufferInfo {
-            fboid: fboid.try_into().unwrap(),
-            format: Format::RGBA8.into(),
+fn skia_make_icon(pict: &Pict) -> Option<Icon> {
+    let (pixels, size) = skia_pict_pixels(pict)?;
+    match Icon::from_rgba(pixels, size.x as u32, size.y as u32) {
+        Ok(icon) => Some(icon),
+        Err(err) => {
+            warn!("Unusable window icon: {}", err);
+            None
         }
-    };
+    }
+}
+
+/// Time available to build and render a frame
+const FRAME_BUDGET: Duration = Duration::from_millis(16);
+/// Consecutive over-budget frames before frames start being skipped
+const OVERRUN_LIMIT: u32 = 3;
+
+/// Keeps slow frames from piling up. Once rendering repeatedly exceeds the budget, pending
+/// redraws are coalesced so that at least one frame's worth of time passes between frames;
+/// animations are driven by time, so they just skip the intermediate frames.
+struct FramePacer {
+    pending: bool,
+    last_frame: Instant,
+    last_cost: Duration,
+    overruns: u32,
+}
+
+impl FramePacer {
+    fn new() -> FramePacer {
+        FramePacer {
+            pending: false,
+            last_frame: Instant::now(),
+            last_cost: Duration::ZERO,
+            overruns: 0,
+        }
+    }
+
+    fn is_adaptive(&self) -> bool {
+        self.overruns >= OVERRUN_LIMIT
+    }
+
+    fn record(&mut self, frame_start: Instant) {
+        self.last_cost = frame_start.elapsed();
+        self.last_frame = Instant::now();
+        if self.last_cost > FRAME_BUDGET {
+            self.overruns = self.overruns.saturating_add(1);
+        } else {
+            self.overruns = 0;
+        }
+    }
 
+    fn next_frame(&self) -> Instant {
+        self.last_frame + self.last_cost
+    }
 
-    windowed_context
-        .window();
-
-    fn create_surface(
-        windowed_context: &WindowedContext,
-        fb_info: &FramebufferInfo,
-        gr_context: &mut DirectContext,
-    ) -> Surface {
-        let pixel_format = windowed_context.get_pixel_format();
-        let size = windowed_context.window().inner_size();
-        let backend_render_target = BackendRenderTarget::new_gl(
-            (
-                size.width.try_into().unwrap(),
-                size.height.try_into().unwrap(),
-            ),
-            pixel_format.multisampling.map(|s| s.try_into().unwrap()),
-            pixel_format.stencil_bits.try_into().unwrap(),
-            *fb_info,
-        );
-        Surface::from_backend_render_target(
-            gr_context,
-            &backend_render_target,
-            SurfaceOrigin::BottomLeft,
-            ColorType::RGBA8888,
-            None,
-            None,
-        )
-            .unwrap()
+    fn should_draw(&self, now: Instant) -> bool {
+        !self.is_adaptive() || now >= self.next_frame()
     }
+}
+
+pub fn skia_bootstrap() {
+    let requested = match std::env::var("CARIBOU_BACKEND") {
+        Ok(name) => RenderBackend::from_name(&name).unwrap_or_else(|| {
+            warn!("Unknown CARIBOU_BACKEND {:?}", name);
+            *Caribou::instance().window.backend.get()
+        }),
+        Err(_) => *Caribou::instance().window.backend.get(),
+    };
+    let el = EventLoop::new();
+    let wb = skia_make_window_builder();
+    // Fullscreen needs the monitor the window ends up on, so it is applied once it exists
+    let fullscreen = *Caribou::instance().window.fullscreen.get();
+
+    let (target, backend) = skia_make_target(requested, wb, &el);
+    Caribou::instance().window.backend.set(backend);
 
-    let mut surface = create_surface(&windowed_context, &fb_info, &mut gr_context);
-    let sf = windowed_context.window().scale_factor() as f32;
+    let sf = target.window().scale_factor() as f32;
     //println!("{}", sf);
+    {
+        let instance = Caribou::instance();
+        let window = &instance.window;
+        let gl_window = target.window();
+        window.scale_factor.set(sf);
+        skia_sync_window_size(gl_window);
+        if let Ok(position) = gl_window.outer_position() {
+            window.position.set_distinct(IntPair::new(position.x, position.y));
+        }
+        skia_sync_window_state(gl_window);
+        skia_apply_color_scheme(skia_detect_color_scheme(gl_window));
+    }
 
-    windowed_context.window().set_ime_allowed(true);
-    windowed_context.window().set_ime_position(Position::Logical((100.0, 100.0).into()));
+    target.window().set_ime_allowed(true);
 
     let mut frame = 0;
 
-    // Guarantee the drop order inside the FnMut closure. `WindowedContext` _must_ be dropped after
-    // `DirectContext`.
-    //
-    // https://github.com/rust-skia/rust-skia/issues/476
-    skia_gl_set_env(SkiaEnv {
-        surface,
-        gr_context,
-        windowed_context,
-    });
+    skia_set_env(SkiaEnv { target });
+
+    skia_listen_window();
+    skia_apply_fullscreen(fullscreen);
+
+    *REDRAW_PROXY.lock().unwrap() = Some(el.create_proxy());
+    let mut pacer = FramePacer::new();
+    let mut cursor = CursorIcon::Arrow;
+    let mut ime_anchor = None;
+    // IME text arrives as `Ime::Commit`, which is the only source used for it. Some platforms
+    // also echo the committed characters as `ReceivedCharacter`, and may report the raw keys
+    // of a composition as characters too, so characters are ignored while a preedit is shown
+    // and those repeating a commit are swallowed until the events of the iteration are done.
+    let mut composing = false;
+    let mut committed: VecDeque<char> = VecDeque::new();
+    let mut last_frame: Option<Instant> = None;
+    let mut frame_wanted = false;
+    let mut modifiers = ModifiersState::empty();
 
     el.run(move |event, _, control_flow| {
-        let env = skia_gl_get_env();
-        *control_flow = ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(16));
+        let env = skia_get_env();
 
         #[allow(deprecated)]
         match event {
-            Event::LoopDestroyed => {}
+            Event::LoopDestroyed => {
+                *REDRAW_PROXY.lock().unwrap() = None;
+            }
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::Resized(physical_size) => {
-                    env.surface =
-                        create_surface(&env.windowed_context, &fb_info, &mut env.gr_context);
-                    env.windowed_context.resize(physical_size)
+                    if physical_size.width > 0 && physical_size.height > 0 {
+                        env.target.resize(physical_size);
+                    }
+                    skia_sync_window_size(env.window());
+                    let instance = Caribou::instance();
+                    instance.window.on_resized.broadcast(*instance.window.size.get());
+                    skia_sync_window_state(env.window());
+                }
+                WindowEvent::Moved(position) => {
+                    let position = IntPair::new(position.x, position.y);
+                    let instance = Caribou::instance();
+                    instance.window.position.set(position);
+                    instance.window.on_moved.broadcast(position);
+                    skia_sync_window_state(env.window());
+                }
+                WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                    // The new monitor gets a surface of its own pixel size
+                    if new_inner_size.width > 0 && new_inner_size.height > 0 {
+                        env.target.resize(*new_inner_size);
+                    }
+                    let scale_factor = scale_factor as f32;
+                    let instance = Caribou::instance();
+                    instance.window.scale_factor.set(scale_factor);
+                    skia_sync_window_size(env.window());
+                    instance.window.on_scale_factor_changed.broadcast(scale_factor);
+                    Caribou::notify_dpi_changed(scale_factor);
+                    skia_sync_window_state(env.window());
+                    env.window().request_redraw();
+                }
+                WindowEvent::HoveredFile(path) => Caribou::route_file_hover(path),
+                WindowEvent::HoveredFileCancelled => Caribou::route_file_hover_cancelled(),
+                WindowEvent::DroppedFile(path) => Caribou::route_file_drop(path),
+                WindowEvent::CloseRequested => {
+                    if Caribou::instance().window.on_close_requested.none_false() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                WindowEvent::ReceivedCharacter(c) => {
+                    if composing {
+                        return;
+                    }
+                    if committed.front() == Some(&c) {
+                        committed.pop_front();
+                        return;
+                    }
+                    committed.clear();
+                    if Caribou::dispatch_char(c) {
+                        env.window().request_redraw();
+                    }
+                }
+                WindowEvent::ModifiersChanged(state) => modifiers = state,
+                WindowEvent::ThemeChanged(theme) => {
+                    skia_apply_color_scheme(match theme {
+                        glutin::window::Theme::Light => ColorScheme::Light,
+                        glutin::window::Theme::Dark => ColorScheme::Dark,
+                    });
+                }
+                WindowEvent::Focused(focused) => {
+                    let instance = Caribou::instance();
+                    instance.window.focused.set_distinct(focused);
+                    if focused {
+                        instance.window.on_focus.broadcast();
+                    } else {
+                        // Releases happening elsewhere never reach the window
+                        Caribou::release_all_keys();
+                        modifiers = ModifiersState::empty();
+                        instance.window.on_blur.broadcast();
+                    }
+                    env.window().request_redraw();
                 }
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                 WindowEvent::KeyboardInput {
                     input:
                     KeyboardInput {
-                        scancode,
+                        state,
                         virtual_keycode,
-                        modifiers,
                         ..
                     },
                     ..
                 } => {
-                    println!("Keyboard input: {:?}", virtual_keycode);
-                    if modifiers.logo() {
+                    if modifiers.logo() && state == ElementState::Pressed {
                         if let Some(VirtualKeyCode::Q) = virtual_keycode {
-                            *control_flow = ControlFlow::Exit;
+                            if Caribou::instance().window.on_close_requested.none_false() {
+                                *control_flow = ControlFlow::Exit;
+                            }
                         }
                     }
                     if let Some(vir) = virtual_keycode {
                         let key = gl_virtual_to_key(vir);
-                        let ret_vec = glut_cb_key_retain_vec();
-                        if ret_vec.contains(&key) {
-                            ret_vec.retain(|x| *x != key);
-                            Caribou::instance().on_key_up.broadcast(KeyEvent {
-                                key,
-                                modifiers: vec![]
-                            });
-                        } else {
-                            ret_vec.push(key);
-                            Caribou::instance().on_key_down.broadcast(KeyEvent {
-                                key,
-                                modifiers: vec![]
-                            });
+                        match state {
+                            ElementState::Pressed => {
+                                Caribou::dispatch_key_down(KeyEvent {
+                                    key,
+                                    modifiers: gl_modifiers(modifiers),
+                                    // The OS repeats presses of held keys without releases
+                                    repeat: Caribou::is_key_down(key),
+                                });
+                            }
+                            ElementState::Released => {
+                                Caribou::dispatch_key_up(KeyEvent {
+                                    key,
+                                    modifiers: gl_modifiers(modifiers),
+                                    repeat: false,
+                                });
+                            }
                         }
                     }
                     frame += 1;
-                    env.windowed_context.window().request_redraw();
+                    env.window().request_redraw();
                 }
+                // Widgets are entered along with the first move of the cursor
                 WindowEvent::CursorEntered { .. } => {
                     println!("Cursor entered");
-                    Caribou::root_component().on_mouse_enter.broadcast();
                 }
                 WindowEvent::CursorLeft { .. } => {
                     println!("Cursor left");
-                    Caribou::root_component().on_mouse_leave.broadcast();
+                    Caribou::route_mouse_leave();
                 }
                 WindowEvent::CursorMoved {
                     position,
                     modifiers,
                     ..
                 } => {
-                    Caribou::root_component().on_mouse_move.broadcast(
-                        (position.x as i32, position.y as i32).into());
+                    let scale_factor = Caribou::scale_factor() as f64;
+                    Caribou::route_mouse_move(((position.x / scale_factor) as i32,
+                                               (position.y / scale_factor) as i32).into());
+                    let icon = Caribou::cursor_at(Caribou::mouse_position().to_scalar());
+                    if icon != cursor {
+                        cursor = icon;
+                        env.window().set_cursor_icon(gl_cursor_icon(icon));
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let delta = match delta {
+                        MouseScrollDelta::LineDelta(x, y) =>
+                            ScalarPair::new(x, y).times(SCROLL_LINE_HEIGHT),
+                        MouseScrollDelta::PixelDelta(pos) =>
+                            ScalarPair::new(pos.x as f32, pos.y as f32)
+                                .times(1.0 / Caribou::scale_factor()),
+                    };
+                    Caribou::route_scroll(delta);
                 }
                 WindowEvent::MouseInput {
                     state,
@@ -224,15 +623,34 @@ pub fn skia_bootstrap() {
                         MouseButton::Left => {
                             match state {
                                 ElementState::Pressed => {
-                                    Caribou::root_component().on_primary_down.broadcast();
+                                    Caribou::route_primary_down();
+                                }
+                                ElementState::Released => {
+                                    Caribou::route_primary_up();
+                                }
+                            }
+                        }
+                        MouseButton::Right => {
+                            match state {
+                                ElementState::Pressed => {
+                                    Caribou::route_secondary_down();
                                 }
                                 ElementState::Released => {
-                                    Caribou::root_component().on_primary_up.broadcast();
+                                    Caribou::route_secondary_up();
                                 }
                             }
                         }
-                        MouseButton::Right => {}
-                        MouseButton::Middle => {}
+                        MouseButton::Middle => {
+                            match state {
+                                ElementState::Pressed => {
+                                    Caribou::route_tertiary_down();
+                                }
+                                ElementState::Released => {
+                                    Caribou::route_tertiary_up();
+                                }
+                            }
+                        }
+                        // Side buttons have no widget events of their own
                         MouseButton::Other(_) => {}
                     }
                 }
@@ -241,30 +659,73 @@ pub fn skia_bootstrap() {
                         println!("Ime enabled");
                     }
                     Ime::Preedit(pre, pos) => {
-                        env.windowed_context.window()
-                            .set_ime_position(Position::Logical((100.0, 100.0).into()));
+                        composing = !pre.is_empty();
+                        skia_place_ime(env.window(), &mut ime_anchor);
                         println!("Ime preedit: {:?} {:?}", pre, pos);
                     }
-                    Ime::Commit(str) => {
-                        println!("Ime commit: {:?}", str);
+                    Ime::Commit(text) => {
+                        composing = false;
+                        committed.extend(text.chars());
+                        Caribou::dispatch_commit(text);
+                        env.window().request_redraw();
+                    }
+                    Ime::Disabled => {
+                        composing = false;
+                        committed.clear();
                     }
-                    Ime::Disabled => {}
                 }
                 _ => (),
             },
+            Event::MainEventsCleared => {
+                if EXIT_PENDING.swap(false, Ordering::SeqCst) {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                // Echoes of a commit come with it, so later characters are real input again
+                committed.clear();
+                let flushed = Store::flush();
+                let paused = {
+                    let window = &Caribou::instance().window;
+                    *window.pause_when_unfocused.get() && !*window.focused.get()
+                };
+                let animating = !paused && (Animator::tick() || frame_wanted);
+                let requested = REDRAW_PENDING.swap(false, Ordering::SeqCst);
+                pacer.pending |= flushed || animating || requested;
+                let now = Instant::now();
+                // Sleep until something asks for a frame, except while animations need ticking
+                *control_flow = if animating {
+                    ControlFlow::WaitUntil(now + FRAME_BUDGET)
+                } else {
+                    ControlFlow::Wait
+                };
+                if pacer.pending {
+                    if pacer.should_draw(now) {
+                        pacer.pending = false;
+                        env.window().request_redraw();
+                    } else {
+                        *control_flow = ControlFlow::WaitUntil(pacer.next_frame());
+                    }
+                }
+            }
             Event::RedrawRequested(_) => {
-                {
-                    let canvas = env.surface.canvas();
-                    canvas.clear(Color::WHITE);
+                let frame_start = Instant::now();
+                let delta = last_frame.map_or(Duration::ZERO, |last| frame_start - last);
+                last_frame = Some(frame_start);
+                frame_wanted = Caribou::instance().on_frame.any_true(delta);
+                env.target.draw_frame(&mut |canvas| {
+                    canvas.clear(skia_make_color(Caribou::theme().material("window.background")));
                     canvas.reset_matrix();
-                    // canvas.scale((1.25, 1.25)); //TODO: DPI awareness
+                    let scale_factor = Caribou::scale_factor();
+                    canvas.scale((scale_factor, scale_factor));
                     canvas.save();
-                    skia_render_batch(canvas, Caribou::root_component().on_draw
-                            .broadcast().consolidate());
+                    skia_render_batch(canvas, Caribou::root_component().draw().consolidate());
+                    // Popups are drawn above everything else
+                    skia_render_batch(canvas, Caribou::overlay().draw().consolidate());
                     canvas.restore();
-                }
-                env.surface.canvas().flush();
-                env.windowed_context.swap_buffers().unwrap();
+                });
+                // The caret may have moved along with whatever was redrawn
+                skia_place_ime(env.window(), &mut ime_anchor);
+                pacer.record(frame_start);
             }
             _ => (),
         }
@@ -0,0 +1,60 @@
+use std::rc::Rc;
+use crate::caribou::math::ScalarPair;
+use crate::caribou::widget::WidgetInner;
+use crate::caribou::widgets::{ScrollViewData, TextFieldData};
+
+/// The user-adjustable state of a widget tree, to persist between sessions. Serializable
+/// with the `serde` feature.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WidgetState {
+    pub position: ScalarPair,
+    pub size: ScalarPair,
+    pub enabled: bool,
+    pub opacity: f32,
+    pub rotation: f32,
+    /// Contents of a text field
+    pub text: Option<String>,
+    /// Offset of a scroll view
+    pub scroll_offset: Option<ScalarPair>,
+    pub children: Vec<WidgetState>,
+    pub content: Option<Box<WidgetState>>,
+}
+
+impl WidgetInner {
+    pub fn snapshot_state(&self) -> WidgetState {
+        WidgetState {
+            position: *self.position.get(),
+            size: *self.size.get(),
+            enabled: *self.enabled.get(),
+            opacity: *self.opacity.get(),
+            rotation: *self.rotation.get(),
+            text: self.with_data(|data: &Rc<TextFieldData>| data.text.get_cloned()).ok(),
+            scroll_offset: self.with_data(|data: &Rc<ScrollViewData>| *data.offset.get()).ok(),
+            children: self.children.get().iter().map(|child| child.snapshot_state()).collect(),
+            content: self.content.get_cloned().map(|content| Box::new(content.snapshot_state())),
+        }
+    }
+
+    /// Apply a snapshot taken from a tree of the same shape. Children are matched by index,
+    /// and parts of the snapshot without a counterpart are skipped.
+    pub fn restore_state(&self, state: &WidgetState) {
+        self.position.set(state.position);
+        self.size.set(state.size);
+        self.enabled.set(state.enabled);
+        self.opacity.set(state.opacity);
+        self.rotation.set(state.rotation);
+        if let Some(text) = &state.text {
+            let _ = self.with_data(|data: &Rc<TextFieldData>| data.text.set(text.clone()));
+        }
+        if let Some(offset) = state.scroll_offset {
+            let _ = self.with_data(|data: &Rc<ScrollViewData>| data.offset.set(offset));
+        }
+        for (child, child_state) in self.children.get_cloned().iter().zip(&state.children) {
+            child.restore_state(child_state);
+        }
+        if let (Some(content), Some(content_state)) = (self.content.get_cloned(), &state.content) {
+            content.restore_state(content_state);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use crate::caribou::Caribou;
+use crate::caribou::dispatch::Task;
+use crate::caribou::property::{Listener, Property, PropertyInit};
+use crate::caribou::widget::{create_widget, Widget};
+
+thread_local! {
+    static MARKER: Widget = create_widget();
+    static ENTRIES: RefCell<HashMap<&'static str, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Mutations posted from any thread, applied on the UI thread by `Store::flush`.
+static QUEUE: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+/// A typed name for a piece of application state.
+pub struct StateKey<T> {
+    pub name: &'static str,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> StateKey<T> {
+    pub const fn new(name: &'static str) -> Self {
+        StateKey { name, marker: PhantomData }
+    }
+}
+
+impl<T> Clone for StateKey<T> {
+    fn clone(&self) -> Self {
+        StateKey::new(self.name)
+    }
+}
+
+impl<T> Copy for StateKey<T> {}
+
+/// Application-wide state living on the UI thread, observable like any widget property.
+pub struct Store;
+
+impl Store {
+    /// The property backing a key, created with the default value on first use.
+    pub fn property<T: Default + Clone + 'static>(key: StateKey<T>) -> Property<T> {
+        ENTRIES.with(|entries| {
+            entries.borrow_mut()
+                .entry(key.name)
+                .or_insert_with(|| {
+                    let property: Property<T> = MARKER.with(|marker| marker.init_default_property());
+                    Box::new(property)
+                })
+                .downcast_ref::<Property<T>>()
+                .unwrap_or_else(|| panic!("state `{}` holds a value of another type", key.name))
+                .clone()
+        })
+    }
+
+    pub fn get<T: Default + Clone + 'static>(key: StateKey<T>) -> T {
+        Store::property(key).get_cloned()
+    }
+
+    pub fn set<T: Default + Clone + 'static>(key: StateKey<T>, value: T) {
+        Store::property(key).set(value);
+    }
+
+    pub fn listen<T: Default + Clone + 'static>(key: StateKey<T>, listener: Box<dyn Fn(&T)>) -> Listener<T> {
+        Store::property(key).listen(listener)
+    }
+
+    /// Keep a widget property updated with the value of a key.
+    pub fn bind<T: Default + Clone + 'static>(key: StateKey<T>, target: &Property<T>) -> Listener<T> {
+        let source = Store::property(key);
+        target.set(source.get_cloned());
+        let target = target.clone();
+        source.listen(Box::new(move |value| target.set(value.clone())))
+    }
+
+    /// Queue a change from any thread; it is applied on the UI thread before the next frame.
+    pub fn post<T: Default + Clone + Send + 'static>(key: StateKey<T>, value: T) {
+        Store::post_task(Box::new(move || Store::set(key, value)));
+    }
+
+    /// Queue work from any thread to run on the UI thread before the next frame.
+    pub fn post_task(task: Task) {
+        QUEUE.lock().unwrap().push(task);
+        // Wake the idle event loop so the task gets run
+        Caribou::request_redraw();
+    }
+
+    /// Apply the queued changes, returning whether there were any.
+    pub fn flush() -> bool {
+        let tasks: Vec<Task> = std::mem::take(&mut *QUEUE.lock().unwrap());
+        let applied = !tasks.is_empty();
+        for task in tasks {
+            task();
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+    use crate::caribou::property::Property;
+    use super::{StateKey, Store};
+
+    const COUNT: StateKey<i32> = StateKey::new("count");
+
+    #[test]
+    fn flush_applies_changes_posted_from_other_threads() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let record = seen.clone();
+        Store::listen(COUNT, Box::new(move |value| record.borrow_mut().push(*value)));
+        std::thread::spawn(|| {
+            Store::post(COUNT, 1);
+            Store::post(COUNT, 2);
+        }).join().unwrap();
+        assert_eq!(Store::get(COUNT), 0);
+        assert!(Store::flush());
+        assert_eq!(Store::get(COUNT), 2);
+        assert_eq!(*seen.borrow(), vec![1, 2]);
+        assert!(!Store::flush());
+    }
+
+    #[test]
+    fn bound_property_follows_the_state() {
+        Store::set(COUNT, 3);
+        let target = Property::new(0, Weak::new());
+        Store::bind(COUNT, &target);
+        assert_eq!(*target.get(), 3);
+        Store::set(COUNT, 4);
+        assert_eq!(*target.get(), 4);
+    }
+}
@@ -0,0 +1,529 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Brush, Font, Material};
+use crate::caribou::property::Property;
+use crate::caribou::widget::{Widget, WidgetAcquire, WidgetRefer};
+
+/// A state of a widget a selector can require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StyleState {
+    Hover,
+    Pressed,
+    Focused,
+    Disabled,
+}
+
+impl StyleState {
+    pub fn from_name(name: &str) -> Option<StyleState> {
+        match name {
+            "hover" => Some(StyleState::Hover),
+            "pressed" => Some(StyleState::Pressed),
+            "focused" => Some(StyleState::Focused),
+            "disabled" => Some(StyleState::Disabled),
+            _ => None,
+        }
+    }
+}
+
+/// Picks widgets by their `kind`, their `name` and the states they are in, written like
+/// `Button#save:hover`. Every part is optional; `*` matches any kind.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Selector {
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub states: Vec<StyleState>,
+}
+
+impl Selector {
+    pub fn parse(text: &str) -> Result<Selector, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("missing selector".to_string());
+        }
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        let mut selector = Selector::default();
+        let mut rest = text;
+        if let Some(stripped) = rest.strip_prefix('*') {
+            rest = stripped;
+        } else {
+            let end = rest.find(|c: char| !is_ident(c)).unwrap_or(rest.len());
+            if end > 0 {
+                selector.kind = Some(rest[..end].to_string());
+            }
+            rest = &rest[end..];
+        }
+        while let Some(marker) = rest.chars().next() {
+            let start = marker.len_utf8();
+            let end = rest[start..].find(|c: char| !is_ident(c))
+                .map_or(rest.len(), |end| end + start);
+            let ident = &rest[start..end];
+            if ident.is_empty() {
+                return Err(format!("malformed selector `{}`", text));
+            }
+            match marker {
+                '#' => selector.name = Some(ident.to_string()),
+                ':' => selector.states.push(StyleState::from_name(ident)
+                    .ok_or_else(|| format!("unknown state `{}`", ident))?),
+                _ => return Err(format!("malformed selector `{}`", text)),
+            }
+            rest = &rest[end..];
+        }
+        Ok(selector)
+    }
+
+    pub fn matches(&self, widget: &Widget) -> bool {
+        self.kind.as_ref().map_or(true, |kind| *widget.kind.get() == kind.as_str())
+            && self.name.as_ref().map_or(true, |name| *widget.name.get() == *name)
+            && self.states.iter().all(|state| is_in_state(widget, *state))
+    }
+
+    /// Names outweigh states, which outweigh kinds.
+    fn specificity(&self) -> (bool, usize, bool) {
+        (self.name.is_some(), self.states.len(), self.kind.is_some())
+    }
+}
+
+/// A part of a widget's appearance a style sheet can declare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StyledPart {
+    Background,
+    Foreground,
+    Border,
+    BorderWidth,
+    CornerRadius,
+    Opacity,
+    Font,
+}
+
+/// A value a style rule gives a widget property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Declaration {
+    /// Fill material of `background`
+    Background(Material),
+    /// Fill material of `foreground`, e.g. the caption of a button
+    Foreground(Material),
+    /// Stroke material of `border`
+    Border(Material),
+    BorderWidth(f32),
+    CornerRadius(f32),
+    Opacity(f32),
+    FontFamily(String),
+    FontSize(f32),
+    FontWeight(i32),
+}
+
+impl Declaration {
+    /// The part of the appearance the declaration sets.
+    pub fn part(&self) -> StyledPart {
+        match self {
+            Declaration::Background(_) => StyledPart::Background,
+            Declaration::Foreground(_) => StyledPart::Foreground,
+            Declaration::Border(_) => StyledPart::Border,
+            Declaration::BorderWidth(_) => StyledPart::BorderWidth,
+            Declaration::CornerRadius(_) => StyledPart::CornerRadius,
+            Declaration::Opacity(_) => StyledPart::Opacity,
+            Declaration::FontFamily(_) | Declaration::FontSize(_) | Declaration::FontWeight(_) =>
+                StyledPart::Font,
+        }
+    }
+
+    /// Parse a `property: value` pair such as `background: #336699`.
+    pub fn parse(property: &str, value: &str) -> Result<Declaration, String> {
+        let value = value.trim();
+        let number = || value.trim_end_matches("px").trim().parse::<f32>()
+            .map_err(|_| format!("`{}` is not a number", value));
+        Ok(match property.trim() {
+            "background" => Declaration::Background(parse_material(value)?),
+            "foreground" => Declaration::Foreground(parse_material(value)?),
+            "border" => Declaration::Border(parse_material(value)?),
+            "border-width" => Declaration::BorderWidth(number()?),
+            "corner-radius" => Declaration::CornerRadius(number()?),
+            "opacity" => Declaration::Opacity(number()?),
+            "font-family" => Declaration::FontFamily(value.trim_matches('"').to_string()),
+            "font-size" => Declaration::FontSize(number()?),
+            "font-weight" => Declaration::FontWeight(number()? as i32),
+            other => return Err(format!("unknown property `{}`", other)),
+        })
+    }
+}
+
+/// `transparent`, `#rrggbb` or `#rrggbbaa`.
+fn parse_material(value: &str) -> Result<Material, String> {
+    if value == "transparent" {
+        return Ok(Material::Transparent);
+    }
+    let hex = value.strip_prefix('#')
+        .filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii())
+        .ok_or_else(|| format!("`{}` is not a color", value))?;
+    let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16)
+        .map(|channel| channel as f32 / 255.0)
+        .map_err(|_| format!("`{}` is not a color", value));
+    let alpha = if hex.len() == 8 { channel(3)? } else { 1.0 };
+    Ok(Material::Solid(channel(0)?, channel(1)?, channel(2)?, alpha))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleRule {
+    pub selector: Selector,
+    pub declarations: Vec<Declaration>,
+}
+
+/// Rules giving properties to the widgets their selectors pick. Where rules disagree, the
+/// more specific selector wins, then the later rule.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StyleSheet {
+    pub rules: Vec<StyleRule>,
+}
+
+/// Why a style sheet could not be loaded.
+#[derive(Debug)]
+pub enum StyleSheetError {
+    Io(std::io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl Display for StyleSheetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StyleSheetError::Io(error) => write!(f, "cannot read style sheet: {}", error),
+            StyleSheetError::Syntax { line, message } =>
+                write!(f, "style sheet line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for StyleSheetError {}
+
+impl From<std::io::Error> for StyleSheetError {
+    fn from(error: std::io::Error) -> Self {
+        StyleSheetError::Io(error)
+    }
+}
+
+impl StyleSheet {
+    pub fn new() -> StyleSheet {
+        StyleSheet::default()
+    }
+
+    pub fn with_rule(mut self, selector: Selector, declarations: Vec<Declaration>) -> StyleSheet {
+        self.rules.push(StyleRule { selector, declarations });
+        self
+    }
+
+    /// Parse rules written like CSS, e.g.
+    ///
+    /// ```text
+    /// /* Comments are allowed */
+    /// Button, TextField:focused { border: #3366cc; border-width: 2 }
+    /// #title { font-size: 18; font-weight: 700 }
+    /// ```
+    pub fn parse(text: &str) -> Result<StyleSheet, StyleSheetError> {
+        let text = strip_comments(text);
+        let line_at = |offset: usize| text[..offset].matches('\n').count() + 1;
+        let syntax = |offset: usize, message: String| StyleSheetError::Syntax {
+            line: line_at(offset), message,
+        };
+        let mut sheet = StyleSheet::new();
+        let mut offset = 0;
+        while let Some(open) = text[offset..].find('{').map(|open| offset + open) {
+            let close = text[open..].find('}').map(|close| open + close)
+                .ok_or_else(|| syntax(open, "unclosed rule".to_string()))?;
+            let mut declarations = vec![];
+            let mut start = open + 1;
+            for pair in text[open + 1..close].split(';') {
+                if !pair.trim().is_empty() {
+                    let at = start + (pair.len() - pair.trim_start().len());
+                    let (property, value) = pair.split_once(':')
+                        .ok_or_else(|| syntax(at, format!("expected `property: value`, \
+                            found `{}`", pair.trim())))?;
+                    declarations.push(Declaration::parse(property, value)
+                        .map_err(|message| syntax(at, message))?);
+                }
+                start += pair.len() + 1;
+            }
+            let mut start = offset;
+            for selector in text[offset..open].split(',') {
+                // Errors point at the selector itself rather than at the end of the last rule
+                let at = start + (selector.len() - selector.trim_start().len());
+                start += selector.len() + 1;
+                let selector = Selector::parse(selector)
+                    .map_err(|message| syntax(at, message))?;
+                sheet.rules.push(StyleRule { selector, declarations: declarations.clone() });
+            }
+            offset = close + 1;
+        }
+        let rest = &text[offset..];
+        if !rest.trim().is_empty() {
+            let at = offset + (rest.len() - rest.trim_start().len());
+            return Err(syntax(at, "expected `{` after the selector".to_string()));
+        }
+        Ok(sheet)
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<StyleSheet, StyleSheetError> {
+        StyleSheet::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The declarations of all rules matching the widget, the winning ones last.
+    pub fn declarations_for(&self, widget: &Widget) -> Vec<&Declaration> {
+        let mut rules: Vec<&StyleRule> = self.rules.iter()
+            .filter(|rule| rule.selector.matches(widget))
+            .collect();
+        rules.sort_by_key(|rule| rule.selector.specificity());
+        rules.iter().flat_map(|rule| rule.declarations.iter()).collect()
+    }
+}
+
+/// Blank out `/* */` comments, keeping line breaks so that errors point at the right line.
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        let end = rest[start..].find("*/").map_or(rest.len(), |end| start + end + 2);
+        stripped.extend(rest[start..end].chars().map(|c| if c == '\n' { '\n' } else { ' ' }));
+        rest = &rest[end..];
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Pointer states of a styled widget, the parts the style sheet declares for it, and the
+/// values its properties had before the style sheet took them over.
+#[derive(Default)]
+struct StyleRecord {
+    hovered: Cell<bool>,
+    pressed: Cell<bool>,
+    declared: RefCell<Vec<StyledPart>>,
+    base: RefCell<StyleBase>,
+}
+
+#[derive(Default)]
+struct StyleBase {
+    background: Option<Brush>,
+    foreground: Option<Brush>,
+    border: Option<Brush>,
+    corner_radius: Option<f32>,
+    opacity: Option<f32>,
+    font: Option<Option<Font>>,
+}
+
+fn is_in_state(widget: &Widget, state: StyleState) -> bool {
+    let flag = |flag: fn(&StyleRecord) -> bool| widget.with_data(flag).unwrap_or(false);
+    match state {
+        StyleState::Hover => flag(|record| record.hovered.get()),
+        StyleState::Pressed => flag(|record| record.pressed.get()),
+        StyleState::Focused => Caribou::instance().focused_component.get()
+            .ptr_eq(&widget.refer()),
+        StyleState::Disabled => widget.enabled.is_false(),
+    }
+}
+
+/// Give the widget the properties the current style sheet declares for it, handing back those
+/// it no longer declares. Properties a style sheet has taken over should not be set elsewhere.
+pub fn apply_style_sheet(widget: &Widget) {
+    let sheet = Caribou::style_sheet();
+    if !widget.has_data::<StyleRecord>() {
+        if sheet.rules.is_empty() {
+            return;
+        }
+        track_states(widget);
+    }
+    let mut background = None;
+    let mut foreground = None;
+    let mut border = None;
+    let mut corner_radius = None;
+    let mut opacity = None;
+    let mut font = None;
+    let declarations = sheet.declarations_for(widget);
+    let declared = declarations.iter().map(|declaration| declaration.part()).collect();
+    widget.with_data(|record: &StyleRecord| record.declared.replace(declared)).unwrap();
+    for declaration in declarations {
+        let current = |styled: &Option<Brush>, property: &Property<Brush>|
+            styled.unwrap_or_else(|| *property.get());
+        match declaration {
+            Declaration::Background(material) => background = Some(Brush {
+                fill_mat: *material, ..current(&background, &widget.background)
+            }),
+            Declaration::Foreground(material) => foreground = Some(Brush {
+                fill_mat: *material, ..current(&foreground, &widget.foreground)
+            }),
+            Declaration::Border(material) => border = Some(Brush {
+                stroke_mat: *material, ..current(&border, &widget.border)
+            }),
+            Declaration::BorderWidth(width) => border = Some(Brush {
+                stroke_width: *width, ..current(&border, &widget.border)
+            }),
+            Declaration::CornerRadius(radius) => corner_radius = Some(*radius),
+            Declaration::Opacity(value) => opacity = Some(*value),
+            Declaration::FontFamily(family) => font = Some(Font {
+                family: Arc::new(family.clone()),
+                ..font.unwrap_or_else(|| widget.font.get_cloned().unwrap_or_default())
+            }),
+            Declaration::FontSize(size) => font = Some(Font {
+                size: *size, ..font.unwrap_or_else(|| widget.font.get_cloned().unwrap_or_default())
+            }),
+            Declaration::FontWeight(weight) => font = Some(Font {
+                weight: *weight, ..font.unwrap_or_else(|| widget.font.get_cloned().unwrap_or_default())
+            }),
+        }
+    }
+    // Taken out while the properties are set, as their listeners may look at the widget
+    let mut base = widget.with_data(|record: &StyleRecord| record.base.take()).unwrap();
+    take_over(&widget.background, &mut base.background, background);
+    take_over(&widget.foreground, &mut base.foreground, foreground);
+    take_over(&widget.border, &mut base.border, border);
+    take_over(&widget.corner_radius, &mut base.corner_radius, corner_radius);
+    take_over(&widget.opacity, &mut base.opacity, opacity);
+    take_over(&widget.font, &mut base.font, font.map(Some));
+    widget.with_data(|record: &StyleRecord| record.base.replace(base)).unwrap();
+    widget.invalidate();
+}
+
+/// Whether the current style sheet declares the part for the widget, making the widget's
+/// own value stand even where it is transparent or zero.
+pub fn is_styled(widget: &Widget, part: StyledPart) -> bool {
+    widget.with_data(|record: &StyleRecord| record.declared.borrow().contains(&part))
+        .unwrap_or(false)
+}
+
+/// Set a styled value, remembering the value it replaces, or restore that value once the
+/// property is no longer styled.
+fn take_over<T: Clone + 'static>(property: &Property<T>, base: &mut Option<T>, styled: Option<T>) {
+    match styled {
+        Some(value) => {
+            if base.is_none() {
+                *base = Some(property.get_cloned());
+            }
+            property.set(value);
+        }
+        None => {
+            if let Some(value) = base.take() {
+                property.set(value);
+            }
+        }
+    }
+}
+
+/// Restyle the widget whenever one of the states selectors look at changes.
+fn track_states(widget: &Widget) {
+    widget.insert_data(StyleRecord::default());
+    let set_flag = |comp: &Widget, flag: fn(&StyleRecord) -> &Cell<bool>, value: bool| {
+        let changed = comp.with_data(|record: &StyleRecord| flag(record).replace(value) != value);
+        if changed.unwrap_or(false) {
+            apply_style_sheet(comp);
+        }
+    };
+    widget.on_mouse_enter.subscribe(Box::new(move |comp| {
+        set_flag(&comp, |record| &record.hovered, true);
+    }));
+    widget.on_mouse_leave.subscribe(Box::new(move |comp| {
+        set_flag(&comp, |record| &record.pressed, false);
+        set_flag(&comp, |record| &record.hovered, false);
+    }));
+    widget.on_primary_down.subscribe(Box::new(move |comp| {
+        set_flag(&comp, |record| &record.pressed, true);
+    }));
+    widget.on_primary_up.subscribe(Box::new(move |comp| {
+        set_flag(&comp, |record| &record.pressed, false);
+    }));
+    let back = widget.refer();
+    widget.enabled.listen(Box::new(move |_| {
+        if let Some(comp) = back.acquire() {
+            apply_style_sheet(&comp);
+        }
+    }));
+    track_focus();
+}
+
+thread_local! {
+    static FOCUS_TRACKED: Cell<bool> = Cell::new(false);
+}
+
+/// Restyle the widgets losing and gaining focus, listening once for all styled widgets.
+fn track_focus() {
+    if FOCUS_TRACKED.with(|tracked| tracked.replace(true)) {
+        return;
+    }
+    Caribou::instance().focused_component.listen_change(Box::new(|change| {
+        for widget in [change.old.acquire(), change.new.acquire()].into_iter().flatten() {
+            if widget.has_data::<StyleRecord>() {
+                apply_style_sheet(&widget);
+            }
+        }
+    }));
+}
+
+impl Caribou {
+    pub fn style_sheet() -> Rc<StyleSheet> {
+        Caribou::instance().style_sheet.get_cloned()
+    }
+
+    /// Replace the style sheet and restyle every widget with it.
+    pub fn set_style_sheet(sheet: StyleSheet) {
+        Caribou::instance().style_sheet.set(Rc::new(sheet));
+        crate::caribou::widgets::restyle(&Caribou::root_component());
+        crate::caribou::widgets::restyle(&Caribou::overlay());
+    }
+
+    /// Read a style sheet from a file and use it, keeping the current one if it is malformed.
+    pub fn load_style_sheet(path: impl AsRef<Path>) -> Result<(), StyleSheetError> {
+        Caribou::set_style_sheet(StyleSheet::from_path(path)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Declaration, Selector, StyleSheet, StyleSheetError, StyleState};
+
+    #[test]
+    fn selector_with_every_part() {
+        let selector = Selector::parse("Button#save:hover:pressed").unwrap();
+        assert_eq!(selector.kind.as_deref(), Some("Button"));
+        assert_eq!(selector.name.as_deref(), Some("save"));
+        assert_eq!(selector.states, vec![StyleState::Hover, StyleState::Pressed]);
+    }
+
+    #[test]
+    fn selector_of_any_kind() {
+        assert_eq!(Selector::parse("*").unwrap(), Selector::default());
+        assert_eq!(Selector::parse("*:focused").unwrap().states, vec![StyleState::Focused]);
+    }
+
+    #[test]
+    fn malformed_selectors_are_rejected() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("Button:shiny").is_err());
+        assert!(Selector::parse("Button#").is_err());
+        assert!(Selector::parse("Button.primary").is_err());
+        assert!(Selector::parse("Button§x").is_err());
+    }
+
+    #[test]
+    fn sheet_with_comments_and_selector_lists() {
+        let sheet = StyleSheet::parse("/* buttons\n and fields */\n\
+            Button, TextField:focused { border-width: 2; corner-radius: 4 }\n").unwrap();
+        assert_eq!(sheet.rules.len(), 2);
+        assert_eq!(sheet.rules[0].selector.kind.as_deref(), Some("Button"));
+        assert_eq!(sheet.rules[1].selector.states, vec![StyleState::Focused]);
+        assert_eq!(sheet.rules[1].declarations,
+                   vec![Declaration::BorderWidth(2.0), Declaration::CornerRadius(4.0)]);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let line_of = |text: &str| match StyleSheet::parse(text) {
+            Err(StyleSheetError::Syntax { line, .. }) => line,
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(line_of("Button {\n  border-width: 2;\n  sparkle: 1\n}"), 3);
+        assert_eq!(line_of("Button { border-width: 2 }\n\nButton:shiny { opacity: 1 }"), 3);
+        assert_eq!(line_of("/* a\nb */ Button {"), 2);
+        assert_eq!(line_of("Button { opacity: 1 }\nTextField"), 2);
+    }
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Font, Material};
+use crate::caribou::widget::Widget;
+use crate::caribou::widgets::{Button, TextField};
+
+/// Materials, fonts and metrics keyed by semantic roles such as `button.background.hover`,
+/// read by the default widget styles every time they draw.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    materials: HashMap<String, Material>,
+    fonts: HashMap<String, Font>,
+    metrics: HashMap<String, f32>,
+}
+
+impl Theme {
+    /// A theme without any roles, to be filled in from scratch.
+    pub fn empty() -> Theme {
+        Theme {
+            materials: HashMap::new(),
+            fonts: HashMap::new(),
+            metrics: HashMap::new(),
+        }
+    }
+
+    /// The gray palette the default styles have always been drawn with.
+    pub fn light() -> Theme {
+        Theme::empty()
+            .with_material("window.background", Material::Solid(1.0, 1.0, 1.0, 1.0))
+            .with_material("button.border.normal", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.background.normal", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.caption.normal", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("button.border.hover", Material::Solid(0.9, 0.9, 0.9, 1.0))
+            .with_material("button.background.hover", Material::Solid(0.9, 0.9, 0.9, 1.0))
+            .with_material("button.caption.hover", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("button.border.pressed", Material::Solid(0.3, 0.3, 0.3, 1.0))
+            .with_material("button.background.pressed", Material::Solid(0.3, 0.3, 0.3, 1.0))
+            .with_material("button.caption.pressed", Material::Solid(1.0, 1.0, 1.0, 1.0))
+            .with_material("button.border.disabled", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.background.disabled", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.caption.disabled", Material::Solid(0.4, 0.4, 0.4, 1.0))
+            .with_material("button.focus_ring", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_metric("button.border.width", 2.0)
+            .with_material("text_field.border.normal", Material::Solid(0.6, 0.6, 0.6, 1.0))
+            .with_material("text_field.text.normal", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("text_field.border.focused", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("text_field.text.focused", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("text_field.border.disabled", Material::Solid(0.8, 0.8, 0.8, 1.0))
+            .with_material("text_field.text.disabled", Material::Solid(0.4, 0.4, 0.4, 1.0))
+            .with_material("text_field.background", Material::Solid(1.0, 1.0, 1.0, 1.0))
+            .with_metric("text_field.border.width", 2.0)
+            .with_font("text", Font::default())
+    }
+
+    /// Light text on dark surfaces, with the roles of `Theme::light`.
+    pub fn dark() -> Theme {
+        Theme::light()
+            .with_material("window.background", Material::Solid(0.12, 0.12, 0.12, 1.0))
+            .with_material("button.border.normal", Material::Solid(0.25, 0.25, 0.25, 1.0))
+            .with_material("button.background.normal", Material::Solid(0.25, 0.25, 0.25, 1.0))
+            .with_material("button.caption.normal", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.border.hover", Material::Solid(0.32, 0.32, 0.32, 1.0))
+            .with_material("button.background.hover", Material::Solid(0.32, 0.32, 0.32, 1.0))
+            .with_material("button.caption.hover", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("button.border.pressed", Material::Solid(0.8, 0.8, 0.8, 1.0))
+            .with_material("button.background.pressed", Material::Solid(0.8, 0.8, 0.8, 1.0))
+            .with_material("button.caption.pressed", Material::Solid(0.0, 0.0, 0.0, 1.0))
+            .with_material("button.border.disabled", Material::Solid(0.2, 0.2, 0.2, 1.0))
+            .with_material("button.background.disabled", Material::Solid(0.2, 0.2, 0.2, 1.0))
+            .with_material("button.caption.disabled", Material::Solid(0.5, 0.5, 0.5, 1.0))
+            .with_material("button.focus_ring", Material::Solid(1.0, 1.0, 1.0, 1.0))
+            .with_material("text_field.border.normal", Material::Solid(0.45, 0.45, 0.45, 1.0))
+            .with_material("text_field.text.normal", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("text_field.border.focused", Material::Solid(0.9, 0.9, 0.9, 1.0))
+            .with_material("text_field.text.focused", Material::Solid(0.95, 0.95, 0.95, 1.0))
+            .with_material("text_field.border.disabled", Material::Solid(0.3, 0.3, 0.3, 1.0))
+            .with_material("text_field.text.disabled", Material::Solid(0.5, 0.5, 0.5, 1.0))
+            .with_material("text_field.background", Material::Solid(0.15, 0.15, 0.15, 1.0))
+    }
+
+    /// The built-in theme for a color scheme.
+    pub fn for_scheme(scheme: ColorScheme) -> Theme {
+        match scheme {
+            ColorScheme::Light => Theme::light(),
+            ColorScheme::Dark => Theme::dark(),
+        }
+    }
+
+    pub fn with_material(mut self, role: &str, material: Material) -> Theme {
+        self.set_material(role, material);
+        self
+    }
+
+    pub fn with_font(mut self, role: &str, font: Font) -> Theme {
+        self.set_font(role, font);
+        self
+    }
+
+    pub fn with_metric(mut self, role: &str, metric: f32) -> Theme {
+        self.set_metric(role, metric);
+        self
+    }
+
+    pub fn set_material(&mut self, role: &str, material: Material) {
+        self.materials.insert(role.to_string(), material);
+    }
+
+    pub fn set_font(&mut self, role: &str, font: Font) {
+        self.fonts.insert(role.to_string(), font);
+    }
+
+    pub fn set_metric(&mut self, role: &str, metric: f32) {
+        self.metrics.insert(role.to_string(), metric);
+    }
+
+    /// The material of the role, transparent if the theme lacks it.
+    pub fn material(&self, role: &str) -> Material {
+        self.materials.get(role).copied().unwrap_or(Material::Transparent)
+    }
+
+    /// The font of the role, falling back to the `text` role and then the default font.
+    pub fn font(&self, role: &str) -> Font {
+        self.fonts.get(role).or_else(|| self.fonts.get("text")).cloned().unwrap_or_default()
+    }
+
+    /// The metric of the role, zero if the theme lacks it.
+    pub fn metric(&self, role: &str) -> f32 {
+        self.metrics.get(role).copied().unwrap_or(0.0)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::light()
+    }
+}
+
+/// Whether the user prefers dark text on light surfaces or the other way round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        ColorScheme::Light
+    }
+}
+
+/// Gives a freshly created widget of some kind its drawing, e.g. by subscribing to its `draw_*`
+/// events.
+pub type StyleProvider = Rc<dyn Fn(&Widget)>;
+
+thread_local! {
+    static STYLE_PROVIDERS: RefCell<HashMap<&'static str, StyleProvider>> =
+        RefCell::new(default_style_providers());
+}
+
+fn default_style_providers() -> HashMap<&'static str, StyleProvider> {
+    let mut providers: HashMap<&'static str, StyleProvider> = HashMap::new();
+    providers.insert("Button", Rc::new(|widget| {
+        if let Some(data) = Button::interpret(widget) {
+            data.apply_default_style();
+        }
+    }));
+    providers.insert("TextField", Rc::new(|widget| {
+        if let Some(data) = TextField::interpret(widget) {
+            data.apply_default_style();
+        }
+    }));
+    providers
+}
+
+impl Caribou {
+    /// Style widgets of the kind created from now on with the provider instead of the default
+    /// style.
+    pub fn set_style_provider(kind: &'static str, provider: StyleProvider) {
+        STYLE_PROVIDERS.with(|providers| providers.borrow_mut().insert(kind, provider));
+    }
+
+    /// Leave widgets of the kind created from now on unstyled, for fully custom drawing.
+    pub fn remove_style_provider(kind: &'static str) {
+        STYLE_PROVIDERS.with(|providers| providers.borrow_mut().remove(kind));
+    }
+
+    /// Run the style provider of the widget's kind, if there is one.
+    pub fn provide_style(widget: &Widget) {
+        let provider = STYLE_PROVIDERS.with(|providers| {
+            providers.borrow().get(*widget.kind.get()).cloned()
+        });
+        if let Some(provider) = provider {
+            provider(widget);
+        }
+    }
+}
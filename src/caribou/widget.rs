@@ -1,22 +1,39 @@
-use std::any::Any;
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::iter::FilterMap;
+use std::path::PathBuf;
 use std::rc::{Rc, Weak};
 use std::slice::Iter;
-use crate::caribou::batch::{Batch, Brush, Font};
-use crate::caribou::event::{EventInit, SingleArgEvent, ZeroArgEvent};
-use crate::caribou::input::KeyEvent;
-use crate::caribou::math::IntPair;
+use crate::caribou::Caribou;
+use crate::caribou::batch::{Batch, BatchConsolidation, BatchOp, Brush, Font, Recording, Transform};
+use crate::caribou::event::{EventInit, SingleArgEvent, SubscriptionGuard, ZeroArgEvent};
+use crate::caribou::input::{CursorIcon, KeyEvent};
+use crate::caribou::math::{DimensionPair, IntPair, Region, ScalarPair};
 use crate::caribou::property::*;
+use crate::caribou::style::apply_style_sheet;
+use crate::caribou::routing::RoutedEvent;
 
 pub type Widget = Rc<WidgetInner>;
 pub type WidgetRef = Weak<WidgetInner>;
 
 pub struct WidgetInner {
     // Attributes
+    // - Identification, for style sheets to select the widget by
+    /// Type of the widget, e.g. `Button`
+    pub kind: Property<&'static str>,
+    pub name: Property<String>,
     // - Generic
     pub position: ScalarProperty,
     pub size: ScalarProperty,
+    pub relative_size: Property<DimensionPair>,
+    pub z_index: Property<i32>,
     pub enabled: BoolProperty,
+    /// Cursor shown while hovering the widget, unless a child over it sets its own
+    pub cursor: Property<CursorIcon>,
+    pub exiting: BoolProperty,
+    pub scroll_chaining: Property<ScrollChaining>,
     // - Hierarchical
     pub parent: OptionalProperty<WidgetRef>,
     pub content: OptionalProperty<Widget>,
@@ -24,16 +41,33 @@ pub struct WidgetInner {
     // - Appearance
     pub background: Property<Brush>,
     pub foreground: Property<Brush>,
-    pub boarder: Property<Brush>,
-    pub font: Property<Font>,
+    pub border: Property<Brush>,
+    pub corner_radius: Property<f32>,
+    pub opacity: Property<f32>,
+    /// Clockwise rotation in degrees around the middle of the widget
+    pub rotation: Property<f32>,
+    /// Font of the widget's text; unset, the default styles take the theme's
+    pub font: OptionalProperty<Font>,
+    /// Keep the drawing recorded and replay it until a property of the widget changes or
+    /// `invalidate` is called
+    pub retained: BoolProperty,
+    recording: RefCell<Option<Recording>>,
     // - Arbitrary
+    #[deprecated(note = "store typed data with `insert_data` and read it with `with_data`")]
     pub data: DynamicProperty,
+    storage: RefCell<HashMap<TypeId, Rc<RefCell<Box<dyn Any>>>>>,
+    // Callbacks registered elsewhere that are removed along with the widget
+    subscriptions: RefCell<Vec<SubscriptionGuard>>,
     // Events
     // - Action
     pub action: SingleArgEvent<Rc<dyn Any>>,
     // - Render & update
     pub on_draw: ZeroArgEvent<Batch>,
     pub on_update: ZeroArgEvent,
+    /// The window moved to a display of another scale factor; cached metrics are stale
+    pub on_dpi_changed: SingleArgEvent<f32>,
+    /// The theme was replaced; anything taken from the old one is stale
+    pub on_restyle: ZeroArgEvent,
     // - Mouse
     // -- Button
     pub on_primary_down: ZeroArgEvent,
@@ -42,56 +76,257 @@ pub struct WidgetInner {
     pub on_secondary_up: ZeroArgEvent,
     pub on_tertiary_down: ZeroArgEvent,
     pub on_tertiary_up: ZeroArgEvent,
+    /// Second primary press in quick succession, after its `on_primary_down`
+    pub on_double_click: ZeroArgEvent,
     // -- Motion
     pub on_mouse_move: SingleArgEvent<IntPair>,
     pub on_mouse_enter: ZeroArgEvent,
     pub on_mouse_leave: ZeroArgEvent,
+    // -- Wheel (returns the delta left unconsumed)
+    pub on_scroll: SingleArgEvent<ScalarPair, ScalarPair>,
+    // - Files dragged in from outside (returns whether the widget accepts the file)
+    pub on_file_hover: SingleArgEvent<PathBuf, bool>,
+    pub on_file_leave: ZeroArgEvent,
+    pub on_file_drop: SingleArgEvent<PathBuf, bool>,
+    // - Dragging within the application (returns whether the widget accepts the payload)
+    pub on_drag_over: SingleArgEvent<Rc<dyn Any>, bool>,
+    pub on_drag_leave: ZeroArgEvent,
+    pub on_drop: SingleArgEvent<Rc<dyn Any>, bool>,
+    // -- On the source, telling whether the payload was dropped
+    pub on_drag_end: SingleArgEvent<bool>,
     // - Focus
     // -- Generic
     pub on_gain_focus: ZeroArgEvent<bool>,
     pub on_lose_focus: ZeroArgEvent<bool>,
-    // -- Keyboard
-    pub on_key_down: SingleArgEvent<KeyEvent>,
-    pub on_key_up: SingleArgEvent<KeyEvent>,
+    // -- Keyboard (returns whether the key was handled)
+    pub on_key_down: SingleArgEvent<KeyEvent, bool>,
+    pub on_key_up: SingleArgEvent<KeyEvent, bool>,
     // -- Input
+    /// A typed character, control characters excluded (returns whether it was handled)
+    pub on_char: SingleArgEvent<char, bool>,
     pub on_pre_edit: SingleArgEvent<String>,
     pub on_commit: SingleArgEvent<String>,
+    // - Pointer and keyboard input routed along the tree, before the events above (returns
+    //   whether it was handled, which stops it)
+    pub on_routed: SingleArgEvent<Rc<RoutedEvent>, bool>,
+    /// Where text is being typed, in widget coordinates, so the input method window can be
+    /// placed next to it while the widget is focused
+    pub caret_area: OptionalProperty<Region>,
+}
+
+/// What happens to the part of a scroll delta a widget could not consume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollChaining {
+    /// Bubble the remainder to the enclosing scrollable ancestor
+    Chain,
+    /// Swallow the remainder
+    Contain,
+}
+
+impl Default for ScrollChaining {
+    fn default() -> Self {
+        ScrollChaining::Chain
+    }
 }
 
 pub fn create_widget() -> Widget {
-    Rc::new_cyclic(|back| {
+    let widget = Rc::new_cyclic(|back| {
         WidgetInner {
+            kind: back.init_default_property(),
+            name: back.init_default_property(),
             position: back.init_default_property(),
             size: back.init_default_property(),
+            relative_size: back.init_default_property(),
+            z_index: back.init_default_property(),
             enabled: back.init_property(true),
+            cursor: back.init_default_property(),
+            exiting: back.init_default_property(),
+            scroll_chaining: back.init_default_property(),
             parent: back.init_default_property(),
             content: back.init_default_property(),
             children: back.init_default_property(),
             background: back.init_default_property(),
             foreground: back.init_default_property(),
-            boarder: back.init_default_property(),
+            border: back.init_default_property(),
+            corner_radius: back.init_default_property(),
+            opacity: back.init_property(1.0),
+            rotation: back.init_default_property(),
             font: back.init_default_property(),
+            retained: back.init_default_property(),
+            recording: RefCell::new(None),
+            #[allow(deprecated)]
             data: back.init_default_property(),
+            storage: RefCell::new(HashMap::new()),
+            subscriptions: RefCell::new(Vec::new()),
             action: back.init_event(),
             on_draw: back.init_event(),
             on_update: back.init_event(),
+            on_dpi_changed: back.init_event(),
+            on_restyle: back.init_event(),
             on_primary_down: back.init_event(),
             on_primary_up: back.init_event(),
             on_secondary_down: back.init_event(),
             on_secondary_up: back.init_event(),
             on_tertiary_down: back.init_event(),
             on_tertiary_up: back.init_event(),
+            on_double_click: back.init_event(),
             on_mouse_move: back.init_event(),
             on_mouse_enter: back.init_event(),
             on_mouse_leave: back.init_event(),
+            on_scroll: back.init_event(),
+            on_file_hover: back.init_event(),
+            on_file_leave: back.init_event(),
+            on_file_drop: back.init_event(),
+            on_drag_over: back.init_event(),
+            on_drag_leave: back.init_event(),
+            on_drop: back.init_event(),
+            on_drag_end: back.init_event(),
             on_gain_focus: back.init_event(),
             on_lose_focus: back.init_event(),
             on_key_down: back.init_event(),
             on_key_up: back.init_event(),
+            on_char: back.init_event(),
             on_pre_edit: back.init_event(),
             on_commit: back.init_event(),
+            on_routed: back.init_event(),
+            caret_area: back.init_default_property(),
+        }
+    });
+    // Style sheets apply to widgets as they join a tree or get a new name
+    let back = widget.refer();
+    widget.parent.listen(Box::new(move |_| {
+        if let Some(widget) = back.acquire() {
+            apply_style_sheet(&widget);
+        }
+    }));
+    let back = widget.refer();
+    widget.name.listen(Box::new(move |_| {
+        if let Some(widget) = back.acquire() {
+            apply_style_sheet(&widget);
+        }
+    }));
+    widget
+}
+
+/// Widgets fainter than this take part in neither hit testing nor focus.
+pub const INTERACTIVE_OPACITY: f32 = 0.5;
+
+impl WidgetInner {
+    /// Whether the widget may receive pointer input and focus, which it may not while fading
+    /// out or running its exit animation.
+    pub fn is_interactive(&self) -> bool {
+        *self.opacity.get() >= INTERACTIVE_OPACITY && self.exiting.is_false()
+    }
+}
+
+impl WidgetInner {
+    /// Broadcast `on_draw`, or replay the recorded drawing of a retained widget.
+    pub fn draw(&self) -> Vec<Batch> {
+        if self.retained.is_false() {
+            return self.on_draw.broadcast();
+        }
+        let recording = self.recording.borrow().clone();
+        let recording = match recording {
+            Some(recording) => recording,
+            None => {
+                let recording = Caribou::record(self.on_draw.broadcast().consolidate());
+                self.recording.replace(Some(recording.clone()));
+                recording
+            }
+        };
+        let batch = Batch::new();
+        batch.add_op(BatchOp::Recording {
+            transform: Transform::default(),
+            recording,
+        });
+        vec![batch]
+    }
+
+    /// Keep a subscription until the widget is dropped.
+    pub fn own(&self, guard: SubscriptionGuard) {
+        self.subscriptions.borrow_mut().push(guard);
+    }
+
+    /// Drop the recorded drawing of this widget and of the ancestors containing it.
+    pub fn invalidate(&self) {
+        self.recording.replace(None);
+        let mut current = self.parent.get_cloned().and_then(|parent| parent.upgrade());
+        while let Some(ancestor) = current {
+            ancestor.recording.replace(None);
+            current = ancestor.parent.get_cloned().and_then(|parent| parent.upgrade());
+        }
+        Caribou::request_redraw();
+    }
+
+    /// Like `invalidate`, but only redraws if a recording was actually dropped. Called
+    /// whenever a property of the widget notifies its listeners.
+    pub(crate) fn discard_recordings(&self) {
+        let mut discarded = self.recording.borrow_mut().take().is_some();
+        let mut current = self.parent.get_cloned().and_then(|parent| parent.upgrade());
+        while let Some(ancestor) = current {
+            discarded |= ancestor.recording.borrow_mut().take().is_some();
+            current = ancestor.parent.get_cloned().and_then(|parent| parent.upgrade());
         }
-    })
+        if discarded {
+            Caribou::request_redraw();
+        }
+    }
+}
+
+/// Why typed widget data could not be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataError {
+    /// No data of the type was inserted
+    Missing(&'static str),
+    /// The data is already borrowed in a conflicting way further up the stack
+    Busy(&'static str),
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataError::Missing(name) => write!(f, "widget has no data of type {}", name),
+            DataError::Busy(name) => write!(f, "widget data of type {} is already in use", name),
+        }
+    }
+}
+
+impl std::error::Error for DataError {}
+
+impl WidgetInner {
+    /// Store a value of type `T` on the widget, replacing any previous value of that type.
+    pub fn insert_data<T: 'static>(&self, value: T) {
+        self.storage.borrow_mut()
+            .insert(TypeId::of::<T>(), Rc::new(RefCell::new(Box::new(value))));
+    }
+
+    pub fn remove_data<T: 'static>(&self) -> bool {
+        self.storage.borrow_mut().remove(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn has_data<T: 'static>(&self) -> bool {
+        self.storage.borrow().contains_key(&TypeId::of::<T>())
+    }
+
+    fn data_cell<T: 'static>(&self) -> Result<Rc<RefCell<Box<dyn Any>>>, DataError> {
+        self.storage.borrow().get(&TypeId::of::<T>()).cloned()
+            .ok_or(DataError::Missing(type_name::<T>()))
+    }
+
+    /// Read the data of type `T`. The borrow lasts only for the closure, so it cannot be held
+    /// across broadcasts; reading while `with_data_mut` is running yields `DataError::Busy`.
+    pub fn with_data<T: 'static, R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, DataError> {
+        let cell = self.data_cell::<T>()?;
+        let data = cell.try_borrow().map_err(|_| DataError::Busy(type_name::<T>()))?;
+        Ok(f(data.downcast_ref::<T>().unwrap()))
+    }
+
+    /// Modify the data of type `T`, failing with `DataError::Busy` on re-entrant access.
+    pub fn with_data_mut<T: 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, DataError> {
+        let cell = self.data_cell::<T>()?;
+        let mut data = cell.try_borrow_mut().map_err(|_| DataError::Busy(type_name::<T>()))?;
+        Ok(f(data.downcast_mut::<T>().unwrap()))
+    }
 }
 
 trait SameAs {
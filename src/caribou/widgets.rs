@@ -1,103 +1,457 @@
 use std::borrow::Borrow;
-use std::cell::{Ref, RefCell};
-use std::rc::Rc;
-use crate::caribou::batch::{Batch, BatchConsolidation, BatchOp, Brush, Font, FontSlant, Material, Path, PathOp, TextAlignment, Transform};
-use crate::caribou::math::{IntPair, Region};
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use crate::caribou::anim::{AnimationHandle, Animator, ChildTransition, FinishFunc, StepFunc, TransitionEffect};
+use crate::caribou::behavior::Interactivity;
+use crate::caribou::batch::{Batch, BatchConsolidation, BatchOp, Brush, Font, FontSlant, Material, Path, PathOp, TextAlignment, TextDecoration, TextLayout, TextOverflow, Transform};
+use crate::caribou::math::{Region, ScalarPair};
 use crate::Caribou;
-use crate::caribou::widget::{create_widget, Widget, WidgetInner, WidgetRef, WidgetVec, WidgetRefVec, WidgetRefer, WidgetAcquire};
+use crate::caribou::widget::{create_widget, Widget, WidgetRef, WidgetVec, WidgetRefer, WidgetAcquire};
 use crate::caribou::event::{Event, EventInit, Subscriber, ZeroArgEvent};
-use crate::caribou::input::Key;
-use crate::caribou::property::{Property, PropertyInit};
+use crate::caribou::input::{CursorIcon, Key};
+use crate::caribou::property::{BoolProperty, Property, PropertyInit};
+use crate::caribou::style::{apply_style_sheet, is_styled, StyledPart};
+use crate::caribou::theme::Theme;
 
 pub struct Layout;
 
 pub struct LayoutData {
-    cur_hov: RefCell<Vec<WidgetRef>>,
-    cur_pos: RefCell<IntPair>,
+    pub enter_transition: Property<Option<ChildTransition>>,
+    pub exit_transition: Property<Option<ChildTransition>>,
+    /// Skip drawing children that lie entirely outside the visible bounds
+    pub cull_children: BoolProperty,
+    known: RefCell<Vec<Widget>>,
+    exiting: RefCell<Vec<Widget>>,
+    motions: RefCell<Vec<ChildMotion>>,
+}
+
+/// Placement adjustment of a child while its enter or exit transition plays.
+struct ChildMotion {
+    child: WidgetRef,
+    offset: ScalarPair,
+    scale: f32,
+    animation: AnimationHandle,
+}
+
+/// Children ordered from bottom to top by `z_index`, keeping insertion order among equals.
+pub fn z_ordered(children: &[Widget]) -> Vec<Widget> {
+    let mut ordered = children.to_vec();
+    ordered.sort_by_key(|child| *child.z_index.get());
+    ordered
+}
+
+/// Position of a widget's origin in window coordinates, following the `parent` links.
+pub fn window_position(widget: &Widget) -> ScalarPair {
+    let mut position = *widget.position.get();
+    let mut current = widget.parent.get_cloned().and_then(|parent| parent.upgrade());
+    while let Some(ancestor) = current {
+        position = position + *ancestor.position.get();
+        if let Some(data) = ScrollView::interpret(&ancestor) {
+            position = position - *data.offset.get();
+        }
+        current = ancestor.parent.get_cloned().and_then(|parent| parent.upgrade());
+    }
+    position
+}
+
+/// The cursor position relative to a widget's origin, which may lie outside the widget.
+pub fn mouse_position(widget: &Widget) -> ScalarPair {
+    Caribou::mouse_position().to_scalar() - window_position(widget)
+}
+
+/// The widget followed by its interactive descendants stacked at a point in its
+/// coordinates, outermost first, going down through the topmost child at each level.
+pub fn widgets_at(widget: &Widget, point: ScalarPair) -> Vec<Widget> {
+    let mut chain = vec![widget.clone()];
+    let mut point = point;
+    loop {
+        let current = chain.last().unwrap().clone();
+        let next = match ScrollView::interpret(&current) {
+            Some(data) => current.content.get_cloned()
+                .filter(|content| content.is_interactive())
+                .and_then(|content| hit_test(&content, point + *data.offset.get())
+                    .map(|local| (content, local))),
+            None => z_ordered(&current.children.get()).into_iter().rev()
+                .filter(|child| child.is_interactive())
+                .find_map(|child| hit_test(&child, point).map(|local| (child, local))),
+        };
+        match next {
+            Some((child, local)) => {
+                chain.push(child);
+                point = local;
+            }
+            None => return chain,
+        }
+    }
+}
+
+/// The cursor shape at a point in the widget's coordinates: the one of the topmost
+/// interactive descendant there that sets one, or the widget's own.
+pub fn cursor_at(widget: &Widget, point: ScalarPair) -> CursorIcon {
+    widgets_at(widget, point).iter().rev()
+        .map(|widget| *widget.cursor.get())
+        .find(|icon| *icon != CursorIcon::Inherit)
+        .unwrap_or(CursorIcon::Inherit)
+}
+
+/// Where a widget sits in its parent, ignoring transient motions of transitions.
+pub fn placement(widget: &Widget) -> Transform {
+    Transform {
+        translate: *widget.position.get(),
+        ..Transform::default()
+    }.rotated_about(*widget.rotation.get(), widget.size.get().times(0.5))
+}
+
+/// The point in the coordinates of the widget corresponding to one in its parent, if the
+/// widget covers it.
+pub fn hit_test(widget: &Widget, point: ScalarPair) -> Option<ScalarPair> {
+    let local = placement(widget).invert()?.apply_to_point(point);
+    Region::origin_size(ScalarPair::default(), *widget.size.get())
+        .contains(local)
+        .then_some(local)
+}
+
+/// The part of a widget that can end up on screen, in its own coordinates.
+pub fn visible_region(widget: &Widget) -> Region {
+    let bounds = Region::origin_size(ScalarPair::default(), *widget.size.get());
+    let parent = widget.parent.get_cloned().and_then(|parent| parent.upgrade());
+    match parent.as_ref().and_then(|parent| ScrollView::interpret(parent)) {
+        Some(data) => {
+            let viewport = Region::origin_size(*data.offset.get(),
+                                               *parent.as_ref().unwrap().size.get());
+            bounds.intersection(&viewport).unwrap_or_default()
+        }
+        None => bounds,
+    }
+}
+
+/// Resolves the relative sizes throughout a subtree, top-down.
+pub fn relayout(widget: &Widget) {
+    if Layout::interpret(widget).is_some() {
+        Layout::resolve_children(widget, *widget.size.get());
+    }
+    for child in widget.children.get().iter() {
+        relayout(child);
+    }
+    if let Some(content) = widget.content.get_cloned() {
+        relayout(&content);
+    }
+}
+
+/// Tells a subtree that the scale factor changed, dropping the drawings recorded for the old
+/// one.
+pub fn dpi_changed(widget: &Widget, scale_factor: f32) {
+    widget.invalidate();
+    widget.on_dpi_changed.broadcast(scale_factor);
+    for child in widget.children.get().iter() {
+        dpi_changed(child, scale_factor);
+    }
+    if let Some(content) = widget.content.get_cloned() {
+        dpi_changed(&content, scale_factor);
+    }
+}
+
+/// Tells a subtree that the theme or style sheet changed, styling it again and dropping the
+/// drawings recorded with the old styles.
+pub fn restyle(widget: &Widget) {
+    apply_style_sheet(widget);
+    widget.invalidate();
+    widget.on_restyle.broadcast();
+    for child in widget.children.get().iter() {
+        restyle(child);
+    }
+    if let Some(content) = widget.content.get_cloned() {
+        restyle(&content);
+    }
+}
+
+fn is_parent_of(comp: &Widget, child: &Widget) -> bool {
+    child.parent.get().as_ref().map_or(false, |parent| Weak::ptr_eq(parent, &comp.refer()))
+}
+
+/// Draws the `background` and `border` brushes of a container, to be placed behind its children.
+pub fn draw_decoration(comp: &Widget, batch: &Batch) {
+    let size = *comp.size.get();
+    let radius = *comp.corner_radius.get();
+    let shape = |inset: f32| {
+        let origin = ScalarPair::new(inset, inset);
+        let size = size - origin.times(2.0);
+        Path::from_vec(vec![if radius > 0.0 {
+            PathOp::RoundRect(origin, size, (radius - inset).max(0.0))
+        } else {
+            PathOp::Rect(origin, size)
+        }])
+    };
+    let background = *comp.background.get();
+    if !background.is_transparent() {
+        batch.add_op(BatchOp::Path {
+            transform: Transform::default(),
+            path: shape(0.0),
+            brush: background,
+        });
+    }
+    let border = *comp.border.get();
+    if !border.is_transparent() {
+        // Inset by half the stroke so the parent's clip does not cut it off
+        batch.add_op(BatchOp::Path {
+            transform: Transform::default(),
+            path: shape(border.stroke_width / 2.0),
+            brush: border,
+        });
+    }
 }
 
 impl Layout {
     pub fn create() -> Widget {
         let widget = create_widget();
+        widget.kind.set("Layout");
+        let back = widget.refer();
+        widget.size.listen(Box::new(move |size| {
+            if let Some(comp) = back.acquire() {
+                Layout::resolve_children(&comp, *size);
+            }
+        }));
+        let back = widget.refer();
+        widget.children.listen(Box::new(move |children| {
+            if let Some(comp) = back.acquire() {
+                Layout::track_children(&comp, children);
+            }
+        }));
         widget.on_draw.subscribe(Box::new(|comp| {
+            Layout::resolve_children(&comp, *comp.size.get());
             let mut batch = Batch::new();
-            comp.children.get().iter().for_each(|child| {
+            draw_decoration(&comp, &batch);
+            let data = Layout::interpret(&comp).unwrap();
+            // Children playing their exit transition are still drawn in place
+            let mut drawn = comp.children.get_cloned();
+            drawn.extend(data.exiting.borrow().iter().cloned());
+            let visible = visible_region(&comp);
+            let cull = data.cull_children.is_true();
+            z_ordered(&drawn).iter().for_each(|child| {
+                let size = *child.size.get();
+                let (offset, scale) = data.motions.borrow().iter()
+                    .find(|motion| Weak::ptr_eq(&motion.child, &child.refer()))
+                    .map_or((ScalarPair::default(), 1.0),
+                            |motion| (motion.offset, motion.scale));
+                let placed = Region::origin_size(
+                    *child.position.get() + offset + size.times((1.0 - scale) / 2.0),
+                    size.times(scale));
+                let opacity = *child.opacity.get();
+                if opacity <= 0.0 || cull && !visible.intersects(&placed) {
+                    return;
+                }
                 let transform = Transform {
-                    translate: *child.position.get(),
-                    clip_size: Some(*child.size.get()),
+                    translate: *child.position.get() + offset
+                        + size.times((1.0 - scale) / 2.0),
+                    scale: (scale, scale).into(),
+                    clip_size: Some(size),
+                    opacity,
                     ..Transform::default()
-                };
-                let batches = child.on_draw.broadcast();
+                }.rotated_about(*child.rotation.get(), size.times(0.5));
+                let batches = child.draw();
                 for entry in batches {
                     batch.add_op(BatchOp::Batch {
                         transform,
                         batch: entry,
+                        filter: None,
                     });
                 }
             });
             batch
         }));
-        widget.on_mouse_move.subscribe(Box::new(|comp, pos| {
-            let data: Ref<LayoutData> = comp.data.get_as().unwrap();
-            let mut cur_hov = data.cur_hov.borrow_mut();
-            cur_hov.clean();
-            let mut cur_pos = data.cur_pos.borrow_mut();
-            *cur_pos = pos;
-            let mut new_hov = Vec::new();
-            for child in comp.children.get().iter() {
-                let child_pos = *child.position.get();
-                let child_size = *child.size.get();
-                if Region::origin_size(child_pos, child_size).contains(pos.to_scalar()) {
-                    let child_pos = pos - child_pos.to_int();
-                    if !cur_hov.contains_ref(&child.refer()) {
-                        child.on_mouse_enter.broadcast();
-                    } else {
-                        child.on_mouse_move.broadcast(child_pos);
-                    }
-                    new_hov.push(child.refer());
+        widget.insert_data(Rc::new(LayoutData {
+            enter_transition: widget.init_default_property(),
+            exit_transition: widget.init_default_property(),
+            cull_children: widget.init_property(true),
+            known: RefCell::new(vec![]),
+            exiting: RefCell::new(vec![]),
+            motions: RefCell::new(vec![]),
+        }));
+        widget
+    }
+
+    pub fn interpret(comp: &Widget) -> Option<Rc<LayoutData>> {
+        comp.with_data(|data: &Rc<LayoutData>| data.clone()).ok()
+    }
+
+    /// Plays the configured transitions for children added to or removed from `children`.
+    fn track_children(comp: &Widget, children: &Vec<Widget>) {
+        let data = match Layout::interpret(comp) {
+            Some(data) => data,
+            None => return,
+        };
+        let previous = data.known.replace(children.clone());
+        let enter = *data.enter_transition.get();
+        let exit = *data.exit_transition.get();
+        for child in children.iter().filter(|child| !previous.contains_widget(child)) {
+            child.parent.put(comp.refer());
+            // A child added back during its exit transition is revived
+            if child.exiting.is_true() {
+                data.exiting.borrow_mut().retain(|ghost| !Rc::ptr_eq(ghost, child));
+                child.exiting.set(false);
+                child.opacity.set(1.0);
+            }
+            match enter {
+                Some(transition) => Layout::play_transition(comp, child, transition, true),
+                None => Layout::stop_transition(comp, child),
+            }
+        }
+        for child in previous.iter().filter(|child| !children.contains_widget(child)) {
+            if is_parent_of(comp, child) {
+                child.parent.clear();
+            }
+            match exit {
+                Some(transition) => {
+                    // Removal is deferred until the exit transition finishes
+                    child.exiting.set(true);
+                    data.exiting.borrow_mut().push(child.clone());
+                    Layout::play_transition(comp, child, transition, false);
                 }
+                None => Layout::stop_transition(comp, child),
+            }
+        }
+    }
+
+    fn stop_transition(comp: &Widget, child: &Widget) {
+        let data = Layout::interpret(comp).unwrap();
+        data.motions.borrow_mut().retain(|motion| {
+            if Weak::ptr_eq(&motion.child, &child.refer()) {
+                motion.animation.cancel();
+                false
+            } else {
+                true
             }
-            for child in cur_hov.iter() {
-                if !new_hov.contains_ref(child) {
-                    child.acquire().unwrap().on_mouse_leave.broadcast();
+        });
+    }
+
+    fn play_transition(comp: &Widget, child: &Widget, transition: ChildTransition, entering: bool) {
+        Layout::stop_transition(comp, child);
+        let comp_ref = comp.refer();
+        let child_ref = child.refer();
+        let step: StepFunc = Box::new(move |progress: f32| {
+            if let (Some(comp), Some(child)) = (comp_ref.acquire(), child_ref.acquire()) {
+                let progress = transition.easing.evaluate(progress);
+                let amount = if entering { 1.0 - progress } else { progress };
+                Layout::apply_motion(&comp, &child, transition.effect, amount);
+                Caribou::request_redraw();
+            }
+        });
+        let comp_ref = comp.refer();
+        let child_ref = child.refer();
+        let finish: FinishFunc = Box::new(move || {
+            if let (Some(comp), Some(child)) = (comp_ref.acquire(), child_ref.acquire()) {
+                Layout::stop_transition(&comp, &child);
+                if !entering {
+                    // Leave the detached widget as it was before the transition
+                    let data = Layout::interpret(&comp).unwrap();
+                    data.exiting.borrow_mut().retain(|ghost| !Rc::ptr_eq(ghost, &child));
+                    child.exiting.set(false);
+                    child.opacity.set(1.0);
                 }
+                Caribou::request_redraw();
             }
-            *cur_hov = new_hov;
-        }));
-        widget.on_mouse_leave.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<LayoutData>().unwrap();
-            let mut cur_hov = data.cur_hov.borrow_mut();
-            cur_hov.clean();
-            for child in cur_hov.iter() {
-                child.acquire().unwrap().on_mouse_leave.broadcast();
+        });
+        let animation = Animator::start(transition.duration, step, Some(finish));
+        Layout::interpret(comp).unwrap().motions.borrow_mut().push(ChildMotion {
+            child: child.refer(),
+            offset: ScalarPair::default(),
+            scale: 1.0,
+            animation,
+        });
+        Layout::apply_motion(comp, child, transition.effect, if entering { 1.0 } else { 0.0 });
+    }
+
+    /// Applies `amount` (0 being in place, 1 being fully out) of a transition effect.
+    fn apply_motion(comp: &Widget, child: &Widget, effect: TransitionEffect, amount: f32) {
+        if let TransitionEffect::Fade = effect {
+            child.opacity.set(1.0 - amount);
+            return;
+        }
+        let data = Layout::interpret(comp).unwrap();
+        let mut motions = data.motions.borrow_mut();
+        if let Some(motion) = motions.iter_mut()
+            .find(|motion| Weak::ptr_eq(&motion.child, &child.refer())) {
+            match effect {
+                TransitionEffect::Slide(offset) => motion.offset = offset.times(amount),
+                TransitionEffect::Scale(from) => motion.scale = 1.0 + (from - 1.0) * amount,
+                TransitionEffect::Fade => {}
+            }
+        }
+    }
+
+    /// Resolves the relative sizes of the children against the given parent size.
+    pub fn resolve_children(comp: &Widget, size: ScalarPair) {
+        for child in comp.children.get().iter() {
+            let resolved = child.relative_size.get()
+                .resolve(*child.size.get(), *child.position.get(), size);
+            child.size.set_distinct(resolved);
+        }
+    }
+}
+
+pub struct ScrollView;
+
+pub struct ScrollViewData {
+    pub offset: Property<ScalarPair>,
+}
+
+impl ScrollView {
+    pub fn create() -> Widget {
+        let comp = create_widget();
+        comp.kind.set("ScrollView");
+        comp.on_draw.subscribe(Box::new(|comp| {
+            let mut batch = Batch::new();
+            draw_decoration(&comp, &batch);
+            if let Some(content) = comp.content.get_cloned() {
+                let data = ScrollView::interpret(&comp).unwrap();
+                let transform = Transform {
+                    translate: ScalarPair::default() - *data.offset.get(),
+                    opacity: *content.opacity.get(),
+                    ..Transform::default()
+                };
+                for entry in content.draw() {
+                    batch.add_op(BatchOp::Batch {
+                        transform,
+                        batch: entry,
+                        filter: None,
+                    });
+                }
             }
-            cur_hov.clear();
+            batch
         }));
-        widget.on_primary_down.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<LayoutData>().unwrap();
-            let mut cur_hov = data.cur_hov.borrow_mut();
-            cur_hov.clean();
-            for child in cur_hov.iter() {
-                child.acquire().unwrap().on_primary_down.broadcast();
+        let back = comp.refer();
+        comp.content.listen(Box::new(move |content| {
+            if let (Some(comp), Some(content)) = (back.acquire(), content) {
+                content.parent.put(comp.refer());
             }
         }));
-        widget.on_primary_up.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<LayoutData>().unwrap();
-            let mut cur_hov = data.cur_hov.borrow_mut();
-            cur_hov.clean();
-            for child in cur_hov.iter() {
-                child.acquire().unwrap().on_primary_up.broadcast();
+        comp.on_scroll.subscribe(Box::new(|comp, delta| {
+            let content = match comp.content.get_cloned() {
+                Some(content) => content,
+                None => return delta,
+            };
+            let data = ScrollView::interpret(&comp).unwrap();
+            let range = *content.size.get() - *comp.size.get();
+            let offset = *data.offset.get();
+            let target = ScalarPair {
+                x: (offset.x - delta.x).clamp(0.0, range.x.max(0.0)),
+                y: (offset.y - delta.y).clamp(0.0, range.y.max(0.0)),
+            };
+            if target != offset {
+                data.offset.set(target);
+                Caribou::request_redraw();
             }
+            // Whatever could not be applied at the end of the range bubbles up
+            delta - (offset - target)
         }));
-        widget.data.set(Some(Box::new(LayoutData {
-            cur_hov: RefCell::new(vec![]),
-            cur_pos: RefCell::new(Default::default())
-        })));
-        widget
+        comp.insert_data(Rc::new(ScrollViewData {
+            offset: comp.init_default_property(),
+        }));
+        comp
     }
 
-    pub fn interpret(comp: &Widget) -> Option<Ref<LayoutData>> {
-        comp.data.get_as::<LayoutData>()
+    pub fn interpret(comp: &Widget) -> Option<Rc<ScrollViewData>> {
+        comp.with_data(|data: &Rc<ScrollViewData>| data.clone()).ok()
     }
 }
 
@@ -115,18 +469,38 @@ pub struct ButtonData {
     pub draw_hover: ZeroArgEvent<Batch>,
     pub draw_pressed: ZeroArgEvent<Batch>,
     pub draw_disabled: ZeroArgEvent<Batch>,
-    state: RefCell<ButtonState>,
-    focused: RefCell<bool>,
+    pub interactivity: Rc<Interactivity>,
+    default_styled: Cell<bool>,
+}
+
+impl ButtonData {
+    pub fn state(&self) -> ButtonState {
+        if self.interactivity.is_pressed.is_true() {
+            ButtonState::Pressed
+        } else if self.interactivity.is_hovered.is_true() {
+            ButtonState::Hover
+        } else {
+            ButtonState::Normal
+        }
+    }
 }
 
 impl Button {
+    /// A button drawn by the style provider of its kind, the default style unless replaced.
     pub fn create() -> Widget {
+        let comp = Button::create_unstyled();
+        Caribou::provide_style(&comp);
+        comp
+    }
+
+    /// A button drawing nothing until subscribers are added to its `draw_*` events.
+    pub fn create_unstyled() -> Widget {
         let comp = create_widget();
+        comp.kind.set("Button");
         comp.on_draw.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            let state = data.state.borrow();
+            let data = Button::interpret(&comp).unwrap();
             if comp.enabled.is_true() {
-                match &*state {
+                match data.state() {
                     ButtonState::Normal => data.draw_normal.broadcast(),
                     ButtonState::Hover => data.draw_hover.broadcast(),
                     ButtonState::Pressed => data.draw_pressed.broadcast(),
@@ -135,94 +509,96 @@ impl Button {
                 data.draw_disabled.broadcast().consolidate()
             }
         }));
-        comp.on_primary_down.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            data.state.replace(ButtonState::Pressed);
-            Caribou::request_redraw();
-            Caribou::instance().focused_component.set(Rc::downgrade(&comp));
-        }));
+        let interactivity = Interactivity::attach(&comp);
         comp.on_primary_up.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            data.state.replace(ButtonState::Hover);
             if comp.enabled.is_true() {
                 comp.action.broadcast(Rc::new(()));
             }
-            Caribou::request_redraw();
-        }));
-        comp.on_mouse_enter.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            data.state.replace(ButtonState::Hover);
-            Caribou::request_redraw();
-        }));
-        comp.on_mouse_leave.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            data.state.replace(ButtonState::Normal);
-            Caribou::request_redraw();
         }));
         comp.size.set((100.0, 30.0).into());
-        comp.data.set(Some(Box::new(ButtonData {
+        comp.insert_data(Rc::new(ButtonData {
             text: comp.init_property("按钮".to_string()),
             draw_normal: comp.init_event(),
             draw_hover: comp.init_event(),
             draw_pressed: comp.init_event(),
             draw_disabled: comp.init_event(),
-            state: RefCell::new(ButtonState::Normal),
-            focused: RefCell::new(false)
-        })));
-        comp.on_gain_focus.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            if comp.enabled.is_true() {
-                data.focused.replace(true);
-                Caribou::request_redraw();
-                println!("Gained focus!");
-                true
-            } else {
-                false
-            }
-        }));
-        comp.on_lose_focus.subscribe(Box::new(|comp| {
-            println!("Lost focus!");
-            let data = comp.data.get_as::<ButtonData>().unwrap();
-            data.focused.replace(false);
-            Caribou::request_redraw();
-            true
+            interactivity,
+            default_styled: Cell::new(false),
         }));
         comp.on_key_down.subscribe(Box::new(|comp, event| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
+            let data = Button::interpret(&comp).unwrap();
             match event.key {
                 Key::Return | Key::Space | Key::NumpadEnter => {
-                    data.state.replace(ButtonState::Pressed);
-                    Caribou::request_redraw();
+                    if data.interactivity.is_pressed.set_distinct(true) {
+                        Caribou::request_redraw();
+                    }
+                    true
                 }
-                _ => {}
+                _ => false
             }
         }));
         comp.on_key_up.subscribe(Box::new(|comp, event| {
-            let data = comp.data.get_as::<ButtonData>().unwrap();
+            let data = Button::interpret(&comp).unwrap();
             match event.key {
                 Key::Return | Key::Space | Key::NumpadEnter => {
-                    data.state.replace(ButtonState::Normal);
+                    let changed = data.interactivity.is_pressed.set_distinct(false);
                     comp.action.broadcast(Rc::new(()));
-                    Caribou::request_redraw();
+                    if changed {
+                        Caribou::request_redraw();
+                    }
+                    true
                 }
-                _ => {}
+                _ => false
             }
         }));
         Caribou::register_auto_tab_order(&comp);
         comp
     }
 
-    pub fn interpret(comp: &Widget) -> Option<Ref<ButtonData>> {
-        comp.data.get_as::<ButtonData>()
+    pub fn interpret(comp: &Widget) -> Option<Rc<ButtonData>> {
+        comp.with_data(|data: &Rc<ButtonData>| data.clone()).ok()
     }
 }
 
-fn button_default_style_on_draw(
-    border_mat: Material, back_mat: Material, caption_mat: Material
-) -> Box<dyn Fn(Widget) -> Batch> {
+/// Horizontal space kept free on both sides of a button's caption
+const BUTTON_PADDING: f32 = 4.0;
+
+/// A material of the widget's own brushes where a style sheet declares it, or else the
+/// theme's.
+fn own_or_themed(comp: &Widget, part: StyledPart, theme: &Theme, role: &str) -> Material {
+    if !is_styled(comp, part) {
+        return theme.material(role);
+    }
+    match part {
+        StyledPart::Background => comp.background.get().fill_mat,
+        StyledPart::Foreground => comp.foreground.get().fill_mat,
+        StyledPart::Border => comp.border.get().stroke_mat,
+        _ => theme.material(role),
+    }
+}
+
+/// The width of the widget's own border where a style sheet declares it, or else the theme's.
+fn own_or_themed_width(comp: &Widget, theme: &Theme, role: &str) -> f32 {
+    if is_styled(comp, StyledPart::BorderWidth) {
+        comp.border.get().stroke_width
+    } else {
+        theme.metric(role)
+    }
+}
+
+/// The widget's own font if one was set, e.g. by a style sheet, or else the theme's.
+fn own_or_themed_font(comp: &Widget, theme: &Theme, role: &str) -> Font {
+    comp.font.get_cloned().unwrap_or_else(|| theme.font(role))
+}
+
+/// Draws a button with the theme's materials for the given state, e.g. `hover`, except where
+/// a style sheet declares them.
+fn button_default_style_on_draw(state: &'static str) -> Box<dyn Fn(Widget) -> Batch> {
     Box::new(move |comp| {
         let mut batch = Batch::new();
-        let data = comp.data.get_as::<ButtonData>().unwrap();
+        let data = Button::interpret(&comp).unwrap();
+        let theme = Caribou::theme();
+        let border_width = own_or_themed_width(&comp, &theme, "button.border.width");
         batch.add_op(BatchOp::Path {
             transform: Transform::default(),
             path: Path::from_vec(vec![
@@ -231,12 +607,15 @@ fn button_default_style_on_draw(
 
             ]),
             brush: Brush {
-                stroke_mat: border_mat,
-                fill_mat: back_mat,
-                stroke_width: 2.0
+                stroke_mat: own_or_themed(&comp, StyledPart::Border, &theme,
+                                          &format!("button.border.{}", state)),
+                fill_mat: own_or_themed(&comp, StyledPart::Background, &theme,
+                                        &format!("button.background.{}", state)),
+                stroke_width: border_width,
+                ..Brush::default()
             }
         });
-        if *data.focused.borrow() {
+        if data.interactivity.is_focused.is_true() {
             batch.add_op(BatchOp::Path {
                 transform: Transform::default(),
                 path: Path::from_vec(vec![
@@ -244,24 +623,30 @@ fn button_default_style_on_draw(
                                  *comp.size.get() - (2.0, 2.0).into()),
                 ]),
                 brush: Brush {
-                    stroke_mat: Material::Solid(0.0, 0.0, 0.0, 1.0),
+                    stroke_mat: theme.material("button.focus_ring"),
                     fill_mat: Material::Transparent,
-                    stroke_width: 2.0
+                    stroke_width: border_width,
+                    ..Brush::default()
                 }
             });
         }
         batch.add_op(BatchOp::Text {
             transform: Transform {
-                translate: comp.size.get().times(0.5),
+                translate: (BUTTON_PADDING, comp.size.get().y / 2.0).into(),
                 ..Transform::default()
             },
             text: data.text.get_cloned(),
-            font: comp.font.get_cloned(),
-            alignment: TextAlignment::Center,
+            font: own_or_themed_font(&comp, &theme, "button.caption"),
+            alignment: TextAlignment::center(),
+            layout: TextLayout::truncated(comp.size.get().x - BUTTON_PADDING * 2.0,
+                                          TextOverflow::EllipsisEnd),
+            decoration: TextDecoration::default(),
             brush: Brush {
                 stroke_mat: Material::Transparent,
-                fill_mat: caption_mat,
-                stroke_width: 1.0
+                fill_mat: own_or_themed(&comp, StyledPart::Foreground, &theme,
+                                        &format!("button.caption.{}", state)),
+                stroke_width: 1.0,
+                ..Brush::default()
             }
         });
         batch
@@ -269,61 +654,71 @@ fn button_default_style_on_draw(
 }
 
 impl ButtonData {
+    /// Draw the button with the theme; does nothing if already done.
     pub fn apply_default_style(&self) {
-        self.draw_normal.subscribe(button_default_style_on_draw(
-            Material::Solid(0.95, 0.95, 0.95, 1.0),
-            Material::Solid(0.95, 0.95, 0.95, 1.0),
-            Material::Solid(0.0, 0.0, 0.0, 1.0),
-        ));
-        self.draw_hover.subscribe(button_default_style_on_draw(
-            Material::Solid(0.9, 0.9, 0.9, 1.0),
-            Material::Solid(0.9, 0.9, 0.9, 1.0),
-            Material::Solid(0.0, 0.0, 0.0, 1.0),
-        ));
-        self.draw_pressed.subscribe(button_default_style_on_draw(
-            Material::Solid(0.3, 0.3, 0.3, 1.0),
-            Material::Solid(0.3, 0.3, 0.3, 1.0),
-            Material::Solid(1.0, 1.0, 1.0, 1.0),
-        ));
-        self.draw_disabled.subscribe(button_default_style_on_draw(
-            Material::Solid(0.95, 0.95, 0.95, 1.0),
-            Material::Solid(0.95, 0.95, 0.95, 1.0),
-            Material::Solid(0.4, 0.4, 0.4, 1.0),
-        ));
+        if self.default_styled.replace(true) {
+            return;
+        }
+        self.draw_normal.subscribe(button_default_style_on_draw("normal"));
+        self.draw_hover.subscribe(button_default_style_on_draw("hover"));
+        self.draw_pressed.subscribe(button_default_style_on_draw("pressed"));
+        self.draw_disabled.subscribe(button_default_style_on_draw("disabled"));
     }
 }
 
 pub struct TextField;
 
+/// Space between the border of a text field and its text
+pub const TEXT_FIELD_PADDING: f32 = 6.0;
+/// Distance kept between the caret and the edges of the visible text
+const CARET_MARGIN: f32 = 8.0;
+
 pub struct TextFieldData {
     pub text: Property<String>,
     pub enabled: Property<bool>,
     pub focused: RefCell<bool>,
+    /// Caret position, in characters
+    pub caret: Property<usize>,
+    /// How far the text is scrolled to the left to keep the caret visible
+    pub scroll: Property<f32>,
     pub draw_unfocused: ZeroArgEvent<Batch>,
     pub draw_focused: ZeroArgEvent<Batch>,
     pub draw_disabled: ZeroArgEvent<Batch>,
     pre_edit: RefCell<Option<String>>,
+    default_styled: Cell<bool>,
 }
 
 impl TextField {
+    /// A text field drawn by the style provider of its kind, the default style unless replaced.
     pub fn create() -> Widget {
+        let comp = TextField::create_unstyled();
+        Caribou::provide_style(&comp);
+        comp
+    }
+
+    /// A text field drawing nothing until subscribers are added to its `draw_*` events.
+    pub fn create_unstyled() -> Widget {
         let comp = create_widget();
+        comp.kind.set("TextField");
+        comp.cursor.set(CursorIcon::IBeam);
         comp.on_draw.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<TextFieldData>().unwrap();
-            if *data.focused.borrow() {
+            let data = TextField::interpret(&comp).unwrap();
+            if !*data.enabled.get() {
+                data.draw_disabled.broadcast().consolidate()
+            } else if *data.focused.borrow() {
                 data.draw_focused.broadcast().consolidate()
             } else {
                 data.draw_unfocused.broadcast().consolidate()
             }
         }));
         comp.on_primary_down.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<TextFieldData>().unwrap();
+            let data = TextField::interpret(&comp).unwrap();
             if *data.enabled.get() {
                 Caribou::instance().focused_component.set(Rc::downgrade(&comp));
             }
         }));
         comp.on_gain_focus.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<TextFieldData>().unwrap();
+            let data = TextField::interpret(&comp).unwrap();
             if *data.enabled.get() {
                 *data.focused.borrow_mut() = true;
                 Caribou::request_redraw();
@@ -333,21 +728,285 @@ impl TextField {
             }
         }));
         comp.on_lose_focus.subscribe(Box::new(|comp| {
-            let data = comp.data.get_as::<TextFieldData>().unwrap();
+            let data = TextField::interpret(&comp).unwrap();
             *data.focused.borrow_mut() = false;
             Caribou::request_redraw();
             true
         }));
         comp.size.set((160.0, 30.0).into());
-        comp.data.set(Some(Box::new(TextFieldData {
+        comp.insert_data(Rc::new(TextFieldData {
             text: comp.init_property(String::new()),
             enabled: comp.init_property(true),
             focused: false.into(),
+            caret: comp.init_default_property(),
+            scroll: comp.init_default_property(),
             draw_unfocused: comp.init_event(),
             draw_focused: comp.init_event(),
             draw_disabled: comp.init_event(),
             pre_edit: None.into(),
-        })));
+            default_styled: Cell::new(false),
+        }));
+        {
+            let data = TextField::interpret(&comp).unwrap();
+            let back = comp.refer();
+            data.text.listen(Box::new(move |text| {
+                if let Some(comp) = back.acquire() {
+                    let data = TextField::interpret(&comp).unwrap();
+                    let caret = (*data.caret.get()).min(text.chars().count());
+                    data.caret.set_distinct(caret);
+                    TextField::scroll_to_caret(&comp, text, caret);
+                }
+            }));
+            let back = comp.refer();
+            data.caret.listen(Box::new(move |caret| {
+                if let Some(comp) = back.acquire() {
+                    let data = TextField::interpret(&comp).unwrap();
+                    TextField::scroll_to_caret(&comp, &data.text.get(), *caret);
+                }
+            }));
+        }
+        let back = comp.refer();
+        comp.size.listen(Box::new(move |_| {
+            if let Some(comp) = back.acquire() {
+                let data = TextField::interpret(&comp).unwrap();
+                TextField::scroll_to_caret(&comp, &data.text.get(), *data.caret.get());
+            }
+        }));
+        comp.on_key_down.subscribe(Box::new(|comp, event| {
+            let data = TextField::interpret(&comp).unwrap();
+            if !*data.enabled.get() {
+                return false;
+            }
+            let text = data.text.get_cloned();
+            let caret = *data.caret.get();
+            let count = text.chars().count();
+            match event.editing_key() {
+                Key::Left => data.caret.set(caret.saturating_sub(1)),
+                Key::Right => data.caret.set((caret + 1).min(count)),
+                Key::Home => data.caret.set(0),
+                Key::End => data.caret.set(count),
+                Key::Backspace if caret > 0 => {
+                    data.caret.set(caret - 1);
+                    data.text.set(remove_char(&text, caret - 1));
+                }
+                Key::Delete if caret < count => {
+                    data.text.set(remove_char(&text, caret));
+                }
+                // Without a selection, copying takes the whole text
+                Key::Copy => {
+                    Caribou::clipboard().set_text(&text);
+                }
+                // Cutting removes the selection, and without one there is nothing to cut
+                Key::Cut => {}
+                Key::Paste => {
+                    let pasted = match Caribou::clipboard().text() {
+                        Some(pasted) => pasted.replace(['\r', '\n'], " "),
+                        None => return true,
+                    };
+                    TextField::insert_at_caret(&comp, &pasted);
+                }
+                _ => return false,
+            }
+            Caribou::request_redraw();
+            true
+        }));
+        comp.on_char.subscribe(Box::new(|comp, c| {
+            TextField::insert_at_caret(&comp, &c.to_string())
+        }));
+        comp.on_commit.subscribe(Box::new(|comp, text| {
+            TextField::insert_at_caret(&comp, &text);
+        }));
         comp
     }
-}
\ No newline at end of file
+
+    /// Type text at the caret, moving the caret behind it. Returns whether the field took it.
+    fn insert_at_caret(comp: &Widget, inserted: &str) -> bool {
+        let data = TextField::interpret(comp).unwrap();
+        if !*data.enabled.get() {
+            return false;
+        }
+        let text = data.text.get_cloned();
+        let caret = *data.caret.get();
+        data.caret.set(caret + inserted.chars().count());
+        data.text.set(insert_str(&text, caret, inserted));
+        Caribou::request_redraw();
+        true
+    }
+
+    pub fn interpret(comp: &Widget) -> Option<Rc<TextFieldData>> {
+        comp.with_data(|data: &Rc<TextFieldData>| data.clone()).ok()
+    }
+
+    /// Adjusts `scroll` so that the caret stays inside the visible width, and so that no
+    /// space is wasted to the right of the text once it is shortened.
+    fn scroll_to_caret(comp: &Widget, text: &str, caret: usize) {
+        let data = match TextField::interpret(comp) {
+            Some(data) => data,
+            None => return,
+        };
+        let font = own_or_themed_font(comp, &Caribou::theme(), "text_field.text");
+        let prefix: String = text.chars().take(caret).collect();
+        let caret_x = Caribou::measure_text(&prefix, &font);
+        let text_width = Caribou::measure_text(text, &font);
+        let visible = (comp.size.get().x - TEXT_FIELD_PADDING * 2.0).max(0.0);
+        let mut scroll = *data.scroll.get();
+        if caret_x - scroll > visible - CARET_MARGIN {
+            scroll = caret_x - visible + CARET_MARGIN;
+        }
+        if caret_x - scroll < CARET_MARGIN {
+            scroll = caret_x - CARET_MARGIN;
+        }
+        scroll = scroll.min(text_width + CARET_MARGIN - visible).max(0.0);
+        if scroll != *data.scroll.get() {
+            data.scroll.set(scroll);
+            Caribou::request_redraw();
+        }
+        let baseline = (comp.size.get().y - font.size) / 2.0;
+        comp.caret_area.set(Some(Region::origin_size(
+            (TEXT_FIELD_PADDING + caret_x - scroll, baseline).into(),
+            (1.0, font.size).into(),
+        )));
+    }
+}
+
+fn remove_char(text: &str, index: usize) -> String {
+    text.chars().enumerate()
+        .filter(|(i, _)| *i != index)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+fn insert_str(text: &str, index: usize, inserted: &str) -> String {
+    let mut chars: Vec<char> = text.chars().collect();
+    let index = index.min(chars.len());
+    chars.splice(index..index, inserted.chars());
+    chars.into_iter().collect()
+}
+
+/// Draws a text field with the theme's materials for the given state, e.g. `focused`, except
+/// where a style sheet declares them.
+fn text_field_default_style_on_draw(
+    state: &'static str, show_caret: bool
+) -> Box<dyn Fn(Widget) -> Batch> {
+    Box::new(move |comp| {
+        let data = TextField::interpret(&comp).unwrap();
+        let theme = Caribou::theme();
+        let text_mat = own_or_themed(&comp, StyledPart::Foreground, &theme,
+                                     &format!("text_field.text.{}", state));
+        let size = *comp.size.get();
+        let font = own_or_themed_font(&comp, &theme, "text_field.text");
+        let batch = Batch::new();
+        batch.add_op(BatchOp::Path {
+            transform: Transform::default(),
+            path: Path::from_vec(vec![
+                PathOp::Rect((1.0, 1.0).into(), size - (2.0, 2.0).into()),
+            ]),
+            brush: Brush {
+                stroke_mat: own_or_themed(&comp, StyledPart::Border, &theme,
+                                          &format!("text_field.border.{}", state)),
+                fill_mat: own_or_themed(&comp, StyledPart::Background, &theme,
+                                        "text_field.background"),
+                stroke_width: own_or_themed_width(&comp, &theme, "text_field.border.width"),
+                ..Brush::default()
+            }
+        });
+        // The text is drawn inside its own clip, shifted by the scroll offset
+        let text = data.text.get_cloned();
+        let scroll = *data.scroll.get();
+        let baseline = (size.y - font.size) / 2.0;
+        let viewport = Batch::new();
+        viewport.add_op(BatchOp::Text {
+            transform: Transform {
+                translate: (-scroll, baseline).into(),
+                ..Transform::default()
+            },
+            text: text.clone(),
+            font: font.clone(),
+            alignment: TextAlignment::origin(),
+            layout: TextLayout::default(),
+            decoration: TextDecoration::default(),
+            brush: Brush::solid_fill(text_mat),
+        });
+        if show_caret {
+            let prefix: String = text.chars().take(*data.caret.get()).collect();
+            let caret_x = Caribou::measure_text(&prefix, &font) - scroll;
+            viewport.add_op(BatchOp::Path {
+                transform: Transform::default(),
+                path: Path::from_vec(vec![
+                    PathOp::MoveTo((caret_x, baseline).into()),
+                    PathOp::LineTo((caret_x, baseline + font.size).into()),
+                ]),
+                brush: Brush::solid_stroke(text_mat, 1.0),
+            });
+        }
+        batch.add_op(BatchOp::Batch {
+            transform: Transform {
+                translate: (TEXT_FIELD_PADDING, 0.0).into(),
+                clip_size: Some((size.x - TEXT_FIELD_PADDING * 2.0, size.y).into()),
+                ..Transform::default()
+            },
+            batch: viewport,
+            filter: None,
+        });
+        batch
+    })
+}
+
+impl TextFieldData {
+    /// Draw the text field with the theme; does nothing if already done.
+    pub fn apply_default_style(&self) {
+        if self.default_styled.replace(true) {
+            return;
+        }
+        self.draw_unfocused.subscribe(text_field_default_style_on_draw("normal", false));
+        self.draw_focused.subscribe(text_field_default_style_on_draw("focused", true));
+        self.draw_disabled.subscribe(text_field_default_style_on_draw("disabled", false));
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use crate::caribou::batch::Batch;
+    use crate::caribou::math::ScalarPair;
+    use crate::caribou::widget::{create_widget, Widget};
+    use super::Layout;
+
+    /// A child of the layout counting how often it is drawn.
+    fn counted_child(layout: &Widget, position: ScalarPair) -> Rc<Cell<u32>> {
+        let child = create_widget();
+        child.position.set(position);
+        child.size.set(ScalarPair::new(20.0, 20.0));
+        let drawn = Rc::new(Cell::new(0));
+        let counter = drawn.clone();
+        child.on_draw.subscribe(Box::new(move |_| {
+            counter.set(counter.get() + 1);
+            Batch::new()
+        }));
+        layout.children.push(child);
+        drawn
+    }
+
+    #[test]
+    fn layout_culls_children_outside_its_size() {
+        let layout = Layout::create();
+        layout.size.set(ScalarPair::new(640.0, 400.0));
+        let inside = counted_child(&layout, ScalarPair::new(1270.0, 790.0));
+        let outside = counted_child(&layout, ScalarPair::new(1280.0, 100.0));
+        layout.draw();
+        assert_eq!((inside.get(), outside.get()), (0, 0));
+        layout.size.set(ScalarPair::new(1280.0, 800.0));
+        layout.draw();
+        assert_eq!((inside.get(), outside.get()), (1, 0));
+    }
+
+    #[test]
+    fn layout_draws_every_child_without_culling() {
+        let layout = Layout::create();
+        layout.size.set(ScalarPair::new(640.0, 400.0));
+        let outside = counted_child(&layout, ScalarPair::new(1280.0, 100.0));
+        Layout::interpret(&layout).unwrap().cull_children.set(false);
+        layout.draw();
+        assert_eq!(outside.get(), 1);
+    }
+}
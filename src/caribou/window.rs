@@ -2,17 +2,150 @@ use std::collections::{LinkedList, VecDeque};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{Builder, JoinHandle, spawn, Thread};
-use crate::caribou::property::{IntProperty, Property, PropertyInit, ScalarProperty};
+use crate::caribou::property::{BoolProperty, IntProperty, OptionalProperty, Property, PropertyInit, ScalarProperty};
 use crate::{Layout, WidgetInner};
-use crate::caribou::batch::{Batch};
+use crate::caribou::batch::{Batch, ImageError, Pict, TextRendering};
+use crate::caribou::event::{EventInit, SingleArgEvent, ZeroArgEvent};
+use crate::caribou::input::KeyEvent;
+use crate::caribou::math::{IntPair, ScalarPair};
 use crate::caribou::skia::runtime::skia_bootstrap;
+use crate::caribou::theme::ColorScheme;
 use crate::caribou::widget::{create_widget, Widget};
 
 pub struct Window {
     marker: Widget,
+    // Attributes
     pub title: Property<String>,
     pub size: IntProperty,
+    /// Size in logical units, which widgets are laid out in
+    pub logical_size: Property<ScalarPair>,
+    pub position: IntProperty,
+    /// Smallest size in logical units the user can resize the window to
+    pub min_size: OptionalProperty<ScalarPair>,
+    /// Largest size in logical units the user can resize the window to
+    pub max_size: OptionalProperty<ScalarPair>,
+    pub resizable: BoolProperty,
+    pub visible: BoolProperty,
+    /// Shown in the title bar and task switcher where the platform supports it
+    pub icon: OptionalProperty<Pict>,
+    pub scale_factor: Property<f32>,
+    /// Whether the window receives keyboard input, as reported by the platform
+    pub focused: BoolProperty,
+    /// Stop ticking animations while the window is not focused, to save power. Animations
+    /// are driven by time, so they jump ahead once the focus returns.
+    pub pause_when_unfocused: BoolProperty,
+    /// GPU API to draw with, read once at launch. The `CARIBOU_BACKEND` environment variable
+    /// overrides it.
+    pub backend: Property<RenderBackend>,
+    /// Samples per pixel for multisample antialiasing, 0 to disable; read once at launch,
+    /// rounded down to a power of two, and dropped if the platform cannot provide them
+    pub msaa_samples: Property<u16>,
+    /// Bits of the stencil buffer, read once at launch
+    pub stencil_bits: Property<u8>,
+    pub text_rendering: Property<TextRendering>,
+    /// Read whenever the surface is created, i.e. at launch and after resizing
+    pub color_space: Property<SurfaceColorSpace>,
+    pub state: Property<WindowState>,
+    pub fullscreen: Property<FullscreenMode>,
+    pub monitor: OptionalProperty<String>,
+    /// Light or dark preference of the platform, as reported by it
+    pub color_scheme: Property<ColorScheme>,
+    /// Switch between `Theme::light` and `Theme::dark` along with `color_scheme`, replacing
+    /// the current theme. Turn it off when using a theme of your own.
+    pub follow_color_scheme: BoolProperty,
     pub root: Property<Widget>,
+    // Events
+    pub on_moved: SingleArgEvent<IntPair>,
+    pub on_resized: SingleArgEvent<IntPair>,
+    pub on_scale_factor_changed: SingleArgEvent<f32>,
+    pub on_state_changed: SingleArgEvent<WindowState>,
+    pub on_fullscreen_changed: SingleArgEvent<FullscreenMode>,
+    pub on_monitor_changed: SingleArgEvent<Option<String>>,
+    pub on_color_scheme_changed: SingleArgEvent<ColorScheme>,
+    pub on_focus: ZeroArgEvent,
+    pub on_blur: ZeroArgEvent,
+    /// The user asked to close the window; any subscriber returning false keeps it open
+    pub on_close_requested: ZeroArgEvent<bool>,
+    // - Keys left unhandled by the focused widget and its ancestors
+    pub on_key_down: SingleArgEvent<KeyEvent, bool>,
+    pub on_key_up: SingleArgEvent<KeyEvent, bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowState {
+    Normal,
+    Maximized,
+    Minimized,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        WindowState::Normal
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Windowed,
+    /// A borderless window covering the current monitor
+    Borderless,
+    /// Exclusive use of the current monitor in its largest video mode
+    Exclusive,
+}
+
+impl Default for FullscreenMode {
+    fn default() -> Self {
+        FullscreenMode::Windowed
+    }
+}
+
+/// GPU APIs the window can be drawn with. Backends other than OpenGL need their cargo feature,
+/// and the window falls back to OpenGL when the chosen one is missing or fails to start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderBackend {
+    OpenGl,
+    /// Needs the `vulkan` feature
+    Vulkan,
+    /// Needs the `metal` feature, on macOS
+    Metal,
+    /// Direct3D 12, for drivers with broken OpenGL. Needs the `d3d` feature, on Windows
+    Direct3D,
+}
+
+impl Default for RenderBackend {
+    fn default() -> Self {
+        RenderBackend::OpenGl
+    }
+}
+
+impl RenderBackend {
+    /// Parse a case-insensitive name such as `gl`, `opengl`, `vulkan`, `metal` or `d3d`.
+    pub fn from_name(name: &str) -> Option<RenderBackend> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gl" | "opengl" => Some(RenderBackend::OpenGl),
+            "vk" | "vulkan" => Some(RenderBackend::Vulkan),
+            "mtl" | "metal" => Some(RenderBackend::Metal),
+            "d3d" | "d3d12" | "direct3d" => Some(RenderBackend::Direct3D),
+            _ => None,
+        }
+    }
+}
+
+/// Color space the window contents are drawn in. Materials are always given in sRGB and
+/// converted to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceColorSpace {
+    Srgb,
+    /// Wide gamut, for displays covering it
+    DisplayP3,
+    /// Write colors to the framebuffer as they are, without color management
+    Unmanaged,
+}
+
+impl Default for SurfaceColorSpace {
+    fn default() -> Self {
+        SurfaceColorSpace::Srgb
+    }
 }
 
 unsafe impl Send for Window {}
@@ -22,13 +155,67 @@ impl Window {
         let marker = create_widget();
         Window {
             marker: marker.clone(),
-            title: marker.init_default_property(),
+            title: marker.init_property("Caribou".to_string()),
             size: marker.init_default_property(),
+            logical_size: marker.init_default_property(),
+            position: marker.init_default_property(),
+            min_size: marker.init_default_property(),
+            max_size: marker.init_default_property(),
+            resizable: marker.init_property(true),
+            visible: marker.init_property(true),
+            icon: marker.init_default_property(),
+            scale_factor: marker.init_property(1.0),
+            focused: marker.init_default_property(),
+            pause_when_unfocused: marker.init_default_property(),
+            backend: marker.init_default_property(),
+            msaa_samples: marker.init_default_property(),
+            stencil_bits: marker.init_property(8),
+            text_rendering: marker.init_default_property(),
+            color_space: marker.init_default_property(),
+            state: marker.init_default_property(),
+            fullscreen: marker.init_default_property(),
+            monitor: marker.init_default_property(),
+            color_scheme: marker.init_default_property(),
+            follow_color_scheme: marker.init_property(true),
             root: marker.init_property(create_widget()),
+            on_moved: marker.init_event(),
+            on_resized: marker.init_event(),
+            on_scale_factor_changed: marker.init_event(),
+            on_state_changed: marker.init_event(),
+            on_fullscreen_changed: marker.init_event(),
+            on_monitor_changed: marker.init_event(),
+            on_color_scheme_changed: marker.init_event(),
+            on_focus: marker.init_event(),
+            on_blur: marker.init_event(),
+            on_close_requested: marker.init_event(),
+            on_key_down: marker.init_event(),
+            on_key_up: marker.init_event(),
         }
     }
 }
 
+impl Window {
+    pub fn maximize(&self) {
+        self.state.set(WindowState::Maximized);
+    }
+
+    pub fn minimize(&self) {
+        self.state.set(WindowState::Minimized);
+    }
+
+    /// Return to a normal window from being maximized, minimized or fullscreen.
+    pub fn restore(&self) {
+        self.fullscreen.set(FullscreenMode::Windowed);
+        self.state.set(WindowState::Normal);
+    }
+
+    /// Use an encoded image (PNG, ICO, ...) as the icon.
+    pub fn set_icon_bytes(&self, bytes: &[u8]) -> Result<(), ImageError> {
+        self.icon.set(Some(Pict::from_bytes(bytes)?));
+        Ok(())
+    }
+}
+
 pub struct Handshake {
     dispatch_queue: Mutex<LinkedList<DispatchMessage>>,
     backend_queue: Mutex<LinkedList<BackendMessage>>,